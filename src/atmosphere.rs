@@ -0,0 +1,268 @@
+/*
+Documentación (bloque):
+Sección: Atmósfera con dispersión simple
+Descripción: Cielo físico para `Skybox`: integra a lo largo de cada dirección de vista la
+luz del sol dispersada una vez por el aire (Rayleigh, que da el azul del cielo y el rojo
+del ocaso) y por los aerosoles (Mie, el halo blanquecino alrededor del sol). El planeta es
+una esfera de radio `GROUND_RADIUS` con una atmósfera de densidad exponencial; el
+observador está a `OBSERVER_ALTITUDE` sobre el suelo. La turbidez escala la cantidad de
+aerosoles: 1 es aire puro y valores de 2 a 10 van de un día claro a uno brumoso. El albedo
+del suelo da color a las direcciones bajo el horizonte y devuelve al cielo parte de la luz
+que recibe el suelo, como una dispersión isótropa más.
+
+Integrar cada rayo es caro, así que al crear la atmósfera (una vez por hora del día) se
+tabula la luz dispersada según la elevación de la vista y su azimut respecto al sol, con
+más resolución cerca del horizonte. Las funciones de fase, que son las que varían deprisa
+cerca del sol, se aplican al consultar la tabla. Todas las radiancias son relativas a una
+irradiancia solar de `SUN_INTENSITY` fuera de la atmósfera.
+*/
+
+use nalgebra::Vector3;
+use rayon::prelude::*;
+
+/// Radios del suelo y del límite superior de la atmósfera, en metros.
+const GROUND_RADIUS: f32 = 6_360e3;
+const ATMOSPHERE_RADIUS: f32 = 6_420e3;
+const OBSERVER_ALTITUDE: f32 = 200.0;
+/// Escalas de altura de la densidad del aire y de los aerosoles.
+const RAYLEIGH_HEIGHT: f32 = 8_000.0;
+const MIE_HEIGHT: f32 = 1_200.0;
+/// Coeficiente de dispersión de Rayleigh a nivel del suelo (R, G, B), por metro.
+const RAYLEIGH_SCATTERING: Vector3<f32> = Vector3::new(5.8e-6, 13.5e-6, 33.1e-6);
+/// Dispersión de Mie a nivel del suelo por cada unidad de turbidez por encima de 1.
+const MIE_SCATTERING_PER_TURBIDITY: f32 = 21e-6;
+/// Fracción de la extinción de los aerosoles que es dispersión (el resto se absorbe).
+const MIE_ALBEDO: f32 = 0.9;
+/// Anisotropía de la fase de Henyey-Greenstein de los aerosoles.
+const MIE_G: f32 = 0.76;
+
+/// Irradiancia del sol fuera de la atmósfera, en las unidades de radiancia del render.
+const SUN_INTENSITY: f32 = 10.0;
+
+const VIEW_STEPS: usize = 24;
+const LIGHT_STEPS: usize = 8;
+const ELEVATION_SAMPLES: usize = 96;
+const AZIMUTH_SAMPLES: usize = 24;
+
+/// Luz dispersada tabulada para una dirección, antes de aplicar las fases.
+#[derive(Clone, Copy)]
+struct Inscatter {
+    rayleigh: Vector3<f32>,
+    mie: Vector3<f32>,
+    /// Luz que no depende del ángulo con el sol: el suelo y su rebote en el aire.
+    isotropic: Vector3<f32>,
+}
+
+pub struct Atmosphere {
+    sun_direction: Vector3<f32>,
+    mie_scattering: f32,
+    table: Vec<Inscatter>,
+}
+
+/// Distancias a las que el rayo `origin + t * dir` entra y sale de la esfera de radio
+/// `radius` centrada en el origen, si la corta.
+fn ray_sphere(origin: &Vector3<f32>, dir: &Vector3<f32>, radius: f32) -> Option<(f32, f32)> {
+    let b = origin.dot(dir);
+    let c = origin.norm_squared() - radius * radius;
+    let discriminant = b * b - c;
+    if discriminant < 0.0 {
+        return None;
+    }
+    let root = discriminant.sqrt();
+    Some((-b - root, -b + root))
+}
+
+fn rayleigh_phase(cos_theta: f32) -> f32 {
+    3.0 / (16.0 * std::f32::consts::PI) * (1.0 + cos_theta * cos_theta)
+}
+
+fn mie_phase(cos_theta: f32) -> f32 {
+    let g2 = MIE_G * MIE_G;
+    (1.0 - g2) / (4.0 * std::f32::consts::PI * (1.0 + g2 - 2.0 * MIE_G * cos_theta).powf(1.5))
+}
+
+/// Coordenada de tabla de una elevación `y` (seno): la raíz concentra muestras en el horizonte.
+fn elevation_coordinate(y: f32) -> f32 {
+    0.5 + 0.5 * y.signum() * y.abs().sqrt()
+}
+
+impl Atmosphere {
+    pub fn new(sun_direction: Vector3<f32>, turbidity: f32, ground_albedo: f32) -> Self {
+        let mut atmosphere = Atmosphere {
+            sun_direction: sun_direction.normalize(),
+            mie_scattering: MIE_SCATTERING_PER_TURBIDITY * (turbidity - 1.0).max(0.0),
+            table: Vec::new(),
+        };
+
+        let observer = Vector3::new(0.0, GROUND_RADIUS + OBSERVER_ALTITUDE, 0.0);
+        let sun_height = atmosphere.sun_direction.y.max(0.0);
+        let ground_radiance = atmosphere.transmittance_to_sun(&observer) * (ground_albedo * sun_height / std::f32::consts::PI);
+
+        let (forward, side) = atmosphere.sun_frame();
+        atmosphere.table = (0..ELEVATION_SAMPLES * AZIMUTH_SAMPLES)
+            .into_par_iter()
+            .map(|index| {
+                let s = (index / AZIMUTH_SAMPLES) as f32 / (ELEVATION_SAMPLES - 1) as f32 * 2.0 - 1.0;
+                let y = s.signum() * s * s;
+                let azimuth = (index % AZIMUTH_SAMPLES) as f32 / (AZIMUTH_SAMPLES - 1) as f32 * std::f32::consts::PI;
+                let horizontal = (1.0 - y * y).max(0.0).sqrt();
+                let dir = forward * (horizontal * azimuth.cos()) + side * (horizontal * azimuth.sin()) + Vector3::new(0.0, y, 0.0);
+                atmosphere.integrate(&observer, &dir, &ground_radiance)
+            })
+            .collect();
+        atmosphere
+    }
+
+    /// Horizontal hacia el sol y su perpendicular, que definen el azimut de la tabla.
+    fn sun_frame(&self) -> (Vector3<f32>, Vector3<f32>) {
+        let horizontal = Vector3::new(self.sun_direction.x, 0.0, self.sun_direction.z);
+        let forward = if horizontal.norm() > 1e-4 { horizontal.normalize() } else { Vector3::new(1.0, 0.0, 0.0) };
+        (forward, Vector3::new(0.0, 1.0, 0.0).cross(&forward))
+    }
+
+    /// Extinción por metro a partir de las densidades relativas de aire y aerosoles.
+    fn extinction(&self, rayleigh_depth: f32, mie_depth: f32) -> Vector3<f32> {
+        RAYLEIGH_SCATTERING * rayleigh_depth + Vector3::repeat(self.mie_scattering / MIE_ALBEDO * mie_depth)
+    }
+
+    /// Densidades relativas de aire y aerosoles en `point`.
+    fn density(point: &Vector3<f32>) -> (f32, f32) {
+        let height = (point.norm() - GROUND_RADIUS).max(0.0);
+        ((-height / RAYLEIGH_HEIGHT).exp(), (-height / MIE_HEIGHT).exp())
+    }
+
+    /// Espesores ópticos de aire y aerosoles desde `point` hasta el sol, o `None` si el
+    /// planeta lo tapa.
+    fn optical_depth_to_sun(&self, point: &Vector3<f32>) -> Option<(f32, f32)> {
+        if matches!(ray_sphere(point, &self.sun_direction, GROUND_RADIUS), Some((_, far)) if far > 0.0) {
+            return None;
+        }
+        let (_, length) = ray_sphere(point, &self.sun_direction, ATMOSPHERE_RADIUS)?;
+        let step = length / LIGHT_STEPS as f32;
+        let mut depth = (0.0, 0.0);
+        for i in 0..LIGHT_STEPS {
+            let (rayleigh, mie) = Self::density(&(point + self.sun_direction * ((i as f32 + 0.5) * step)));
+            depth.0 += rayleigh * step;
+            depth.1 += mie * step;
+        }
+        Some(depth)
+    }
+
+    /// Fracción de la luz del sol que llega a `point`, por canal.
+    fn transmittance_to_sun(&self, point: &Vector3<f32>) -> Vector3<f32> {
+        match self.optical_depth_to_sun(point) {
+            Some((rayleigh, mie)) => self.extinction(rayleigh, mie).map(|tau| (-tau).exp()),
+            None => Vector3::zeros(),
+        }
+    }
+
+    /// Integra la luz dispersada hacia `origin` a lo largo de `dir`.
+    fn integrate(&self, origin: &Vector3<f32>, dir: &Vector3<f32>, ground_radiance: &Vector3<f32>) -> Inscatter {
+        let (_, top) = ray_sphere(origin, dir, ATMOSPHERE_RADIUS).unwrap_or((0.0, 0.0));
+        let ground = ray_sphere(origin, dir, GROUND_RADIUS).map(|(near, _)| near).filter(|&near| near > 0.0);
+        let length = ground.unwrap_or(top);
+        let step = length / VIEW_STEPS as f32;
+
+        let mut inscatter = Inscatter { rayleigh: Vector3::zeros(), mie: Vector3::zeros(), isotropic: Vector3::zeros() };
+        let mut view_depth = (0.0, 0.0);
+        for i in 0..VIEW_STEPS {
+            let point = origin + dir * ((i as f32 + 0.5) * step);
+            let (rayleigh, mie) = Self::density(&point);
+            view_depth.0 += rayleigh * step * 0.5;
+            view_depth.1 += mie * step * 0.5;
+
+            let view_transmittance = self.extinction(view_depth.0, view_depth.1).map(|tau| (-tau).exp());
+            if let Some(sun_depth) = self.optical_depth_to_sun(&point) {
+                let transmittance = self.extinction(view_depth.0 + sun_depth.0, view_depth.1 + sun_depth.1).map(|tau| (-tau).exp());
+                inscatter.rayleigh += transmittance * (rayleigh * step);
+                inscatter.mie += transmittance * (mie * step);
+            }
+            let scattering = RAYLEIGH_SCATTERING * rayleigh + Vector3::repeat(self.mie_scattering * mie);
+            inscatter.isotropic += view_transmittance.component_mul(&scattering).component_mul(ground_radiance) * (0.5 * step);
+
+            view_depth.0 += rayleigh * step * 0.5;
+            view_depth.1 += mie * step * 0.5;
+        }
+
+        if ground.is_some() {
+            let transmittance = self.extinction(view_depth.0, view_depth.1).map(|tau| (-tau).exp());
+            inscatter.isotropic += transmittance.component_mul(ground_radiance);
+        }
+        inscatter
+    }
+
+    fn lookup(&self, elevation: usize, azimuth: usize) -> &Inscatter {
+        &self.table[elevation * AZIMUTH_SAMPLES + azimuth]
+    }
+
+    /// Radiancia del cielo (o del suelo, bajo el horizonte) en la dirección `dir`, sin el disco solar.
+    pub fn radiance(&self, dir: &Vector3<f32>) -> Vector3<f32> {
+        let dir = dir.normalize();
+        let (forward, side) = self.sun_frame();
+        let azimuth = dir.dot(&side).abs().atan2(dir.dot(&forward));
+
+        let e = elevation_coordinate(dir.y) * (ELEVATION_SAMPLES - 1) as f32;
+        let a = azimuth / std::f32::consts::PI * (AZIMUTH_SAMPLES - 1) as f32;
+        let (e0, a0) = ((e as usize).min(ELEVATION_SAMPLES - 2), (a as usize).min(AZIMUTH_SAMPLES - 2));
+        let (fe, fa) = (e - e0 as f32, a - a0 as f32);
+        let blend = |f: fn(&Inscatter) -> Vector3<f32>| {
+            let low = f(self.lookup(e0, a0)).lerp(&f(self.lookup(e0, a0 + 1)), fa);
+            let high = f(self.lookup(e0 + 1, a0)).lerp(&f(self.lookup(e0 + 1, a0 + 1)), fa);
+            low.lerp(&high, fe)
+        };
+
+        let cos_theta = dir.dot(&self.sun_direction);
+        let rayleigh = blend(|s| s.rayleigh).component_mul(&RAYLEIGH_SCATTERING) * rayleigh_phase(cos_theta);
+        let mie = blend(|s| s.mie) * (self.mie_scattering * mie_phase(cos_theta));
+        (rayleigh + mie + blend(|s| s.isotropic)) * SUN_INTENSITY
+    }
+
+    /// Fracción de la luz del sol que llega al observador, por canal: blanca con el sol
+    /// alto y el aire limpio, roja cerca del horizonte y nula bajo él.
+    pub fn sun_transmittance(&self) -> Vector3<f32> {
+        self.transmittance_to_sun(&Vector3::new(0.0, GROUND_RADIUS + OBSERVER_ALTITUDE, 0.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Direcciones repartidas por toda la esfera, por encima y por debajo del horizonte.
+    fn directions() -> Vec<Vector3<f32>> {
+        let mut directions = Vec::new();
+        for i in 0..9 {
+            let y = i as f32 / 4.0 - 1.0;
+            for j in 0..8 {
+                let angle = j as f32 / 8.0 * std::f32::consts::TAU;
+                let horizontal = (1.0 - y * y).sqrt();
+                directions.push(Vector3::new(horizontal * angle.cos(), y, horizontal * angle.sin()));
+            }
+        }
+        directions
+    }
+
+    #[test]
+    fn con_el_sol_muy_bajo_el_horizonte_todo_es_negro() {
+        let atmosphere = Atmosphere::new(Vector3::new(0.3, -0.5, 0.8), 3.0, 0.3);
+        assert_eq!(atmosphere.sun_transmittance(), Vector3::zeros());
+        for dir in directions() {
+            assert_eq!(atmosphere.radiance(&dir), Vector3::zeros(), "dirección {:?}", dir);
+        }
+    }
+
+    #[test]
+    fn el_cielo_es_azul_de_dia_y_el_sol_enrojece_al_ponerse() {
+        let noon = Atmosphere::new(Vector3::new(0.0, 1.0, 0.3), 2.0, 0.3);
+        let zenith = noon.radiance(&Vector3::new(0.0, 1.0, 0.0));
+        assert!(zenith.z > zenith.x && zenith.x > 0.0, "cénit {:?}", zenith);
+        for dir in directions() {
+            assert!(noon.radiance(&dir).iter().all(|c| c.is_finite() && *c >= 0.0), "dirección {:?}", dir);
+        }
+
+        let high = noon.sun_transmittance();
+        let low = Atmosphere::new(Vector3::new(1.0, 0.03, 0.0), 2.0, 0.3).sun_transmittance();
+        assert!(high.min() > 0.7, "sol alto {:?}", high);
+        assert!(low.x > low.z && low.x < high.x, "sol rasante {:?}", low);
+    }
+}
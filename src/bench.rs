@@ -0,0 +1,166 @@
+/*
+Documentación (bloque):
+Sección: Benchmark del raycasting (`--bench`)
+Descripción: Mide el DDA sobre mundos grandes y dispersos generados al vuelo, recorriendo
+voxel a voxel y saltando el espacio vacío con `Occupancy`. Para cada mundo lanza los rayos
+primarios de una cámara que lo mira desde fuera, como en el render, y rayos en direcciones
+aleatorias desde puntos de su interior, como los rebotes del path tracer. Informa de los
+millones de rayos por segundo de cada modo y comprueba que ambos encuentran los mismos
+impactos, y de la memoria que ocupan sus chunks frente a un vector denso del mismo tamaño.
+Los mundos se generan con semilla fija, así que los resultados son comparables entre
+ejecuciones.
+*/
+
+use crate::registry::registry;
+use crate::{BlockState, BlockType, Rng, VoxelWorld};
+use nalgebra::{Point3, Vector3};
+use std::time::Instant;
+
+const IMAGE_WIDTH: usize = 640;
+const IMAGE_HEIGHT: usize = 480;
+const SECONDARY_RAYS: usize = 300_000;
+
+/// Los dos primeros cubos opacos del registro instalado (el mismo dos veces si solo hay
+/// uno), o error si no hay ninguno y los mundos quedarían vacíos.
+fn solid_blocks() -> Result<(BlockType, BlockType), String> {
+    let mut cubes = registry().blocks().map(|(block, _)| block).filter(|block| block.is_opaque());
+    let first = cubes.next().ok_or("el registro de bloques no tiene ningún cubo opaco con el que construir los mundos")?;
+    Ok((first, cubes.next().unwrap_or(first)))
+}
+
+/// Suelo de un bloque de grosor con columnas sueltas de altura variable.
+fn sparse_terrain(floor: BlockType, pillar: BlockType) -> VoxelWorld {
+    let (width, height, depth) = (256, 96, 256);
+    let mut world = VoxelWorld::with_dimensions(width, height, depth);
+    for z in 0..depth as i32 {
+        for x in 0..width as i32 {
+            world.set_block(x, 0, z, floor);
+        }
+    }
+    let mut rng = Rng::new(17);
+    for _ in 0..300 {
+        let (x, z) = (rng.index(width) as i32, rng.index(depth) as i32);
+        for y in 1..=1 + rng.index(60) as i32 {
+            world.set_block(x, y, z, pillar);
+        }
+    }
+    world
+}
+
+/// Volumen casi vacío con unas pocas islas flotantes.
+fn floating_islands(block: BlockType) -> VoxelWorld {
+    let (width, height, depth) = (384, 128, 384);
+    let mut world = VoxelWorld::with_dimensions(width, height, depth);
+    let mut rng = Rng::new(29);
+    for _ in 0..40 {
+        let (cx, cy, cz) = (rng.index(width - 8) as i32, rng.index(height - 4) as i32, rng.index(depth - 8) as i32);
+        for y in cy..cy + 3 {
+            for z in cz..cz + 8 {
+                for x in cx..cx + 8 {
+                    world.set_block(x, y, z, block);
+                }
+            }
+        }
+    }
+    world
+}
+
+/// Terreno macizo de un millón de columnas con colinas suaves, para medir la memoria.
+fn large_terrain(rock: BlockType, soil: BlockType) -> VoxelWorld {
+    let (width, height, depth) = (1024, 256, 1024);
+    let mut world = VoxelWorld::with_dimensions(width, height, depth);
+    for z in 0..depth as i32 {
+        for x in 0..width as i32 {
+            let (fx, fz) = (x as f32, z as f32);
+            let top = (40.0 + 24.0 * (fx * 0.013).sin() * (fz * 0.011).cos() + 8.0 * (fx * 0.05 + fz * 0.03).sin()) as i32;
+            for y in 0..=top {
+                world.set_block(x, y, z, if y > top - 3 { soil } else { rock });
+            }
+        }
+    }
+    world
+}
+
+/// Rayos primarios de una cámara que mira el centro del mundo desde una esquina elevada.
+fn primary_rays(world: &VoxelWorld) -> Vec<(Point3<f32>, Vector3<f32>)> {
+    let size = Vector3::new(world.width as f32, world.height as f32, world.depth as f32);
+    let target = Point3::from(size * 0.5);
+    let camera = target + Vector3::new(-0.7 * size.x, 0.6 * size.y + 20.0, -0.7 * size.z);
+    let forward = (target - camera).normalize();
+    let right = forward.cross(&Vector3::new(0.0, 1.0, 0.0)).normalize();
+    let up = right.cross(&forward);
+    let tan_half_fov = (std::f32::consts::PI / 6.0).tan();
+    let aspect_ratio = IMAGE_WIDTH as f32 / IMAGE_HEIGHT as f32;
+
+    let mut rays = Vec::with_capacity(IMAGE_WIDTH * IMAGE_HEIGHT);
+    for j in 0..IMAGE_HEIGHT {
+        for i in 0..IMAGE_WIDTH {
+            let x = (2.0 * (i as f32 + 0.5) / IMAGE_WIDTH as f32 - 1.0) * tan_half_fov * aspect_ratio;
+            let y = -(2.0 * (j as f32 + 0.5) / IMAGE_HEIGHT as f32 - 1.0) * tan_half_fov;
+            rays.push((camera, (forward + right * x + up * y).normalize()));
+        }
+    }
+    rays
+}
+
+/// Rayos en direcciones uniformes desde puntos al azar del interior del mundo.
+fn secondary_rays(world: &VoxelWorld) -> Vec<(Point3<f32>, Vector3<f32>)> {
+    let mut rng = Rng::new(41);
+    (0..SECONDARY_RAYS)
+        .map(|_| {
+            let origin = Point3::new(
+                rng.next_f32() * world.width as f32,
+                rng.next_f32() * world.height as f32,
+                rng.next_f32() * world.depth as f32,
+            );
+            let z = 2.0 * rng.next_f32() - 1.0;
+            let phi = std::f32::consts::TAU * rng.next_f32();
+            let r = (1.0 - z * z).sqrt();
+            (origin, Vector3::new(r * phi.cos(), z, r * phi.sin()))
+        })
+        .collect()
+}
+
+/// Lanza todos los rayos y devuelve los segundos empleados y la distancia de cada impacto.
+fn cast_all(world: &VoxelWorld, rays: &[(Point3<f32>, Vector3<f32>)], skip_empty: bool) -> (f32, Vec<Option<f32>>) {
+    let start = Instant::now();
+    let hits = rays
+        .iter()
+        .map(|(origin, dir)| world.traverse(origin, dir, BlockType::AIRE, skip_empty).map(|(t, ..)| t))
+        .collect();
+    (start.elapsed().as_secs_f32(), hits)
+}
+
+pub fn run() -> Result<(), Box<dyn std::error::Error>> {
+    let (a, b) = solid_blocks().map_err(|e| format!("--bench: {}", e))?;
+    println!("Benchmark de raycasting (un hilo, {}x{} rayos primarios, {} secundarios)", IMAGE_WIDTH, IMAGE_HEIGHT, SECONDARY_RAYS);
+    let worlds = [
+        ("terreno disperso", sparse_terrain(a, b)),
+        ("islas flotantes", floating_islands(b)),
+        ("terreno grande", large_terrain(a, b)),
+    ];
+    for (name, world) in &worlds {
+        println!("\n{} ({}x{}x{}):", name, world.width, world.height, world.depth);
+        let (chunks, bytes) = world.voxels.usage();
+        let dense = world.width * world.height * world.depth * (size_of::<BlockType>() + size_of::<BlockState>());
+        let mib = |bytes: usize| bytes as f32 / (1024.0 * 1024.0);
+        println!("  {:<12} {} chunks, {:.1} MiB (denso: {:.1} MiB)", "memoria", chunks, mib(bytes), mib(dense));
+        for (kind, rays) in [("primarios", primary_rays(world)), ("secundarios", secondary_rays(world))] {
+            let (dense_secs, dense_hits) = cast_all(world, &rays, false);
+            let (skip_secs, skip_hits) = cast_all(world, &rays, true);
+            let mismatches = dense_hits.iter().zip(&skip_hits)
+                .filter(|(a, b)| match (a, b) {
+                    (Some(a), Some(b)) => (a - b).abs() > 1e-3,
+                    (None, None) => false,
+                    _ => true,
+                })
+                .count();
+            let mrays = |secs: f32| rays.len() as f32 / secs / 1e6;
+            println!(
+                "  {:<12} voxel a voxel {:>7.2} Mrayos/s | con salto {:>7.2} Mrayos/s | x{:.1} | {} diferencias",
+                kind, mrays(dense_secs), mrays(skip_secs), dense_secs / skip_secs, mismatches
+            );
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,354 @@
+/*
+Documentación (bloque):
+Sección: Almacenamiento por chunks
+Descripción: Guarda los voxels del mundo en chunks de 16³ en lugar de en un vector denso
+del tamaño de la caja envolvente, para que la memoria crezca con lo construido y no con
+el volumen. Un chunk sin bloques no se reserva: su hueco en la tabla es `None`. Cada chunk
+reservado tiene una paleta con los valores distintos que contiene (bloque y orientación) y
+guarda por voxel solo el índice en ella, empaquetado en 1, 2, 4, 8 o 16 bits según el
+tamaño de la paleta. Cada entrada lleva la cuenta de los voxels que la usan, y un valor
+nuevo ocupa la primera entrada que haya quedado sin voxels; solo cuando todas están en uso
+se reempaquetan los índices con el doble de bits. Así, reescribir un voxel una y otra vez
+no hace crecer la paleta. Cuando el chunk se queda sin bloques se libera entero.
+
+`ChunkGrid` aplica la misma división a datos por voxel que no se comprimen con paleta,
+como la luz de bloque: solo existen los chunks en los que se ha escrito.
+*/
+
+use crate::{BlockState, BlockType};
+
+/// Lado en voxels de un chunk.
+pub const CHUNK: usize = 16;
+const VOLUME: usize = CHUNK * CHUNK * CHUNK;
+
+/// Contenido de un voxel: su bloque y su orientación.
+pub type Voxel = (BlockType, BlockState);
+
+fn empty() -> Voxel {
+    (BlockType::AIRE, BlockState::default())
+}
+
+/// Reparto de un volumen en chunks: número de chunks por eje e índices.
+struct ChunkLayout {
+    dims: [usize; 3],
+}
+
+impl ChunkLayout {
+    fn new(width: usize, height: usize, depth: usize) -> Self {
+        ChunkLayout { dims: [width.div_ceil(CHUNK), height.div_ceil(CHUNK), depth.div_ceil(CHUNK)] }
+    }
+
+    fn count(&self) -> usize {
+        self.dims.iter().product()
+    }
+
+    #[inline]
+    fn chunk(&self, x: usize, y: usize, z: usize) -> usize {
+        ((y / CHUNK) * self.dims[2] + z / CHUNK) * self.dims[0] + x / CHUNK
+    }
+
+    #[inline]
+    fn local(x: usize, y: usize, z: usize) -> usize {
+        ((y % CHUNK) << 8) | ((z % CHUNK) << 4) | (x % CHUNK)
+    }
+
+    /// Coordenadas de mundo del voxel `local` del chunk `chunk`.
+    fn position(&self, chunk: usize, local: usize) -> (usize, usize, usize) {
+        let (cx, cz, cy) = (chunk % self.dims[0], (chunk / self.dims[0]) % self.dims[2], chunk / (self.dims[0] * self.dims[2]));
+        (cx * CHUNK + local % CHUNK, cy * CHUNK + local / (CHUNK * CHUNK), cz * CHUNK + (local / CHUNK) % CHUNK)
+    }
+}
+
+struct Chunk {
+    palette: Vec<Voxel>,
+    /// Voxels que usan cada entrada de la paleta; las que llegan a cero se reutilizan.
+    counts: Vec<u16>,
+    /// Bits por índice: siempre una potencia de dos, para que ninguno cruce dos palabras.
+    bits: usize,
+    data: Vec<u64>,
+    /// Voxels que no son aire; el chunk se libera al llegar a cero.
+    occupied: usize,
+}
+
+impl Chunk {
+    fn new() -> Self {
+        Chunk { palette: vec![empty()], counts: vec![VOLUME as u16], bits: 1, data: vec![0; VOLUME / 64], occupied: 0 }
+    }
+
+    #[inline]
+    fn index_at(&self, local: usize) -> usize {
+        let per_word = 64 / self.bits;
+        let shift = (local % per_word) * self.bits;
+        ((self.data[local / per_word] >> shift) & ((1 << self.bits) - 1)) as usize
+    }
+
+    fn set_index(&mut self, local: usize, index: usize) {
+        let per_word = 64 / self.bits;
+        let shift = (local % per_word) * self.bits;
+        let mask = ((1u64 << self.bits) - 1) << shift;
+        let word = &mut self.data[local / per_word];
+        *word = (*word & !mask) | ((index as u64) << shift);
+    }
+
+    /// Índice de `voxel` en la paleta. Si no está, ocupa una entrada sin voxels o, si no
+    /// queda ninguna, se añade al final (ensanchando los índices si hace falta).
+    fn palette_index(&mut self, voxel: Voxel) -> usize {
+        if let Some(index) = self.palette.iter().position(|&v| v == voxel) {
+            return index;
+        }
+        if let Some(index) = self.counts.iter().position(|&count| count == 0) {
+            self.palette[index] = voxel;
+            return index;
+        }
+        if self.palette.len() == 1 << self.bits {
+            let indices: Vec<usize> = (0..VOLUME).map(|local| self.index_at(local)).collect();
+            self.bits *= 2;
+            self.data = vec![0; VOLUME * self.bits / 64];
+            for (local, index) in indices.into_iter().enumerate() {
+                self.set_index(local, index);
+            }
+        }
+        self.palette.push(voxel);
+        self.counts.push(0);
+        self.palette.len() - 1
+    }
+
+    fn bytes(&self) -> usize {
+        std::mem::size_of::<Chunk>()
+            + self.palette.len() * (std::mem::size_of::<Voxel>() + std::mem::size_of::<u16>())
+            + self.data.len() * 8
+    }
+}
+
+pub struct ChunkStore {
+    layout: ChunkLayout,
+    chunks: Vec<Option<Box<Chunk>>>,
+}
+
+impl ChunkStore {
+    pub fn new(width: usize, height: usize, depth: usize) -> Self {
+        let layout = ChunkLayout::new(width, height, depth);
+        let chunks = std::iter::repeat_with(|| None).take(layout.count()).collect();
+        ChunkStore { layout, chunks }
+    }
+
+    /// Contenido del voxel `(x, y, z)`, que debe estar dentro del mundo.
+    #[inline]
+    pub fn get(&self, x: usize, y: usize, z: usize) -> Voxel {
+        match &self.chunks[self.layout.chunk(x, y, z)] {
+            Some(chunk) => chunk.palette[chunk.index_at(ChunkLayout::local(x, y, z))],
+            None => empty(),
+        }
+    }
+
+    /// Cambia el voxel `(x, y, z)`, que debe estar dentro del mundo. El aire no guarda orientación.
+    pub fn set(&mut self, x: usize, y: usize, z: usize, voxel: Voxel) {
+        let voxel = if voxel.0 == BlockType::AIRE { empty() } else { voxel };
+        let local = ChunkLayout::local(x, y, z);
+        let slot = &mut self.chunks[self.layout.chunk(x, y, z)];
+        let chunk = match slot {
+            Some(chunk) => chunk,
+            None if voxel == empty() => return,
+            None => slot.insert(Box::new(Chunk::new())),
+        };
+
+        let previous_index = chunk.index_at(local);
+        let previous = chunk.palette[previous_index];
+        if previous == voxel {
+            return;
+        }
+        let index = chunk.palette_index(voxel);
+        chunk.set_index(local, index);
+        chunk.counts[previous_index] -= 1;
+        chunk.counts[index] += 1;
+        if previous.0 != BlockType::AIRE {
+            chunk.occupied -= 1;
+        }
+        if voxel.0 != BlockType::AIRE {
+            chunk.occupied += 1;
+        }
+        if chunk.occupied == 0 {
+            *slot = None;
+        }
+    }
+
+    /// Voxels que no son aire, recorriendo solo los chunks reservados.
+    pub fn occupied(&self) -> impl Iterator<Item = ((usize, usize, usize), Voxel)> + '_ {
+        self.chunks.iter().enumerate().flat_map(move |(index, chunk)| {
+            chunk.iter().flat_map(move |chunk| {
+                (0..VOLUME).filter_map(move |local| {
+                    let voxel = chunk.palette[chunk.index_at(local)];
+                    (voxel.0 != BlockType::AIRE).then(|| (self.layout.position(index, local), voxel))
+                })
+            })
+        })
+    }
+
+    /// Chunks reservados y bytes que ocupan en total, incluida la tabla de chunks.
+    pub fn usage(&self) -> (usize, usize) {
+        let allocated = self.chunks.iter().flatten().count();
+        let bytes = self.chunks.len() * std::mem::size_of::<Option<Box<Chunk>>>()
+            + self.chunks.iter().flatten().map(|chunk| chunk.bytes()).sum::<usize>();
+        (allocated, bytes)
+    }
+}
+
+/// Valores por voxel guardados en chunks que solo se reservan al escribir en ellos.
+pub struct ChunkGrid<T> {
+    layout: ChunkLayout,
+    chunks: Vec<Option<Box<[T]>>>,
+}
+
+impl<T: Copy + Default> ChunkGrid<T> {
+    pub fn new(width: usize, height: usize, depth: usize) -> Self {
+        let layout = ChunkLayout::new(width, height, depth);
+        let chunks = std::iter::repeat_with(|| None).take(layout.count()).collect();
+        ChunkGrid { layout, chunks }
+    }
+
+    /// Valor del voxel `(x, y, z)`, que debe estar dentro del mundo; `T::default()` si no se escribió.
+    #[inline]
+    pub fn get(&self, x: usize, y: usize, z: usize) -> T {
+        match &self.chunks[self.layout.chunk(x, y, z)] {
+            Some(values) => values[ChunkLayout::local(x, y, z)],
+            None => T::default(),
+        }
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, z: usize, value: T) {
+        let values = self.chunks[self.layout.chunk(x, y, z)]
+            .get_or_insert_with(|| vec![T::default(); VOLUME].into_boxed_slice());
+        values[ChunkLayout::local(x, y, z)] = value;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Facing, Rng};
+    use std::collections::HashMap;
+
+    const SIZE: (usize, usize, usize) = (40, 20, 33);
+
+    /// Voxel distinto para cada `n`: bloques de 1 en adelante con las cuatro orientaciones.
+    fn voxel(n: usize) -> Voxel {
+        (BlockType(1 + (n / 4) as u16), BlockState::new(Facing::ALL[n % 4], false))
+    }
+
+    /// Comprueba que las cuentas de la paleta de cada chunk coinciden con sus índices.
+    fn assert_counts(store: &ChunkStore) {
+        for chunk in store.chunks.iter().flatten() {
+            let mut counts = vec![0u16; chunk.palette.len()];
+            for local in 0..VOLUME {
+                counts[chunk.index_at(local)] += 1;
+            }
+            assert_eq!(chunk.counts, counts);
+            let air = (0..chunk.palette.len()).filter(|&i| chunk.palette[i] == empty()).map(|i| counts[i] as usize).sum::<usize>();
+            assert_eq!(chunk.occupied, VOLUME - air);
+        }
+    }
+
+    #[test]
+    fn las_escrituras_al_azar_se_leen_igual() {
+        let mut store = ChunkStore::new(SIZE.0, SIZE.1, SIZE.2);
+        let mut written = HashMap::new();
+        let mut rng = Rng::new(7);
+        let mut random = |n: usize| rng.next_u32() as usize % n;
+
+        for round in 0..4 {
+            for _ in 0..6000 {
+                let (x, y, z) = (random(SIZE.0), random(SIZE.1), random(SIZE.2));
+                // La mitad de las escrituras son aire, a veces con una orientación que no se guarda.
+                let value = if random(2) == 0 {
+                    (BlockType::AIRE, BlockState::new(Facing::East, random(2) == 0))
+                } else {
+                    voxel(random(12 + round * 40))
+                };
+                store.set(x, y, z, value);
+                if value.0 == BlockType::AIRE {
+                    written.remove(&(x, y, z));
+                } else {
+                    written.insert((x, y, z), value);
+                }
+            }
+            assert_counts(&store);
+            for (&(x, y, z), &value) in &written {
+                assert_eq!(store.get(x, y, z), value, "voxel ({}, {}, {})", x, y, z);
+            }
+            let occupied: HashMap<_, _> = store.occupied().collect();
+            assert_eq!(occupied, written);
+        }
+    }
+
+    #[test]
+    fn reescribir_un_voxel_no_hace_crecer_la_paleta() {
+        let mut store = ChunkStore::new(CHUNK, CHUNK, CHUNK);
+        store.set(3, 4, 5, voxel(0));
+        store.set(3, 4, 5, voxel(1));
+        let bytes = store.usage().1;
+        for n in 2..70_000 {
+            store.set(3, 4, 5, voxel(n));
+        }
+        assert_eq!(store.get(3, 4, 5), voxel(69_999));
+        assert_eq!(store.usage().1, bytes);
+        let chunk = store.chunks[0].as_ref().unwrap();
+        assert_eq!((chunk.bits, chunk.palette.len()), (2, 3));
+        assert_counts(&store);
+    }
+
+    #[test]
+    fn la_paleta_se_ensancha_sin_perder_indices() {
+        let mut store = ChunkStore::new(CHUNK, CHUNK, CHUNK);
+        let position = |n: usize| ChunkLayout::new(CHUNK, CHUNK, CHUNK).position(0, n * 7 % VOLUME);
+        let mut widths = Vec::new();
+        for n in 0..300 {
+            let (x, y, z) = position(n);
+            store.set(x, y, z, voxel(n));
+            let bits = store.chunks[0].as_ref().unwrap().bits;
+            if widths.last() != Some(&bits) {
+                widths.push(bits);
+            }
+            for m in 0..=n {
+                let (x, y, z) = position(m);
+                assert_eq!(store.get(x, y, z), voxel(m), "valor {} tras escribir {} a {} bits", m, n, bits);
+            }
+        }
+        assert_eq!(widths, [1, 2, 4, 8, 16]);
+    }
+
+    #[test]
+    fn un_chunk_vacio_se_libera() {
+        let mut store = ChunkStore::new(SIZE.0, SIZE.1, SIZE.2);
+        let positions = [(0, 0, 0), (15, 15, 15), (16, 0, 0), (39, 19, 32)];
+        for (n, &(x, y, z)) in positions.iter().enumerate() {
+            store.set(x, y, z, voxel(n));
+        }
+        assert_eq!(store.usage().0, 3);
+
+        // Cambiar un bloque por otro no altera la cuenta de ocupados.
+        store.set(0, 0, 0, voxel(9));
+        store.set(0, 0, 0, empty());
+        assert_eq!(store.usage().0, 3);
+        store.set(15, 15, 15, (BlockType::AIRE, BlockState::new(Facing::West, true)));
+        assert_eq!(store.usage().0, 2);
+        for &(x, y, z) in &positions[2..] {
+            store.set(x, y, z, empty());
+        }
+        assert_eq!(store.usage().0, 0);
+        assert_eq!(store.occupied().count(), 0);
+
+        // Escribir aire en un chunk sin reservar no lo reserva.
+        store.set(20, 5, 5, empty());
+        assert_eq!(store.usage().0, 0);
+    }
+
+    #[test]
+    fn la_rejilla_solo_reserva_los_chunks_escritos() {
+        let mut grid: ChunkGrid<u32> = ChunkGrid::new(SIZE.0, SIZE.1, SIZE.2);
+        grid.set(17, 3, 32, 5);
+        grid.set(39, 19, 0, 8);
+        assert_eq!(grid.chunks.iter().flatten().count(), 2);
+        assert_eq!((grid.get(17, 3, 32), grid.get(39, 19, 0)), (5, 8));
+        assert_eq!((grid.get(16, 3, 32), grid.get(0, 0, 0)), (0, 0));
+    }
+}
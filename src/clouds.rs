@@ -0,0 +1,123 @@
+/*
+Documentación (bloque):
+Sección: Capa de nubes
+Descripción: Nubes procedimentales en un plano horizontal a `altitude` bloques sobre el
+suelo del mundo. En cada punto del plano el espesor óptico sale de un fBm de ruido de
+valor: la cobertura fija el umbral a partir del cual hay nube y la densidad lo opaca que
+es. El viento desplaza el ruido a `wind_speed` bloques por segundo y, más despacio, lo
+hace evolucionar, así que las nubes cambian de forma mientras avanzan. Un rayo que cruza
+el plano con elevación `y` atraviesa `τ / y` de espesor, de modo que las nubes rasantes
+son más opacas. Sirve tanto para el cielo (vista desde el origen del mundo) como para las
+sombras: los rayos de sombra hacia el sol o la luna se atenúan con la misma función, y
+las sombras se mueven con las nubes.
+*/
+
+use nalgebra::{Point3, Vector3};
+
+use crate::noise::fbm;
+
+/// Bloques por unidad de ruido: el tamaño típico de una nube.
+const CLOUD_SCALE: f32 = 48.0;
+const CLOUD_OCTAVES: usize = 5;
+/// Espesor óptico vertical de una nube completa con densidad 1.
+const CLOUD_OPTICAL_DEPTH: f32 = 3.0;
+/// Anchura de la transición entre cielo despejado y nube, en valores de fBm.
+const CLOUD_EDGE: f32 = 0.15;
+/// Velocidad con la que las nubes cambian de forma, relativa a la del viento.
+const CLOUD_EVOLUTION: f32 = 0.15;
+/// Dirección horizontal del viento.
+const WIND_DIRECTION: Vector3<f32> = Vector3::new(0.928, 0.0, 0.371);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CloudSettings {
+    /// Altura del plano de nubes sobre el suelo del mundo, en bloques.
+    pub altitude: f32,
+    /// Fracción del cielo cubierta, de 0 (despejado) a 1 (cubierto).
+    pub coverage: f32,
+    /// Multiplicador del espesor óptico de las nubes.
+    pub density: f32,
+    /// Velocidad del viento, en bloques por segundo.
+    pub wind_speed: f32,
+}
+
+impl CloudSettings {
+    /// Espesor óptico vertical de las nubes en `(x, z)` tras `seconds` segundos de viento.
+    pub fn optical_depth(&self, x: f32, z: f32, seconds: f32) -> f32 {
+        if self.coverage <= 0.0 || self.density <= 0.0 {
+            return 0.0;
+        }
+        let drift = WIND_DIRECTION * (self.wind_speed * seconds);
+        let evolution = self.wind_speed * seconds * CLOUD_EVOLUTION;
+        let p = Vector3::new(x - drift.x, evolution, z - drift.z) / CLOUD_SCALE;
+        let threshold = 0.75 - 0.5 * self.coverage.min(1.0);
+        let shape = ((fbm(&p, CLOUD_OCTAVES) - threshold) / CLOUD_EDGE).clamp(0.0, 1.0);
+        shape * shape * (3.0 - 2.0 * shape) * self.density * CLOUD_OPTICAL_DEPTH
+    }
+
+    /// Espesor óptico que atraviesa el rayo `origin + t * dir` al cruzar la capa, o 0 si no
+    /// la cruza hacia arriba.
+    pub fn depth_along(&self, origin: &Point3<f32>, dir: &Vector3<f32>, seconds: f32) -> f32 {
+        if dir.y <= 1e-3 || origin.y >= self.altitude {
+            return 0.0;
+        }
+        let crossing = origin + dir * ((self.altitude - origin.y) / dir.y);
+        self.optical_depth(crossing.x, crossing.z, seconds) / dir.y
+    }
+
+    /// Fracción de luz que atraviesa la capa a lo largo del rayo `origin + t * dir`.
+    pub fn transmittance(&self, origin: &Point3<f32>, dir: &Vector3<f32>, seconds: f32) -> f32 {
+        (-self.depth_along(origin, dir, seconds)).exp()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CLOUDS: CloudSettings = CloudSettings { altitude: 60.0, coverage: 0.5, density: 1.0, wind_speed: 4.0 };
+
+    /// Puntos del plano de nubes repartidos en una rejilla de 200×200 bloques.
+    fn grid() -> impl Iterator<Item = (f32, f32)> {
+        (0..400).map(|i| ((i % 20) as f32 * 10.0 - 100.0, (i / 20) as f32 * 10.0 - 100.0))
+    }
+
+    #[test]
+    fn sin_cobertura_no_hay_nubes() {
+        let clear = CloudSettings { coverage: 0.0, ..CLOUDS };
+        for (x, z) in grid() {
+            assert_eq!(clear.optical_depth(x, z, 37.0), 0.0);
+        }
+        assert_eq!(clear.transmittance(&Point3::origin(), &Vector3::new(0.1, 0.2, 0.0).normalize(), 37.0), 1.0);
+        assert!(grid().any(|(x, z)| CLOUDS.optical_depth(x, z, 37.0) > 0.0), "con cobertura 0.5 debería haber alguna nube");
+    }
+
+    #[test]
+    fn las_nubes_dependen_solo_del_punto_y_el_tiempo() {
+        for (x, z) in grid() {
+            assert_eq!(CLOUDS.optical_depth(x, z, 12.5), CLOUDS.optical_depth(x, z, 12.5));
+            // Sin viento las nubes no se mueven ni cambian.
+            let calm = CloudSettings { wind_speed: 0.0, ..CLOUDS };
+            assert_eq!(calm.optical_depth(x, z, 0.0), calm.optical_depth(x, z, 500.0));
+            // Más cobertura nunca quita nube.
+            let overcast = CloudSettings { coverage: 0.9, ..CLOUDS };
+            assert!(overcast.optical_depth(x, z, 12.5) >= CLOUDS.optical_depth(x, z, 12.5));
+        }
+    }
+
+    #[test]
+    fn los_rayos_rasantes_atraviesan_mas_nube() {
+        let (x, z) = grid().find(|&(x, z)| CLOUDS.optical_depth(x, z, 0.0) > 0.0).unwrap();
+        let vertical = CLOUDS.optical_depth(x, z, 0.0);
+        let below = Point3::new(x, 0.0, z);
+        assert!((CLOUDS.depth_along(&below, &Vector3::new(0.0, 1.0, 0.0), 0.0) - vertical).abs() < 1e-5);
+
+        // Un rayo inclinado que cruza la capa en el mismo punto atraviesa `1 / y` veces más.
+        let dir = Vector3::new(0.8, 0.6, 0.0);
+        let origin = Point3::new(x - 80.0, 0.0, z);
+        assert!((CLOUDS.depth_along(&origin, &dir, 0.0) - vertical / 0.6).abs() < 1e-3);
+
+        // Los rayos que bajan o que salen por encima de la capa no la cruzan.
+        assert_eq!(CLOUDS.depth_along(&below, &Vector3::new(0.0, -1.0, 0.0), 0.0), 0.0);
+        assert_eq!(CLOUDS.depth_along(&Point3::new(x, 70.0, z), &Vector3::new(0.0, 1.0, 0.0), 0.0), 0.0);
+    }
+}
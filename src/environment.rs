@@ -0,0 +1,238 @@
+/*
+Documentación (bloque):
+Sección: Mapas de entorno HDR
+Descripción: Cielo a partir de una imagen equirectangular en radiancia lineal, normalmente
+un `.hdr` de Radiance: la columna da el azimut (de -π a π alrededor de +y, empezando por
+-x) y la fila la distancia al cénit. `Skybox` la usa como fondo, en las reflexiones y, en
+path tracing, como luz (image-based lighting). Para muestrear esa luz sin ruido se elige
+cada texel con probabilidad proporcional a su luminancia por el ángulo sólido que cubre
+(`sin θ`): una distribución marginal por filas y otra condicional por columnas dentro de
+cada fila, ambas guardadas como funciones de distribución acumuladas. El mapa vive en su
+propio sistema de coordenadas; la rotación y la intensidad las aplica `Skybox`.
+*/
+
+use nalgebra::Vector3;
+use std::f32::consts::PI;
+
+use crate::tonemap::luminance;
+
+pub struct EnvironmentMap {
+    width: usize,
+    height: usize,
+    pixels: Vec<Vector3<f32>>,
+    /// Probabilidad acumulada de cada fila (`height + 1` valores, de 0 a 1).
+    marginal: Vec<f32>,
+    /// Probabilidad acumulada de cada columna dentro de su fila (`width + 1` por fila).
+    conditional: Vec<f32>,
+    /// Peso de cada texel dividido por la suma de todos: su probabilidad de salir elegido.
+    texel_probability: Vec<f32>,
+    /// Dirección del texel más brillante e irradiancia sobre un plano orientado hacia él.
+    key_direction: Vector3<f32>,
+    key_irradiance: Vector3<f32>,
+}
+
+/// Dirección del punto `(u, v)` del mapa, ambos en [0, 1].
+fn direction(u: f32, v: f32) -> Vector3<f32> {
+    let (phi, theta) = ((u - 0.5) * 2.0 * PI, v * PI);
+    Vector3::new(theta.sin() * phi.cos(), theta.cos(), theta.sin() * phi.sin())
+}
+
+/// Punto `(u, v)` del mapa en la dirección unitaria `dir`.
+fn coordinates(dir: &Vector3<f32>) -> (f32, f32) {
+    (dir.z.atan2(dir.x) / (2.0 * PI) + 0.5, dir.y.clamp(-1.0, 1.0).acos() / PI)
+}
+
+/// Índice del intervalo de `cdf` que contiene a `u` y posición de `u` dentro de él.
+fn invert_cdf(cdf: &[f32], u: f32) -> (usize, f32) {
+    let index = cdf.partition_point(|&c| c <= u).clamp(1, cdf.len() - 1) - 1;
+    let width = cdf[index + 1] - cdf[index];
+    let offset = if width > 0.0 { ((u - cdf[index]) / width).clamp(0.0, 1.0) } else { 0.5 };
+    (index, offset)
+}
+
+/// Acumula `weights` en una función de distribución de `weights.len() + 1` valores. Si todos
+/// son cero, la distribución es uniforme.
+fn cumulative(weights: &[f32]) -> (Vec<f32>, f32) {
+    let total: f32 = weights.iter().sum();
+    let mut cdf = Vec::with_capacity(weights.len() + 1);
+    let mut sum = 0.0;
+    cdf.push(0.0);
+    for (i, &weight) in weights.iter().enumerate() {
+        sum += weight;
+        cdf.push(if total > 0.0 { sum / total } else { (i + 1) as f32 / weights.len() as f32 });
+    }
+    (cdf, total)
+}
+
+impl EnvironmentMap {
+    pub fn load(path: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let image = image::open(path)
+            .map_err(|e| format!("no se pudo cargar el mapa de entorno {}: {}", path, e))?
+            .into_rgb32f();
+        let (width, height) = (image.width() as usize, image.height() as usize);
+        if width < 2 || height < 2 {
+            return Err(format!("el mapa de entorno {} es demasiado pequeño", path).into());
+        }
+        if width != 2 * height {
+            println!("⚠ {} mide {}x{}; un mapa equirectangular debería ser el doble de ancho que de alto", path, width, height);
+        }
+        let pixels: Vec<Vector3<f32>> = image
+            .pixels()
+            .map(|p| Vector3::new(p[0], p[1], p[2]).map(|c| if c.is_finite() { c.max(0.0) } else { 0.0 }))
+            .collect();
+        Ok(Self::from_pixels(width, height, pixels))
+    }
+
+    fn from_pixels(width: usize, height: usize, pixels: Vec<Vector3<f32>>) -> Self {
+        let weights: Vec<f32> = pixels
+            .iter()
+            .enumerate()
+            .map(|(i, color)| luminance(color) * (((i / width) as f32 + 0.5) / height as f32 * PI).sin())
+            .collect();
+
+        let mut conditional = Vec::with_capacity(height * (width + 1));
+        let mut row_totals = Vec::with_capacity(height);
+        for row in weights.chunks(width) {
+            let (cdf, total) = cumulative(row);
+            conditional.extend(cdf);
+            row_totals.push(total);
+        }
+        let (marginal, total) = cumulative(&row_totals);
+        let texel_probability = if total > 0.0 {
+            weights.iter().map(|weight| weight / total).collect()
+        } else {
+            vec![1.0 / (width * height) as f32; width * height]
+        };
+
+        let brightest = (0..pixels.len())
+            .max_by(|&a, &b| luminance(&pixels[a]).total_cmp(&luminance(&pixels[b])))
+            .unwrap_or(0);
+        let key_direction = direction(((brightest % width) as f32 + 0.5) / width as f32, ((brightest / width) as f32 + 0.5) / height as f32);
+        let solid_angle = 2.0 * PI * PI / (width * height) as f32;
+        let key_irradiance = pixels.iter().enumerate().fold(Vector3::zeros(), |sum, (i, color)| {
+            let (u, v) = (((i % width) as f32 + 0.5) / width as f32, ((i / width) as f32 + 0.5) / height as f32);
+            let cos = key_direction.dot(&direction(u, v)).max(0.0);
+            sum + color * (cos * solid_angle * (v * PI).sin())
+        });
+
+        EnvironmentMap { width, height, pixels, marginal, conditional, texel_probability, key_direction, key_irradiance }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Radiancia del mapa en la dirección unitaria `dir`, con interpolación bilineal.
+    pub fn radiance(&self, dir: &Vector3<f32>) -> Vector3<f32> {
+        let (u, v) = coordinates(dir);
+        let x = u * self.width as f32 - 0.5;
+        let y = (v * self.height as f32 - 0.5).clamp(0.0, (self.height - 1) as f32);
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let column = |offset: i32| (x0 as i32 + offset).rem_euclid(self.width as i32) as usize;
+        let row = |offset: usize| (y0 as usize + offset).min(self.height - 1) * self.width;
+        let texel = |dx: i32, dy: usize| self.pixels[row(dy) + column(dx)];
+        let top = texel(0, 0).lerp(&texel(1, 0), fx);
+        let bottom = texel(0, 1).lerp(&texel(1, 1), fx);
+        top.lerp(&bottom, fy)
+    }
+
+    /// Elige una dirección con probabilidad proporcional a la luz que llega de ella, a partir
+    /// de dos números uniformes en [0, 1). Devuelve la dirección, su radiancia y la densidad
+    /// de probabilidad por unidad de ángulo sólido. La densidad es la del texel elegido,
+    /// constante dentro de él salvo por el `sin θ` del ángulo sólido, mientras que la
+    /// radiancia se interpola como en `radiance`; cerca del borde de un texel brillante el
+    /// cociente entre ambas deja de ser constante, a cambio de no mostrar los texels.
+    pub fn sample(&self, u1: f32, u2: f32) -> (Vector3<f32>, Vector3<f32>, f32) {
+        let (row, fy) = invert_cdf(&self.marginal, u1);
+        let (column, fx) = invert_cdf(&self.conditional[row * (self.width + 1)..(row + 1) * (self.width + 1)], u2);
+        let (u, v) = ((column as f32 + fx) / self.width as f32, (row as f32 + fy) / self.height as f32);
+        let dir = direction(u, v);
+
+        let sin_theta = (v * PI).sin().max(1e-4);
+        let pdf = self.texel_probability[row * self.width + column] * (self.width * self.height) as f32 / (2.0 * PI * PI * sin_theta);
+        (dir, self.radiance(&dir), pdf)
+    }
+
+    /// Dirección de la zona más brillante del mapa y la irradiancia que da el mapa entero
+    /// sobre un plano orientado hacia ella.
+    pub fn key_light(&self) -> (Vector3<f32>, Vector3<f32>) {
+        (self.key_direction, self.key_irradiance)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WIDTH: usize = 16;
+    const HEIGHT: usize = 8;
+    /// Texel brillante del mapa de prueba: columna y fila.
+    const BRIGHT: (usize, usize) = (5, 3);
+
+    /// Mapa tenue (0.01) con un único texel de radiancia `bright`.
+    fn single_bright_texel(bright: f32) -> EnvironmentMap {
+        let mut pixels = vec![Vector3::repeat(0.01); WIDTH * HEIGHT];
+        pixels[BRIGHT.1 * WIDTH + BRIGHT.0] = Vector3::repeat(bright);
+        EnvironmentMap::from_pixels(WIDTH, HEIGHT, pixels)
+    }
+
+    /// `count` muestras bien repartidas: `u1` recorre [0, 1) a pasos regulares y `u2` salta
+    /// por la razón áurea (una red de rango 1), así ninguna fila del mapa recibe de más.
+    fn stratified(map: &EnvironmentMap, count: usize) -> Vec<(Vector3<f32>, Vector3<f32>, f32)> {
+        let golden = (5f64.sqrt() - 1.0) / 2.0;
+        (0..count).map(|i| map.sample(((i as f64 + 0.5) / count as f64) as f32, (i as f64 * golden).fract() as f32)).collect()
+    }
+
+    fn texel(dir: &Vector3<f32>) -> (usize, usize) {
+        let (u, v) = coordinates(dir);
+        (((u * WIDTH as f32) as usize).min(WIDTH - 1), ((v * HEIGHT as f32) as usize).min(HEIGHT - 1))
+    }
+
+    #[test]
+    fn las_muestras_caen_en_el_texel_brillante() {
+        let map = single_bright_texel(1000.0);
+        let expected = map.texel_probability[BRIGHT.1 * WIDTH + BRIGHT.0];
+        assert!(expected > 0.99, "probabilidad del texel brillante {}", expected);
+
+        let samples = stratified(&map, 1 << 14);
+        let inside = samples.iter().filter(|(dir, ..)| texel(dir) == BRIGHT).count();
+        let fraction = inside as f32 / samples.len() as f32;
+        assert!((fraction - expected).abs() < 0.002, "{} de las muestras en el texel brillante, se esperaba {}", fraction, expected);
+    }
+
+    #[test]
+    fn la_densidad_integra_uno_sobre_la_esfera() {
+        // Con muestras tomadas según la densidad, la media de `1 / pdf` estima el ángulo
+        // sólido en el que es positiva: la esfera entera, porque ningún texel es negro. El
+        // texel brillante es moderado para que las muestras alcancen también a los tenues.
+        for map in [single_bright_texel(1.0), EnvironmentMap::from_pixels(WIDTH, HEIGHT, vec![Vector3::repeat(0.5); WIDTH * HEIGHT])] {
+            let samples = stratified(&map, 1 << 16);
+            let solid_angle = samples.iter().map(|&(.., pdf)| 1.0 / pdf as f64).sum::<f64>() / samples.len() as f64;
+            assert!((solid_angle / (4.0 * std::f64::consts::PI) - 1.0).abs() < 0.002, "ángulo sólido {}", solid_angle);
+        }
+    }
+
+    #[test]
+    fn la_densidad_es_la_del_texel_y_la_radiancia_se_interpola() {
+        let map = single_bright_texel(1000.0);
+        let probability = map.texel_probability[BRIGHT.1 * WIDTH + BRIGHT.0];
+        let mut radiances = Vec::new();
+        for (dir, radiance, pdf) in stratified(&map, 1 << 12) {
+            if texel(&dir) != BRIGHT {
+                continue;
+            }
+            let sin_theta = (1.0 - dir.y * dir.y).sqrt();
+            let expected = probability * (WIDTH * HEIGHT) as f32 / (2.0 * PI * PI * sin_theta);
+            assert!((pdf / expected - 1.0).abs() < 1e-3, "pdf {} en vez de {}", pdf, expected);
+            radiances.push(radiance.x);
+        }
+        // En el centro del texel la radiancia es la suya; hacia los bordes se mezcla con los vecinos.
+        let (min, max) = radiances.iter().fold((f32::INFINITY, 0.0f32), |(min, max), &r| (min.min(r), max.max(r)));
+        assert!(max > 900.0 && min < 400.0, "radiancias entre {} y {}", min, max);
+    }
+}
@@ -3,11 +3,17 @@ Project: cubo (raytracer voxel)
 
 Breve descripción:
 Este archivo contiene el programa principal del raytracer por voxels. Implementa:
-- Carga de capas voxel desde archivos en `capas/`.
-- Un gestor sencillo de texturas que crea marcadores si faltan recursos.
-- Un trazador de rayos DDA robusto con un fallback por raymarching.
-- Un sombreador PBR simplificado con Fresnel, especular y reflexiones recursivas.
-- Un skybox procedimental día/noche con sol y estrellas.
+- Carga de capas voxel desde archivos en `capas/` (carpeta configurable por CLI).
+- Un gestor sencillo de texturas con mipmaps y filtrado, que crea marcadores si faltan recursos.
+- Un trazador de rayos DDA con intersección exacta contra las cajas de cada forma de bloque.
+- Un sombreador PBR simplificado con Fresnel, especular, sombras y reflexiones recursivas.
+- Un ciclo día/noche continuo que mueve el sol y la luna (con fases), con posición solar
+  geográfica opcional y un campo de estrellas estable.
+- Un cielo físico por dispersión atmosférica (Rayleigh y Mie), un degradado simple o un
+  mapa de entorno HDR que ilumina la escena en path tracing.
+- Una capa de nubes procedimental opcional movida por el viento, que proyecta sombras en el
+  diorama.
+- Un revelado HDR con exposición (manual o automática), tone mapping y salida sRGB.
 - Una cámara orbital con controles básicos para inspeccionar la escena.
 
 Nota: la documentación más específica para secciones concretas aparece junto a
@@ -18,243 +24,463 @@ use nalgebra::{Point3, Vector3};
 use image::{RgbImage, Rgb};
 use rayon::prelude::*;
 use std::fs;
-
-const WIDTH: usize = 600;
-const HEIGHT: usize = 450;
+use std::sync::Arc;
+
+mod atmosphere;
+mod bench;
+mod chunks;
+mod clouds;
+mod environment;
+mod nbt;
+mod night;
+mod noise;
+mod occupancy;
+mod registry;
+mod schematic;
+#[cfg(test)]
+mod test_util;
+mod tonemap;
+mod vox;
+
+use atmosphere::Atmosphere;
+use chunks::{ChunkGrid, ChunkStore};
+use clouds::CloudSettings;
+use environment::EnvironmentMap;
+use occupancy::Occupancy;
+use registry::{registry, BlockShape, ShapeBox};
+use tonemap::{srgb_to_linear, Develop, ToneMap};
+
+const DEFAULT_WIDTH: usize = 600;
+const DEFAULT_HEIGHT: usize = 450;
+
+const DEFAULT_ORBIT_YAW: f32 = 0.8;
+const DEFAULT_ORBIT_RADIUS: f32 = 13.0;
+const DEFAULT_ORBIT_HEIGHT: f32 = 6.0;
+
+/// Segundos que dura un día completo del ciclo día/noche.
+const DEFAULT_DAY_LENGTH: f32 = 120.0;
+/// Cobertura con la que la tecla B muestra las nubes si no se pidió otra con `--cloud-coverage`.
+const DEFAULT_CLOUD_COVERAGE: f32 = 0.4;
+/// Día del año que se usa con `--latitude` o `--longitude` si no se da `--date` (21 de junio).
+const SUMMER_SOLSTICE: u32 = 172;
 
 /*
 Documentación (bloque):
 Sección: Tipos y constantes del motor
-Descripción: Enumeración de tipos de bloque utilizados en el diorama, colores de fallback y
-matriz que indica qué bloques emiten luz. Estos valores sirven como referencia para cargar
-texturas, calcular materiales y determinar fuentes emisivas en el sombreado.
+Descripción: Identificador de los tipos de bloque del diorama. Los colores de fallback,
+las texturas, los materiales y qué bloques emiten luz ya no están en tablas del código:
+se consultan en el registro de bloques (ver `registry.rs`).
 */
 
-#[derive(Debug, Clone, Copy, PartialEq)]
-#[repr(u8)]
-pub enum BlockType {
-    Aire = 0,
-    TerracotaNaranja = 1,
-    Netherrack = 2,
-    BloqueMagma = 3,
-    TerracotaNormal = 4,
-    TerracotaAmarilla = 5,
-    ObsidianaNormal = 6,
-    Lava = 7,
-    BloqueOro = 8,
-    Cofre = 9,
-    EscaleraPiedra = 10,
-    SlabPiedra = 11,
-    CryingObsidian = 12,
-}
-
-
-static BLOCK_COLORS: [(f32, f32, f32); 13] = [
-    (0.0, 0.0, 0.0),
-    (0.8, 0.4, 0.1),
-    (0.4, 0.2, 0.2),
-    (0.9, 0.3, 0.1),
-    (0.6, 0.4, 0.3),
-    (0.9, 0.8, 0.2),
-    (0.1, 0.1, 0.2),
-    (1.0, 0.5, 0.0),
-    (1.0, 0.8, 0.0),
-    (0.5, 0.3, 0.2),
-    (0.5, 0.5, 0.5),
-    (0.6, 0.6, 0.6),
-    (0.3, 0.1, 0.4),
-];
-
-static EMISSIVE_BLOCKS: [bool; 13] = [
-    false, false, false, true,
-    false, false, false, true,
-    false, false, false, false, false,
-];
+/// Identificador de bloque: posición de su definición en el registro (`bloques.txt`).
+/// El 0 es siempre el aire.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct BlockType(u16);
+
+impl BlockType {
+    pub const AIRE: BlockType = BlockType(0);
+}
+
+impl std::fmt::Debug for BlockType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", registry().get(*self).name)
+    }
+}
 
 /*
 Documentación (bloque):
 Sección: Gestor de texturas
-Descripción: Implementa carga de imágenes desde la carpeta `textures/`. Si falta algún
+Descripción: Implementa carga de imágenes desde la carpeta de texturas. Si falta algún
 archivo, genera un marcador (placeholder) básico en disco y utiliza un color de fallback.
 Proporciona métodos para obtener una textura por `BlockType` y muestrear colores UV.
+Las imágenes se decodifican de sRGB a lineal al cargarlas, y cada textura guarda su cadena
+de mipmaps, promediada ya en lineal.
 */
 
-pub struct Texture {
-    pixels: Vec<Rgb<u8>>,
+/// Un nivel de la cadena de mipmaps, con el color lineal de cada texel en [0,1].
+struct MipLevel {
+    texels: Vec<Vector3<f32>>,
     width: u32,
     height: u32,
 }
 
+impl MipLevel {
+    #[inline]
+    fn texel(&self, x: u32, y: u32) -> Vector3<f32> {
+        self.texels[(y * self.width + x) as usize]
+    }
+
+    /// Texel más cercano a `(u, v)`.
+    fn nearest(&self, u: f32, v: f32) -> Vector3<f32> {
+        let x = ((u * self.width as f32) as u32).min(self.width - 1);
+        let y = ((v * self.height as f32) as u32).min(self.height - 1);
+        self.texel(x, y)
+    }
+
+    /// Interpolación bilineal entre los cuatro texels más cercanos a `(u, v)`. Los bordes
+    /// se extienden en lugar de repetirse, porque cada cara muestra la textura completa.
+    fn bilinear(&self, u: f32, v: f32) -> Vector3<f32> {
+        let x = (u * self.width as f32 - 0.5).clamp(0.0, (self.width - 1) as f32);
+        let y = (v * self.height as f32 - 0.5).clamp(0.0, (self.height - 1) as f32);
+        let (x0, y0) = (x as u32, y as u32);
+        let (x1, y1) = ((x0 + 1).min(self.width - 1), (y0 + 1).min(self.height - 1));
+        let (fx, fy) = (x - x0 as f32, y - y0 as f32);
+        let top = self.texel(x0, y0).lerp(&self.texel(x1, y0), fx);
+        let bottom = self.texel(x0, y1).lerp(&self.texel(x1, y1), fx);
+        top.lerp(&bottom, fy)
+    }
+
+    /// Nivel siguiente de la cadena: la mitad de tamaño por eje, promediando bloques de 2×2.
+    fn downsample(&self) -> MipLevel {
+        let (width, height) = ((self.width / 2).max(1), (self.height / 2).max(1));
+        let mut texels = Vec::with_capacity((width * height) as usize);
+        for y in 0..height {
+            for x in 0..width {
+                let (sx, sy) = (x * 2, y * 2);
+                let (sx1, sy1) = ((sx + 1).min(self.width - 1), (sy + 1).min(self.height - 1));
+                texels.push((self.texel(sx, sy) + self.texel(sx1, sy) + self.texel(sx, sy1) + self.texel(sx1, sy1)) * 0.25);
+            }
+        }
+        MipLevel { texels, width, height }
+    }
+}
+
+/*
+Documentación (bloque):
+Sección: Filtrado de texturas
+Descripción: `Nearest` toma siempre el texel más cercano del nivel 0, sin mipmaps.
+`Bilinear` interpola dentro del nivel de mipmap más adecuado a la huella del rayo y
+`Trilinear` además mezcla los dos niveles vecinos, para que no se note el salto entre
+ellos. Mientras un texel cubra más de un píxel (de cerca) los tres modos usan el texel
+más cercano, así el pixel art se ve nítido; el filtrado solo actúa al alejarse, cuando
+varios texels caen en el mismo píxel y el muestreo puntual produciría moiré.
+*/
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum TextureFilter {
+    Nearest,
+    Bilinear,
+    Trilinear,
+}
+
+impl TextureFilter {
+    fn parse(value: &str) -> Option<Self> {
+        match value {
+            "nearest" => Some(TextureFilter::Nearest),
+            "bilinear" => Some(TextureFilter::Bilinear),
+            "trilinear" => Some(TextureFilter::Trilinear),
+            _ => None,
+        }
+    }
+
+    /// Siguiente modo, para alternarlos desde la ventana.
+    fn next(self) -> Self {
+        match self {
+            TextureFilter::Nearest => TextureFilter::Bilinear,
+            TextureFilter::Bilinear => TextureFilter::Trilinear,
+            TextureFilter::Trilinear => TextureFilter::Nearest,
+        }
+    }
+}
+
+pub struct Texture {
+    /// Cadena de mipmaps: el nivel 0 es la imagen original y el último mide 1×1.
+    levels: Vec<MipLevel>,
+}
+
 impl Texture {
     fn new(width: u32, height: u32, color: Rgb<u8>) -> Self {
-        Texture {
-            pixels: vec![color; (width * height) as usize],
-            width,
-            height,
-        }
+        Self::from_image(RgbImage::from_pixel(width, height, color))
     }
 
     fn from_image(img: RgbImage) -> Self {
         let (width, height) = (img.width(), img.height());
-        let pixels = img.into_raw()
-            .chunks(3)
-            .map(|chunk| Rgb([chunk[0], chunk[1], chunk[2]]))
+        let texels = img.pixels()
+            .map(|px| Vector3::new(px[0] as f32, px[1] as f32, px[2] as f32).map(|c| srgb_to_linear(c / 255.0)))
             .collect();
 
-        Texture { pixels, width, height }
+        let mut levels = vec![MipLevel { texels, width, height }];
+        while let Some(last) = levels.last().filter(|level| level.width > 1 || level.height > 1) {
+            levels.push(last.downsample());
+        }
+        Texture { levels }
     }
 
+    /// Color en `(u, v)` con el filtro `filter`, para una huella de `footprint` unidades de
+    /// mundo (un bloque mide 1). La huella elige el nivel de mipmap: `log2` de los texels
+    /// del nivel 0 que cubre. Con huella 0 es un muestreo puntual del nivel 0.
+    fn sample(&self, u: f32, v: f32, footprint: f32, filter: TextureFilter) -> Vector3<f32> {
+        let base = &self.levels[0];
+        let lod = (footprint * base.width.max(base.height) as f32).log2();
+        if filter == TextureFilter::Nearest || lod.is_nan() || lod <= 0.0 {
+            return base.nearest(u, v);
+        }
 
-    fn sample(&self, u: f32, v: f32) -> Rgb<u8> {
-        let x = ((u * self.width as f32) as u32).min(self.width - 1);
-        let y = ((v * self.height as f32) as u32).min(self.height - 1);
-        let index = (y * self.width + x) as usize;
-        self.pixels[index]
+        let last = (self.levels.len() - 1) as f32;
+        match filter {
+            TextureFilter::Bilinear => self.levels[lod.round().min(last) as usize].bilinear(u, v),
+            _ => {
+                let lod = lod.min(last);
+                let level = lod.floor() as usize;
+                let fine = self.levels[level].bilinear(u, v);
+                match self.levels.get(level + 1) {
+                    Some(coarse) => fine.lerp(&coarse.bilinear(u, v), lod - level as f32),
+                    None => fine,
+                }
+            }
+        }
+    }
+}
+
+/*
+Documentación (bloque):
+Sección: Conos de rayo
+Descripción: Aproximan la huella de un píxel a lo largo del rayo para elegir el nivel de
+mipmap. El cono parte con la anchura de un píxel en la cámara (`spread`, en radianes) y se
+ensancha linealmente con la distancia recorrida; sobre una superficie inclinada la huella
+se alarga en `1 / cos`, limitado para no emborronar del todo las caras rasantes. Los
+reflejos y las refracciones conservan la apertura del cono, como en un espejo plano.
+*/
+#[derive(Debug, Clone, Copy)]
+pub struct RayCone {
+    /// Anchura del cono en el origen del rayo.
+    width: f32,
+    /// Ángulo que se abre por unidad de distancia.
+    spread: f32,
+}
+
+/// Coseno mínimo con el que se alarga la huella sobre caras rasantes.
+const CONE_MIN_COS: f32 = 0.2;
+
+impl RayCone {
+    /// Cono de un píxel de una cámara con `tan_half_fov` y `image_height` filas.
+    fn primary(tan_half_fov: f32, image_height: usize) -> Self {
+        RayCone { width: 0.0, spread: 2.0 * tan_half_fov / image_height as f32 }
+    }
+
+    /// Cono del rayo que sale del impacto a distancia `t`.
+    fn advance(self, t: f32) -> Self {
+        RayCone { width: self.width + self.spread * t, spread: self.spread }
+    }
+
+    /// Huella sobre una cara con normal `normal` alcanzada a distancia `t` en dirección `dir`.
+    fn footprint(self, t: f32, dir: &Vector3<f32>, normal: &Vector3<f32>) -> f32 {
+        self.advance(t).width / dir.dot(normal).abs().max(CONE_MIN_COS)
+    }
+}
+
+/// Caras de un bloque a efectos de textura. El frente es la cara que mira hacia -z en el
+/// marco canónico del bloque, es decir, hacia donde indique su `BlockState`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BlockFace {
+    Top,
+    Bottom,
+    Side,
+    Front,
+}
+
+impl BlockFace {
+    const ALL: [BlockFace; 4] = [BlockFace::Top, BlockFace::Bottom, BlockFace::Side, BlockFace::Front];
+
+    /// Cara correspondiente a una normal ya llevada al marco canónico (ver `VoxelWorld::texture_face`).
+    fn from_normal(normal: &Vector3<f32>) -> Self {
+        if normal.y > 0.5 {
+            BlockFace::Top
+        } else if normal.y < -0.5 {
+            BlockFace::Bottom
+        } else if normal.z < -0.5 {
+            BlockFace::Front
+        } else {
+            BlockFace::Side
+        }
     }
 }
 
 pub struct TextureManager {
     textures: Vec<Texture>,
+    /// Por bloque, índice en `textures` de cada cara, en el orden de `BlockFace::ALL`.
+    faces: Vec<[usize; 4]>,
+    filter: TextureFilter,
 }
 
 impl TextureManager {
-    fn new() -> Self {
+    fn new(filter: TextureFilter) -> Self {
         TextureManager {
             textures: Vec::new(),
+            faces: Vec::new(),
+            filter,
         }
     }
 
-    fn load_textures(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+    fn load_textures(&mut self, texture_dir: &str) -> Result<(), Box<dyn std::error::Error>> {
         /*
-        Descripción: Lista de nombres esperados para las texturas. Si faltan, se crea un
-        PNG de marcador con color de fallback. Los índices coinciden con `BlockType`.
+        Descripción: Resuelve, para cada bloque del registro y en el mismo orden, la
+        textura de sus cuatro caras (arriba, abajo, lados y frente), para que
+        `get_texture` pueda indexar por `BlockType` y cara. Cada fichero se carga una
+        sola vez aunque lo compartan varios bloques o caras. Si el fichero falta, se crea
+        un PNG de marcador con el color de fallback del bloque.
         */
 
-        let texture_files = [
-            "aire.png",
-            "terracota_naranja.png",
-            "netherrack.png",
-            "bloque_magma.png",
-            "terracota_normal.png",
-            "terracota_amarilla.png",
-            "obsidiana.png",
-            "lava.png",
-            "bloque_oro.png",
-            "cofre.png",
-            "piedra.png",
-            "piedra.png",
-            "crying_obsidian.png",
-        ];
+        let _ = std::fs::create_dir_all(texture_dir);
+        let mut loaded: std::collections::HashMap<String, usize> = std::collections::HashMap::new();
+
+        for (_, block) in registry().blocks() {
+            let fallback_color = block.color;
+            let fallback_px = Rgb([
+                (fallback_color.0 * 255.0) as u8,
+                (fallback_color.1 * 255.0) as u8,
+                (fallback_color.2 * 255.0) as u8,
+            ]);
+
+            let mut faces = [0usize; 4];
+            let mut solid_color = None;
+            for (slot, face) in faces.iter_mut().zip(BlockFace::ALL) {
+                *slot = match block.texture_for(face) {
+                    Some(filename) => match loaded.get(filename) {
+                        Some(&index) => index,
+                        None => {
+                            let index = self.load_file(texture_dir, filename, fallback_px, &block.name);
+                            loaded.insert(filename.to_string(), index);
+                            index
+                        }
+                    },
+                    None => *solid_color.get_or_insert_with(|| {
+                        self.textures.push(Texture::new(16, 16, fallback_px));
+                        self.textures.len() - 1
+                    }),
+                };
+            }
+            self.faces.push(faces);
+        }
 
+        Ok(())
+    }
 
-        let _ = std::fs::create_dir_all("textures");
-
-        for (i, filename) in texture_files.iter().enumerate() {
-            let path = format!("textures/{}", filename);
-
-                /*
-                Si el archivo no existe, se genera un PNG de marcador de 16×16 con el color
-                base definido en `BLOCK_COLORS`. Esto ayuda a evitar fallos visuales cuando
-                faltan recursos.
-                */
-            if !std::path::Path::new(&path).exists() {
-                let fallback_color = BLOCK_COLORS[i];
-                let mut img = RgbImage::new(16, 16);
-                let px = image::Rgb([
-                    (fallback_color.0 * 255.0) as u8,
-                    (fallback_color.1 * 255.0) as u8,
-                    (fallback_color.2 * 255.0) as u8,
-                ]);
-                for y in 0..16 {
-                    for x in 0..16 {
-                        img.put_pixel(x, y, px);
-                    }
+    /// Carga `filename` de la carpeta de texturas y devuelve su índice en `textures`.
+    fn load_file(&mut self, texture_dir: &str, filename: &str, fallback_px: Rgb<u8>, block_name: &str) -> usize {
+        let path = std::path::Path::new(texture_dir).join(filename).display().to_string();
+
+            /*
+            Si el archivo no existe, se genera un PNG de marcador de 16×16 con el color
+            de fallback del registro. Esto ayuda a evitar fallos visuales cuando
+            faltan recursos.
+            */
+        if !std::path::Path::new(&path).exists() {
+            let mut img = RgbImage::new(16, 16);
+            for y in 0..16 {
+                for x in 0..16 {
+                    img.put_pixel(x, y, fallback_px);
                 }
+            }
 
-                if let Err(e) = img.save(&path) {
-                    println!("⚠ No se pudo crear marcador {}: {}", path, e);
-                } else {
-                    println!("ℹ Marcador creado: {}", path);
-                }
+            if let Err(e) = img.save(&path) {
+                println!("⚠ No se pudo crear marcador {}: {}", path, e);
+            } else {
+                println!("ℹ Marcador creado: {}", path);
             }
+        }
 
-            match image::open(&path) {
-                Ok(img) => {
-                    let rgb_img = img.to_rgb8();
-                    self.textures.push(Texture::from_image(rgb_img));
-                    if i == 10 || i == 11 {
-                        println!("✓ Textura cargada: {} (compartida para escalera/slab)", path);
-                    } else {
-                        println!("✓ Textura cargada: {}", path);
-                    }
-                }
-                Err(_) => {
-
-                    let fallback_color = BLOCK_COLORS[i];
-                    let color = Rgb([
-                        (fallback_color.0 * 255.0) as u8,
-                        (fallback_color.1 * 255.0) as u8,
-                        (fallback_color.2 * 255.0) as u8,
-                    ]);
-                    self.textures.push(Texture::new(16, 16, color));
-                    println!("⚠ No se encontró {} y no se pudo cargar, usando color sólido", path);
-                }
+        match image::open(&path) {
+            Ok(img) => {
+                let rgb_img = img.to_rgb8();
+                self.textures.push(Texture::from_image(rgb_img));
+                println!("✓ Textura cargada: {} ({})", path, block_name);
+            }
+            Err(_) => {
+                self.textures.push(Texture::new(16, 16, fallback_px));
+                println!("⚠ No se encontró {} y no se pudo cargar, usando color sólido", path);
             }
         }
+        self.textures.len() - 1
+    }
 
-        Ok(())
+    /// Textura de la cara `face` de `block_type`.
+    fn get_texture(&self, block_type: BlockType, face: BlockFace) -> &Texture {
+        &self.textures[self.faces[block_type.0 as usize][face as usize]]
     }
 
-    fn get_texture(&self, block_type: BlockType) -> &Texture {
-        &self.textures[block_type as usize]
+    /// Color de la cara `face` de `block_type` en `uv`, filtrado para una huella de `footprint`.
+    fn sample(&self, block_type: BlockType, face: BlockFace, uv: (f32, f32), footprint: f32) -> Vector3<f32> {
+        self.get_texture(block_type, face).sample(uv.0, uv.1, footprint, self.filter)
     }
 }
 
-#[allow(dead_code)]
 impl BlockType {
     #[inline]
     fn from_char(c: char) -> Self {
-        match c {
-            'n' => BlockType::TerracotaNaranja,
-            'i' => BlockType::Netherrack,
-            'l' => BlockType::BloqueMagma,
-            't' => BlockType::TerracotaNormal,
-            'a' => BlockType::TerracotaAmarilla,
-            'o' => BlockType::ObsidianaNormal,
-            'p' => BlockType::Lava,
-            'y' => BlockType::BloqueOro,
-            'c' => BlockType::Cofre,
-            'e' => BlockType::EscaleraPiedra,
-            's' => BlockType::SlabPiedra,
-            'k' => BlockType::CryingObsidian,
-            ' ' => BlockType::Aire,
-            _ => BlockType::Aire,
-        }
+        Self::parse_char(c).unwrap_or(BlockType::AIRE)
+    }
+
+    /// Como `from_char`, pero devuelve `None` para caracteres que no son ningún bloque.
+    #[inline]
+    fn parse_char(c: char) -> Option<Self> {
+        registry().by_char(c)
     }
 
     #[inline]
-    fn get_color(self) -> (f32, f32, f32) {
-        BLOCK_COLORS[self as usize]
+    fn from_index(index: usize) -> Option<Self> {
+        registry().by_index(index)
     }
 
     #[inline]
     fn is_solid(self) -> bool {
-        self != BlockType::Aire
+        self != BlockType::AIRE
     }
 
     #[inline]
     fn emits_light(self) -> bool {
-        EMISSIVE_BLOCKS[self as usize]
+        registry().get(self).emissive
+    }
+
+    /// Color (e intensidad) de la luz que emite el bloque; su color de fallback si el
+    /// registro no indica otro.
+    #[inline]
+    fn light_color(self) -> Vector3<f32> {
+        let def = registry().get(self);
+        let (r, g, b) = def.light.unwrap_or(def.color);
+        Vector3::new(r, g, b)
+    }
+
+    /// Los cubos completos cortan la luz de bloque; el aire, las formas parciales (slabs,
+    /// escaleras, postes...) y los bloques translúcidos no.
+    #[inline]
+    fn is_opaque(self) -> bool {
+        self.is_solid() && self.shape() == BlockShape::Cube && !self.is_translucent()
+    }
+
+    #[inline]
+    fn is_translucent(self) -> bool {
+        registry().get(self).ior.is_some()
+    }
+
+    /// Índice de refracción del medio; 1 para el aire y los bloques opacos.
+    #[inline]
+    fn ior(self) -> f32 {
+        registry().get(self).ior.unwrap_or(1.0)
+    }
+
+    /// Fracción de luz que sobrevive tras recorrer `distance` bloques dentro de este medio.
+    #[inline]
+    fn transmittance(self, distance: f32) -> Vector3<f32> {
+        registry().get(self).absorption.map(|a| (-a * distance).exp())
     }
 
     #[inline]
-    fn is_slab(self) -> bool {
-        self == BlockType::SlabPiedra
+    fn shape(self) -> BlockShape {
+        registry().get(self).shape
     }
 
+    /// Cajas que ocupa el bloque con la orientación `state`; un slab doble es un cubo.
     #[inline]
-    fn is_stairs(self) -> bool {
-        self == BlockType::EscaleraPiedra
+    fn boxes(self, state: BlockState) -> &'static [ShapeBox] {
+        match self.shape() {
+            BlockShape::Slab if state.is_double() => BlockShape::Cube.boxes(),
+            shape => shape.boxes(),
+        }
+    }
+
+    #[inline]
+    fn is_full_cube(self, state: BlockState) -> bool {
+        self.boxes(state) == BlockShape::Cube.boxes()
     }
 }
 
@@ -276,75 +502,9 @@ impl MaterialProperties {
 }
 
 
+#[inline]
 pub fn get_material(block: BlockType) -> MaterialProperties {
-    match block {
-        BlockType::BloqueOro => {
-
-            MaterialProperties::new(
-                Vector3::new(1.0, 0.766, 0.336),
-                0.9,
-                0.12,
-                0.8,
-                0.0,
-                Vector3::new(1.0, 0.85, 0.45),
-            )
-        }
-        BlockType::ObsidianaNormal => {
-
-            MaterialProperties::new(
-                Vector3::new(0.15, 0.12, 0.12),
-                0.0,
-                0.03,
-                0.95,
-                0.0,
-                Vector3::new(0.9, 0.9, 0.95),
-            )
-        }
-        BlockType::CryingObsidian => {
-
-            MaterialProperties::new(
-                Vector3::new(0.30, 0.05, 0.45),
-                0.0,
-                0.12,
-                0.55,
-                0.08,
-                Vector3::new(0.6, 0.2, 0.8),
-            )
-        }
-        BlockType::Lava => {
-
-            MaterialProperties::new(
-                Vector3::new(0.9, 0.4, 0.1),
-                0.0,
-                0.35,
-                0.25,
-                1.5,
-                Vector3::new(1.0, 0.6, 0.2),
-            )
-        }
-        BlockType::BloqueMagma => {
-
-            MaterialProperties::new(
-                Vector3::new(0.5, 0.2, 0.1),
-                0.0,
-                0.28,
-                0.28,
-                0.8,
-                Vector3::new(0.7, 0.3, 0.15),
-            )
-        }
-        _ => {
-
-            MaterialProperties::new(
-                Vector3::new(0.8, 0.8, 0.8),
-                0.0,
-                0.9,
-                0.04,
-                0.0,
-                Vector3::new(0.04, 0.04, 0.04),
-            )
-        }
-    }
+    registry().get(block).material
 }
 
 
@@ -355,17 +515,119 @@ fn reflect(dir: &Vector3<f32>, normal: &Vector3<f32>) -> Vector3<f32> {
 
 
 
+/// Reflectancia a incidencia normal (F0) por canal: 0.04 en dieléctricos y el color
+/// especular en metales, de modo que el oro refleja dorado.
+#[inline]
+fn specular_f0(material: &MaterialProperties) -> Vector3<f32> {
+    Vector3::new(0.04, 0.04, 0.04).lerp(&material.specular_color, material.metallic)
+}
+
+/// Aproximación de Schlick, canal a canal.
+#[inline]
+fn fresnel_schlick(cos_theta: f32, f0: &Vector3<f32>) -> Vector3<f32> {
+    let factor = (1.0 - cos_theta.clamp(0.0, 1.0)).powi(5);
+    f0 + (Vector3::new(1.0, 1.0, 1.0) - f0) * factor
+}
+
+/// α de GGX a partir de la `rugosidad` perceptual del registro (α = rugosidad²).
+#[inline]
+fn ggx_alpha(roughness: f32) -> f32 {
+    (roughness * roughness).max(1e-3)
+}
+
+/// Distribución de normales GGX (Trowbridge-Reitz).
+#[inline]
+fn ggx_distribution(n_dot_h: f32, alpha: f32) -> f32 {
+    let a2 = alpha * alpha;
+    let d = n_dot_h * n_dot_h * (a2 - 1.0) + 1.0;
+    a2 / (std::f32::consts::PI * d * d)
+}
+
+/// Enmascaramiento de Smith para GGX en una sola dirección.
 #[inline]
-fn fresnel_simple(view_dir: &Vector3<f32>, normal: &Vector3<f32>, material: &MaterialProperties) -> f32 {
-    let v_dot_n = view_dir.dot(normal).max(0.0);
+fn smith_g1(n_dot_x: f32, alpha: f32) -> f32 {
+    let a2 = alpha * alpha;
+    2.0 * n_dot_x / (n_dot_x + (a2 + (1.0 - a2) * n_dot_x * n_dot_x).sqrt())
+}
+
+/// BRDF especular de Cook-Torrance, D·G·F / (4·(n·l)·(n·v)).
+fn cook_torrance(normal: &Vector3<f32>, view_dir: &Vector3<f32>, light_dir: &Vector3<f32>, alpha: f32, f0: &Vector3<f32>) -> Vector3<f32> {
+    let n_dot_l = normal.dot(light_dir);
+    let n_dot_v = normal.dot(view_dir);
+    if n_dot_l <= 0.0 || n_dot_v <= 0.0 {
+        return Vector3::zeros();
+    }
+
+    let half = (light_dir + view_dir).normalize();
+    let d = ggx_distribution(normal.dot(&half).max(0.0), alpha);
+    let g = smith_g1(n_dot_l, alpha) * smith_g1(n_dot_v, alpha);
+    fresnel_schlick(view_dir.dot(&half), f0) * (d * g / (4.0 * n_dot_l * n_dot_v))
+}
+
+/*
+Descripción: Elige una microfaceta según D(h)·(n·h) y refleja la vista en ella. Devuelve la
+dirección reflejada y su peso BRDF·cos/pdf = F·G·(v·h) / ((n·v)·(n·h)), o `None` si la
+dirección queda bajo la superficie. Con rugosidad baja se reduce a la reflexión espejo.
+*/
+fn sample_ggx_reflection(normal: &Vector3<f32>, view_dir: &Vector3<f32>, alpha: f32, f0: &Vector3<f32>, rng: &mut Rng) -> Option<(Vector3<f32>, Vector3<f32>)> {
+    let u = rng.next_f32();
+    let phi = std::f32::consts::TAU * rng.next_f32();
+    let cos_theta = ((1.0 - u) / (1.0 + (alpha * alpha - 1.0) * u)).sqrt();
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+
+    let (tangent, bitangent) = orthonormal_basis(normal);
+    let half = (tangent * (sin_theta * phi.cos()) + bitangent * (sin_theta * phi.sin()) + normal * cos_theta).normalize();
+    let v_dot_h = view_dir.dot(&half);
+    if v_dot_h <= 0.0 {
+        return None;
+    }
+
+    let dir = reflect(&-view_dir, &half).normalize();
+    let n_dot_l = normal.dot(&dir);
+    let n_dot_v = normal.dot(view_dir);
+    if n_dot_l <= 0.0 || n_dot_v <= 0.0 {
+        return None;
+    }
+
+    let g = smith_g1(n_dot_l, alpha) * smith_g1(n_dot_v, alpha);
+    Some((dir, fresnel_schlick(v_dot_h, f0) * (g * v_dot_h / (n_dot_v * cos_theta))))
+}
 
-    let f0_vec = Vector3::new(0.04, 0.04, 0.04).lerp(&material.specular_color, material.metallic);
 
-    let f0 = (f0_vec.x + f0_vec.y + f0_vec.z) / 3.0;
+/*
+Descripción: Interfaz entre dos medios transparentes de índices `n1` (el del rayo) y `n2`.
+Devuelve la reflectancia de Fresnel (Schlick con F0 = ((n1-n2)/(n1+n2))², evaluada con el
+ángulo del lado más denso) y la dirección refractada por la ley de Snell, o `None` si hay
+reflexión total interna. `normal` apunta hacia el lado del que viene el rayo.
+*/
+fn dielectric_split(dir: &Vector3<f32>, normal: &Vector3<f32>, n1: f32, n2: f32) -> (f32, Option<Vector3<f32>>) {
+    let eta = n1 / n2;
+    let cos_i = (-normal.dot(dir)).clamp(0.0, 1.0);
+    let sin2_t = eta * eta * (1.0 - cos_i * cos_i);
+    if sin2_t >= 1.0 {
+        return (1.0, None);
+    }
 
-    f0 + (1.0 - f0) * (1.0 - v_dot_n).powf(5.0)
+    let cos_t = (1.0 - sin2_t).sqrt();
+    let refracted = (dir * eta + normal * (eta * cos_i - cos_t)).normalize();
+    let r0 = ((n1 - n2) / (n1 + n2)).powi(2);
+    let cos = if n1 > n2 { cos_t } else { cos_i };
+    (r0 + (1.0 - r0) * (1.0 - cos).powi(5), Some(refracted))
 }
 
+/// UV de un punto local del voxel (en [0,1]³) sobre la cara de normal `normal`, ambos en el
+/// marco canónico. Depende de la posición en el voxel y no de la caja, así que las caras de
+/// una forma parcial muestran la parte de la textura que les corresponde, como en Minecraft.
+fn face_uv(local: &Vector3<f32>, normal: &Vector3<f32>) -> (f32, f32) {
+    let (u, v) = if normal.x.abs() > 0.5 {
+        (if normal.x > 0.0 { 1.0 - local.z } else { local.z }, 1.0 - local.y)
+    } else if normal.y.abs() > 0.5 {
+        (local.x, if normal.y > 0.0 { 1.0 - local.z } else { local.z })
+    } else {
+        (if normal.z < 0.0 { 1.0 - local.x } else { local.x }, 1.0 - local.y)
+    };
+    (u.clamp(0.0, 1.0), v.clamp(0.0, 1.0))
+}
 
 fn ray_aabb_intersect(orig: &Point3<f32>, dir: &Vector3<f32>, min: &Point3<f32>, max: &Point3<f32>) -> Option<(f32,f32)> {
 
@@ -398,485 +660,1006 @@ fn ray_aabb_intersect(orig: &Point3<f32>, dir: &Vector3<f32>, min: &Point3<f32>,
 /*
 Documentación (bloque):
 Sección: Helpers físicos y Fresnel
-Descripción: Funciones utilitarias usadas por el sombreado para calcular
-reflexiones y una aproximación de Fresnel (Schlick). Se mantienen simples
-pero son suficientes para el sombreado heurístico del trazador.
+Descripción: Funciones utilitarias usadas por el sombreado: reflexión, Fresnel de
+Schlick por canal y el modelo de microfacetas Cook-Torrance con distribución GGX y
+enmascaramiento de Smith, junto con su muestreo para reflexiones brillantes.
 */
 
+/// Generador pseudoaleatorio PCG de 32 bits. Se siembra de forma determinista (por
+/// ejemplo, con el punto de impacto) para que una imagen fija no parpadee entre frames.
+struct Rng(u32);
 
-pub struct VoxelWorld {
-    blocks: Vec<BlockType>,
-    width: usize,
-    height: usize,
-    depth: usize,
-}
+impl Rng {
+    fn new(seed: u32) -> Self {
+        let mut rng = Rng(seed);
+        rng.next_u32();
+        rng
+    }
 
-impl VoxelWorld {
-    fn new() -> Self {
-        VoxelWorld {
-            blocks: Vec::new(),
-            width: 0,
-            height: 0,
-            depth: 0,
-        }
+    fn from_point(p: &Point3<f32>) -> Self {
+        let seed = p.x.to_bits()
+            ^ p.y.to_bits().rotate_left(11)
+            ^ p.z.to_bits().rotate_left(22);
+        Rng::new(seed)
     }
 
-    /*
-    Documentación (bloque):
-    Sección: Carga del mundo desde archivos
-    Descripción: Lee los ficheros de la carpeta `capas/` y construye la malla voxel interna
-    (`blocks`) con dimensiones fijas esperadas. Si falta una capa, se sustituye por una
-    capa vacía para mantener la geometría.
-    */
-    fn load_from_files(&mut self) -> Result<(), Box<dyn std::error::Error>> {
-        let mut layers = Vec::new();
+    fn next_u32(&mut self) -> u32 {
+        self.0 = self.0.wrapping_mul(747_796_405).wrapping_add(2_891_336_453);
+        let word = ((self.0 >> ((self.0 >> 28) + 4)) ^ self.0).wrapping_mul(277_803_737);
+        (word >> 22) ^ word
+    }
 
+    /// Número uniforme en [0, 1).
+    fn next_f32(&mut self) -> f32 {
+        (self.next_u32() >> 8) as f32 / (1u32 << 24) as f32
+    }
 
-        for i in 0..12 {
-            let filename = format!("capas/{}.txt", i);
-            match fs::read_to_string(&filename) {
-                Ok(content) => {
-                    layers.push(content);
-                    println!("Cargado: {}", filename);
-                }
-                Err(_) => {
-                    println!("No se pudo cargar {}, usando capa vacía", filename);
-                    layers.push("               \n".repeat(19));
-                }
-            }
-        }
+    /// Índice uniforme en `0..n` (`n > 0`).
+    fn index(&mut self, n: usize) -> usize {
+        ((self.next_f32() * n as f32) as usize).min(n - 1)
+    }
 
-        self.load_from_layer_data(&layers);
-        Ok(())
+    /// Punto uniforme en el disco unidad.
+    fn disk(&mut self) -> (f32, f32) {
+        let r = self.next_f32().sqrt();
+        let theta = std::f32::consts::TAU * self.next_f32();
+        (r * theta.cos(), r * theta.sin())
     }
 
-    fn load_from_layer_data(&mut self, layer_data: &[String]) {
-        if layer_data.is_empty() {
-            println!("⚠️ No hay datos de capas para cargar");
-            return;
-        }
+    /// Dirección del hemisferio de `normal` con densidad proporcional al coseno.
+    fn cosine_hemisphere(&mut self, normal: &Vector3<f32>) -> Vector3<f32> {
+        let (x, y) = self.disk();
+        let z = (1.0 - x * x - y * y).max(0.0).sqrt();
+        let (tangent, bitangent) = orthonormal_basis(normal);
+        (tangent * x + bitangent * y + normal * z).normalize()
+    }
+}
 
-        self.width = 15;
-        self.depth = 19;
-        self.height = layer_data.len();
+/// Dos vectores unitarios perpendiculares entre sí y a `n` (que debe estar normalizado).
+fn orthonormal_basis(n: &Vector3<f32>) -> (Vector3<f32>, Vector3<f32>) {
+    let helper = if n.x.abs() > 0.9 { Vector3::new(0.0, 1.0, 0.0) } else { Vector3::new(1.0, 0.0, 0.0) };
+    let tangent = n.cross(&helper).normalize();
+    let bitangent = n.cross(&tangent);
+    (tangent, bitangent)
+}
 
-        self.blocks = vec![BlockType::Aire; self.width * self.height * self.depth];
+/*
+Documentación (bloque):
+Sección: Luz puntual y sombras
+Descripción: La luz principal de la escena: el sol o la luna según la hora (ver
+`Skybox::key_light`), colocada lejos del diorama en su dirección, con su color e
+intensidad en `color`. Con `radius == 0` se comporta como una luz
+puntual y proyecta sombras duras con un único rayo de sombra. Con un radio positivo se
+trata como una esfera: se lanzan `shadow_samples` rayos hacia puntos del disco que la luz
+presenta al punto sombreado y la fracción visible da una penumbra suave. Los bloques
+translúcidos dejan pasar los rayos de sombra atenuados por su absorción, así que el vidrio
+tintado proyecta sombras de color.
+*/
+pub struct Light {
+    pub position: Point3<f32>,
+    pub color: Vector3<f32>,
+    pub radius: f32,
+    pub shadow_samples: usize,
+}
 
-        let mut blocks_loaded = 0;
+/// Desplazamiento del origen de los rayos de sombra a lo largo de la normal.
+const SHADOW_BIAS: f32 = 1e-3;
+/// Distancia de la luz principal al borde del mundo, en la dirección del sol o la luna.
+const LIGHT_DISTANCE: f32 = 22.0;
+
+impl Light {
+    /// Coloca la luz según la hora del cielo, alrededor de `center`, el centro de un mundo cuya
+    /// semidiagonal mide `extent`.
+    fn follow(&mut self, sky: &Skybox, center: &Point3<f32>, extent: f32) {
+        let (direction, color) = sky.key_light();
+        self.position = center + direction * (extent + LIGHT_DISTANCE);
+        self.color = color;
+    }
 
-        for (y, layer_str) in layer_data.iter().enumerate() {
-            let lines: Vec<&str> = layer_str.lines().collect();
-            println!("Capa {}: {} líneas", y, lines.len());
-
-            for (z, line) in lines.iter().enumerate() {
-                if z < self.depth {
-                    let padded_line = format!("{:15}", line);
-                    for (x, ch) in padded_line.chars().take(self.width).enumerate() {
-                        let block_type = BlockType::from_char(ch);
-                        if block_type.is_solid() {
-                            blocks_loaded += 1;
-                        }
-                        let index = y * (self.width * self.depth) + z * self.width + x;
-                        self.blocks[index] = block_type;
-                    }
-                }
-            }
+    /// Fracción de la luz que llega a `point`, por canal (0 = en sombra, 1 = totalmente
+    /// iluminado), incluida la sombra de las nubes de `sky`.
+    fn visibility(&self, world: &VoxelWorld, sky: &Skybox, point: &Point3<f32>, normal: &Vector3<f32>) -> Vector3<f32> {
+        let origin = point + normal * SHADOW_BIAS;
+        // Las nubes están mucho más lejos que la luz, así que su sombra sigue la dirección
+        // del sol o la luna y no la de la posición de la luz.
+        let clouds = sky.cloud_transmittance(&origin, &sky.key_light().0);
+        if self.radius <= 0.0 || self.shadow_samples <= 1 {
+            return world.transmittance(&origin, &self.position) * clouds;
         }
 
-        println!("Mundo cargado: {}x{}x{}", self.width, self.height, self.depth);
-        println!("Total de bloques sólidos cargados: {}", blocks_loaded);
+        let to_light = (self.position - origin).normalize();
+
+        let (tangent, bitangent) = orthonormal_basis(&to_light);
+        let mut rng = Rng::from_point(point);
+        let total = (0..self.shadow_samples)
+            .map(|_| {
+                let (dx, dy) = rng.disk();
+                let target = self.position + (tangent * dx + bitangent * dy) * self.radius;
+                world.transmittance(&origin, &target)
+            })
+            .fold(Vector3::zeros(), |sum, t| sum + t);
+        total * (clouds / self.shadow_samples as f32)
+    }
+}
 
-        println!("Ejemplo de bloques en capa 0:");
-        for z in 0..3.min(self.depth) {
-            for x in 0..5.min(self.width) {
-                let block = self.get_block(x as i32, 0, z as i32);
-                if block.is_solid() {
-                    println!("  Bloque en ({}, 0, {}): {:?}", x, z, block);
-                }
-            }
+
+/*
+Documentación (bloque):
+Sección: Validación de capas
+Descripción: Diagnósticos con fichero, línea y columna (ambas desde 1) para los errores
+que la carga normal corrige en silencio: caracteres desconocidos (que se convertirían en
+aire), tabulaciones, líneas más largas que el resto de la capa y capas intermedias que
+faltan. El ancho esperado es el más frecuente entre todas las líneas no vacías. Los
+ficheros de estados (`n.estado.txt`) se revisan igual, con sus propios caracteres.
+*/
+struct LayerFile {
+    path: String,
+    content: Option<String>,
+    /// Fichero de estados `n.estado.txt` que acompaña a la capa, si existe.
+    states: Option<(String, String)>,
+}
+
+enum LayerProblem {
+    UnknownChar(char),
+    Tab,
+    LineTooLong { length: usize, expected: usize },
+    MissingLayer,
+}
+
+struct LayerDiagnostic {
+    path: String,
+    line: usize,
+    column: usize,
+    problem: LayerProblem,
+}
+
+impl std::fmt::Display for LayerDiagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.problem {
+            LayerProblem::MissingLayer => write!(f, "{}: falta la capa", self.path),
+            LayerProblem::UnknownChar(c) => write!(f, "{}:{}:{}: carácter desconocido {:?}", self.path, self.line, self.column, c),
+            LayerProblem::Tab => write!(f, "{}:{}:{}: tabulación (usa espacios para el aire)", self.path, self.line, self.column),
+            LayerProblem::LineTooLong { length, expected } => write!(
+                f, "{}:{}:{}: línea de {} caracteres, más larga que el ancho esperado ({})",
+                self.path, self.line, self.column, length, expected
+            ),
         }
     }
+}
 
-    #[inline]
-    fn get_block(&self, x: i32, y: i32, z: i32) -> BlockType {
-        if x < 0 || y < 0 || z < 0 ||
-           x >= self.width as i32 || y >= self.height as i32 || z >= self.depth as i32 {
-            return BlockType::Aire;
+fn validate_layers(files: &[LayerFile]) -> Vec<LayerDiagnostic> {
+    let mut width_counts = std::collections::HashMap::new();
+    for content in files.iter().filter_map(|f| f.content.as_ref()) {
+        for line in content.lines().filter(|line| !line.is_empty()) {
+            *width_counts.entry(line.chars().count()).or_insert(0usize) += 1;
         }
-        let index = y as usize * (self.width * self.depth) + z as usize * self.width + x as usize;
-        self.blocks[index]
     }
+    let expected_width = width_counts.into_iter()
+        .max_by_key(|&(width, count)| (count, width))
+        .map_or(0, |(width, _)| width);
+
+    let mut diagnostics = Vec::new();
+    for file in files {
+        let Some(content) = &file.content else {
+            diagnostics.push(LayerDiagnostic { path: file.path.clone(), line: 0, column: 0, problem: LayerProblem::MissingLayer });
+            continue;
+        };
 
-    fn check_special_collision(&self, x: i32, y: i32, z: i32, point: &Point3<f32>) -> bool {
-        let block = self.get_block(x, y, z);
-        let mut local_x = point.x - x as f32;
-        let mut local_y = point.y - y as f32;
-        let mut local_z = point.z - z as f32;
-
-        let eps = 1e-3;
-        local_x = local_x.clamp(0.0 - eps, 1.0 + eps);
-        local_y = local_y.clamp(0.0 - eps, 1.0 + eps);
-        local_z = local_z.clamp(0.0 - eps, 1.0 + eps);
-
-        match block {
-            BlockType::SlabPiedra => local_y <= 0.5 + eps,
-            BlockType::EscaleraPiedra => {
-                if local_y <= 0.5 + eps {
-                    true
-                } else if local_y <= 1.0 + eps && local_z >= 0.5 - eps {
-                    true
-                } else {
-                    false
-                }
-            },
-            _ => {
-                (local_x >= 0.0 - eps) && (local_x <= 1.0 + eps) &&
-                (local_y >= 0.0 - eps) && (local_y <= 1.0 + eps) &&
-                (local_z >= 0.0 - eps) && (local_z <= 1.0 + eps)
-            }
+        validate_grid(&file.path, content, expected_width, |c| BlockType::parse_char(c).is_some(), &mut diagnostics);
+        if let Some((path, states)) = &file.states {
+            validate_grid(path, states, expected_width, |c| BlockState::from_char(c).is_some(), &mut diagnostics);
         }
     }
+    diagnostics
+}
 
-    /*
-    Documentación (bloque):
-    Sección: Mapeo UV
-    Descripción: Calcula las coordenadas UV para el punto de impacto según la cara hitada y
-    maneja casos especiales para slabs y escaleras, devolviendo un par (u,v) en [0,1].
-    */
+/// Revisa una rejilla de caracteres (capa o fichero de estados) línea a línea.
+fn validate_grid(path: &str, content: &str, expected_width: usize, known: impl Fn(char) -> bool, diagnostics: &mut Vec<LayerDiagnostic>) {
+    for (line_no, line) in content.lines().enumerate() {
+        let mut length = 0;
+        for (col, ch) in line.chars().enumerate() {
+            length += 1;
+            let problem = match ch {
+                '\t' => LayerProblem::Tab,
+                c if !known(c) => LayerProblem::UnknownChar(c),
+                _ => continue,
+            };
+            diagnostics.push(LayerDiagnostic { path: path.to_string(), line: line_no + 1, column: col + 1, problem });
+        }
+        if length > expected_width {
+            diagnostics.push(LayerDiagnostic {
+                path: path.to_string(),
+                line: line_no + 1,
+                column: expected_width + 1,
+                problem: LayerProblem::LineTooLong { length, expected: expected_width },
+            });
+        }
+    }
+}
 
-    fn calculate_uv(&self, hit_point: &Point3<f32>, normal: &Vector3<f32>, block_x: i32, block_y: i32, block_z: i32) -> (f32, f32) {
-        let block_type = self.get_block(block_x, block_y, block_z);
-        let local_x = hit_point.x - block_x as f32;
-        let local_y = hit_point.y - block_y as f32;
-        let local_z = hit_point.z - block_z as f32;
+/// Punto cardinal hacia el que mira el frente de un bloque orientable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Facing {
+    North,
+    East,
+    South,
+    West,
+}
 
-        let map_face_x = |nx: f32| {
+impl Facing {
+    /// En el orden de los bits de `BlockState`: cada paso es un giro de 90° hacia el este.
+    const ALL: [Facing; 4] = [Facing::North, Facing::East, Facing::South, Facing::West];
+
+    /// Nombre de la propiedad `facing` de Minecraft.
+    fn name(self) -> &'static str {
+        match self {
+            Facing::North => "north",
+            Facing::East => "east",
+            Facing::South => "south",
+            Facing::West => "west",
+        }
+    }
 
-            let u = if nx > 0.0 { 1.0 - local_z } else { local_z };
-            let v = (1.0 - local_y).clamp(0.0, 1.0);
-            (u.clamp(0.0, 1.0), v)
-        };
+    fn parse(name: &str) -> Option<Self> {
+        Facing::ALL.into_iter().find(|facing| facing.name() == name)
+    }
 
-        let map_face_y = |ny: f32| {
+    fn opposite(self) -> Self {
+        Facing::ALL[(self as usize + 2) % 4]
+    }
+}
 
-            let u = local_x.clamp(0.0, 1.0);
-            let v = if ny > 0.0 { 1.0 - local_z } else { local_z };
-            (u, v.clamp(0.0, 1.0))
-        };
+/*
+Documentación (bloque):
+Sección: Estado de orientación por voxel
+Descripción: Complementa al `BlockType` de cada voxel con su orientación: hacia dónde mira
+el frente (el escalón bajo de una escalera, la cerradura de un cofre), si está invertido
+(escalera boca abajo, slab en la mitad superior) y si es un slab doble. El estado por
+defecto es el de siempre: frente hacia -z (norte) y mitad inferior. La geometría de cada
+forma se define solo en ese marco canónico; `to_canonical` lleva a él los puntos y
+normales de un voxel girado y `to_world` los devuelve al mundo.
+
+En las capas de texto el estado va en un fichero paralelo opcional, `n.estado.txt`, con la
+misma rejilla que `n.txt`: `n`, `e`, `s`, `o` giran el frente al norte, este, sur u oeste;
+en mayúscula además lo invierten; `d` marca un slab doble, y el espacio o `.` dejan el
+estado por defecto.
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct BlockState(u8);
 
-        let map_face_z = |nz: f32| {
+impl BlockState {
+    const FACING_MASK: u8 = 0b0011;
+    const TOP: u8 = 0b0100;
+    const DOUBLE: u8 = 0b1000;
 
-            let u = if nz < 0.0 { 1.0 - local_x } else { local_x };
-            let v = (1.0 - local_y).clamp(0.0, 1.0);
-            (u.clamp(0.0, 1.0), v)
-        };
+    fn new(facing: Facing, top: bool) -> Self {
+        BlockState(facing as u8 | if top { Self::TOP } else { 0 })
+    }
 
-        match block_type {
-            BlockType::SlabPiedra => {
+    const fn double() -> Self {
+        BlockState(Self::DOUBLE)
+    }
 
-                if normal.y.abs() > 0.5 {
+    fn facing(self) -> Facing {
+        Facing::ALL[(self.0 & Self::FACING_MASK) as usize]
+    }
 
-                    map_face_y(normal.y)
-                } else if normal.x.abs() > 0.5 {
+    fn is_top(self) -> bool {
+        self.0 & Self::TOP != 0
+    }
 
-                    let u = if normal.x > 0.0 { 1.0 - local_z } else { local_z };
-                    let v = (1.0 - (local_y / 0.5)).clamp(0.0, 1.0);
-                    (u.clamp(0.0, 1.0), v)
-                } else {
+    fn is_double(self) -> bool {
+        self.0 & Self::DOUBLE != 0
+    }
 
-                    let u = if normal.z < 0.0 { 1.0 - local_x } else { local_x };
-                    let v = (1.0 - (local_y / 0.5)).clamp(0.0, 1.0);
-                    (u.clamp(0.0, 1.0), v)
-                }
-            }
-            BlockType::EscaleraPiedra => {
+    fn from_char(c: char) -> Option<Self> {
+        match c {
+            ' ' | '.' => return Some(BlockState::default()),
+            'd' => return Some(BlockState::double()),
+            _ => {}
+        }
+        let facing = match c.to_ascii_lowercase() {
+            'n' => Facing::North,
+            'e' => Facing::East,
+            's' => Facing::South,
+            'o' => Facing::West,
+            _ => return None,
+        };
+        Some(BlockState::new(facing, c.is_ascii_uppercase()))
+    }
 
-                if normal.y > 0.5 {
+    /// Gira (e invierte, si procede) un vector del mundo al marco canónico del bloque.
+    fn to_canonical(self, v: &Vector3<f32>) -> Vector3<f32> {
+        let (x, z) = match self.facing() {
+            Facing::North => (v.x, v.z),
+            Facing::East => (v.z, -v.x),
+            Facing::South => (-v.x, -v.z),
+            Facing::West => (-v.z, v.x),
+        };
+        Vector3::new(x, if self.is_top() { -v.y } else { v.y }, z)
+    }
 
-                    if local_z >= 0.5 {
+    /// Inversa de `to_canonical`: lleva un vector del marco canónico al mundo.
+    fn to_world(self, v: &Vector3<f32>) -> Vector3<f32> {
+        let (x, z) = match self.facing() {
+            Facing::North => (v.x, v.z),
+            Facing::East => (-v.z, v.x),
+            Facing::South => (-v.x, -v.z),
+            Facing::West => (v.z, -v.x),
+        };
+        Vector3::new(x, if self.is_top() { -v.y } else { v.y }, z)
+    }
 
-                        let u = local_x.clamp(0.0, 1.0);
-                        let v = ((local_z - 0.5) / 0.5).clamp(0.0, 1.0);
-                        (u, v)
-                    } else {
+    /// Como `to_canonical`, para un punto relativo a la esquina del voxel (en [0,1]³).
+    fn to_canonical_local(self, local: &Vector3<f32>) -> Vector3<f32> {
+        let center = Vector3::new(0.5, 0.5, 0.5);
+        center + self.to_canonical(&(local - center))
+    }
+}
 
-                        let u = local_x.clamp(0.0, 1.0);
-                        let v = (local_z / 0.5).clamp(0.0, 1.0);
-                        (u, v)
-                    }
-                } else if normal.x.abs() > 0.5 {
+/// Resultado de un impacto: distancia `t`, normal de la cara, bloque, UV y cara de textura.
+type RayHit = (f32, Vector3<f32>, BlockType, (f32, f32), BlockFace);
 
-                    if local_z >= 0.5 && local_y >= 0.5 {
-                        let u = ((local_z - 0.5) / 0.5).clamp(0.0, 1.0);
-                        let v = (1.0 - ((local_y - 0.5) / 0.5)).clamp(0.0, 1.0);
+/// Atenuación de la luz de bloque por cada voxel que avanza.
+const BLOCK_LIGHT_FALLOFF: f32 = 0.78;
+/// Por debajo de esta intensidad la luz de bloque deja de propagarse.
+const BLOCK_LIGHT_CUTOFF: f32 = 0.02;
 
-                        let u = if normal.x > 0.0 { 1.0 - u } else { u };
-                        (u, v)
-                    } else {
+pub struct VoxelWorld {
+    /// Bloque y orientación de cada voxel, en chunks de 16³ que solo existen si tienen bloques.
+    voxels: ChunkStore,
+    width: usize,
+    height: usize,
+    depth: usize,
+    /// Luz de bloque RGB por voxel, calculada por `bake_block_light`. Vacía hasta entonces.
+    block_light: ChunkGrid<Vector3<f32>>,
+    /// Posiciones de los bloques emisivos, para muestrear luz directa en el path tracer.
+    emitters: Vec<(i32, i32, i32)>,
+    /// Qué voxels no son aire, para que el DDA salte las zonas vacías.
+    occupancy: Occupancy,
+}
 
-                        let u = if normal.x > 0.0 { 1.0 - local_z } else { local_z };
-                        let v = (1.0 - (local_y / 0.5)).clamp(0.0, 1.0);
-                        (u, v)
-                    }
-                } else {
+impl VoxelWorld {
+    fn new() -> Self {
+        VoxelWorld {
+            voxels: ChunkStore::new(0, 0, 0),
+            occupancy: Occupancy::new(0, 0, 0),
+            width: 0,
+            height: 0,
+            depth: 0,
+            block_light: ChunkGrid::new(0, 0, 0),
+            emitters: Vec::new(),
+        }
+    }
+
+    fn with_dimensions(width: usize, height: usize, depth: usize) -> Self {
+        VoxelWorld {
+            voxels: ChunkStore::new(width, height, depth),
+            occupancy: Occupancy::new(width, height, depth),
+            width,
+            height,
+            depth,
+            block_light: ChunkGrid::new(width, height, depth),
+            emitters: Vec::new(),
+        }
+    }
 
-                    if local_z >= 0.5 && local_y >= 0.5 {
+    /*
+    Documentación (bloque):
+    Sección: Carga del mundo según las opciones
+    Descripción: Si se pidió `--import`, elige el importador por la extensión del fichero;
+    si no, lee las capas de texto de la carpeta configurada.
+    */
+    fn load(options: &CliOptions) -> Result<Self, Box<dyn std::error::Error>> {
+        let Some(path) = &options.import_path else {
+            let mut world = VoxelWorld::new();
+            world.load_from_files(&options.world_dir, options.strict)?;
+            return Ok(world);
+        };
 
-                        let u = local_x.clamp(0.0, 1.0);
-                        let v = (1.0 - ((local_y - 0.5) / 0.5)).clamp(0.0, 1.0);
-                        let u = if normal.z < 0.0 { 1.0 - u } else { u };
-                        (u, v)
-                    } else {
+        match file_extension(path).as_str() {
+            "vox" => {
+                let mapping = match &options.vox_mapping {
+                    Some(table) => Some(vox::VoxMapping::from_file(table)?),
+                    None => None,
+                };
+                vox::load_vox(path, mapping.as_ref())
+            }
+            "schem" => schematic::load_schem(path),
+            "nbt" => schematic::load_structure(path),
+            other => Err(format!("formato de importación no soportado: .{}", other).into()),
+        }
+    }
 
-                        let u = if normal.z < 0.0 { 1.0 - local_x } else { local_x };
-                        let v = (1.0 - local_y).clamp(0.0, 1.0);
-                        (u, v)
-                    }
+    fn save(&self, path: &str) -> Result<(), Box<dyn std::error::Error>> {
+        match file_extension(path).as_str() {
+            "vox" => vox::save_vox(self, path),
+            "schem" => schematic::save_schem(self, path),
+            "nbt" => schematic::save_structure(self, path),
+            other => Err(format!("formato de exportación no soportado: .{}", other).into()),
+        }
+    }
+
+    /*
+    Documentación (bloque):
+    Sección: Carga del mundo desde archivos
+    Descripción: Descubre los ficheros numerados (`0.txt`, `1.txt`, ...) de la carpeta de
+    capas y construye la malla voxel interna (`voxels`). La altura es el mayor índice
+    encontrado más uno; si falta alguna capa intermedia se avisa y se sustituye por una
+    capa vacía para mantener la geometría.
+    */
+    fn load_from_files(&mut self, world_dir: &str, strict: bool) -> Result<(), Box<dyn std::error::Error>> {
+        let files = Self::read_layer_files(world_dir)?;
+
+        let diagnostics = validate_layers(&files);
+        if !diagnostics.is_empty() {
+            if strict {
+                for diagnostic in &diagnostics {
+                    eprintln!("✗ {}", diagnostic);
                 }
+                return Err(format!("{} errores en las capas de '{}' (--strict)", diagnostics.len(), world_dir).into());
             }
-            _ => {
+            println!("⚠ {} problemas en las capas de '{}'; usa --validate para ver el detalle", diagnostics.len(), world_dir);
+        }
 
-                if normal.x.abs() > 0.5 {
-                    map_face_x(normal.x)
-                } else if normal.y.abs() > 0.5 {
-                    map_face_y(normal.y)
-                } else {
-                    map_face_z(normal.z)
+        let mut layers = Vec::with_capacity(files.len());
+        let mut states = Vec::with_capacity(files.len());
+        for file in files {
+            states.push(file.states.map(|(path, content)| {
+                println!("Cargado: {}", path);
+                content
+            }));
+            match file.content {
+                Some(content) => {
+                    println!("Cargado: {}", file.path);
+                    layers.push(content);
+                }
+                None => {
+                    println!("⚠ Falta {}, usando capa vacía", file.path);
+                    layers.push(String::new());
                 }
             }
         }
+
+        self.load_from_layer_data(&layers, &states);
+        Ok(())
     }
 
     /*
-    Documentación (bloque):
-    Sección: Raycasting DDA (principal)
-    Descripción: Implementa un DDA robusto para recorrer voxels. Maneja ties (esquinas),
-    intenta varios puntos candidatos para evitar fallas con geometrías delgadas y devuelve
-    distancia t, normal de la cara, tipo de bloque y coordenadas UV si hay impacto.
+    Descripción: Lee todas las capas desde `0.txt` hasta el mayor índice encontrado. Las
+    capas intermedias que no existen quedan con `content: None`. Junto a cada capa se lee
+    su fichero de estados, si lo hay.
     */
-    fn raycast(&self, origin: &Point3<f32>, direction: &Vector3<f32>) -> Option<(f32, Vector3<f32>, BlockType, (f32, f32))> {
-        let mut origin = *origin;
-        let dir = *direction;
+    fn read_layer_files(world_dir: &str) -> Result<Vec<LayerFile>, Box<dyn std::error::Error>> {
+        let layer_indices = Self::discover_layers(world_dir)?;
+        let max_index = match layer_indices.last() {
+            Some(&max_index) => max_index,
+            None => return Err(format!("no se encontró ninguna capa en '{}'", world_dir).into()),
+        };
 
-        let ox = origin.x.floor() as i32;
-        let oy = origin.y.floor() as i32;
-        let oz = origin.z.floor() as i32;
-        if self.get_block(ox, oy, oz).is_solid() {
-            origin = origin + dir * 0.01;
+        let mut files = Vec::with_capacity(max_index + 1);
+        for i in 0..=max_index {
+            let path = std::path::Path::new(world_dir).join(format!("{}.txt", i)).display().to_string();
+            let content = if layer_indices.binary_search(&i).is_ok() {
+                Some(fs::read_to_string(&path).map_err(|e| format!("no se pudo cargar {}: {}", path, e))?)
+            } else {
+                None
+            };
+            let state_path = std::path::Path::new(world_dir).join(format!("{}.estado.txt", i)).display().to_string();
+            let states = match fs::read_to_string(&state_path) {
+                Ok(states) => Some((state_path, states)),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+                Err(e) => return Err(format!("no se pudo cargar {}: {}", state_path, e).into()),
+            };
+            files.push(LayerFile { path, content, states });
         }
+        Ok(files)
+    }
+
+    /// Valida las capas de la carpeta sin construir el mundo (modo `--validate`).
+    fn validate_dir(world_dir: &str) -> Result<Vec<LayerDiagnostic>, Box<dyn std::error::Error>> {
+        Ok(validate_layers(&Self::read_layer_files(world_dir)?))
+    }
+
+    /// Devuelve, ordenados, los índices `n` de todos los ficheros `n.txt` de la carpeta.
+    fn discover_layers(world_dir: &str) -> Result<Vec<usize>, Box<dyn std::error::Error>> {
+        let entries = fs::read_dir(world_dir)
+            .map_err(|e| format!("no se pudo leer la carpeta '{}': {}", world_dir, e))?;
+
+        let mut indices: Vec<usize> = entries
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| {
+                let path = entry.path();
+                if path.extension()? != "txt" {
+                    return None;
+                }
+                path.file_stem()?.to_str()?.parse().ok()
+            })
+            .collect();
+        indices.sort_unstable();
+        indices.dedup();
+        Ok(indices)
+    }
 
-    let center_x = self.width as f32 * 0.5;
-    let center_y = self.height as f32 * 0.5;
-    let center_z = self.depth as f32 * 0.5;
-    let world_center = Vector3::new(center_x, center_y, center_z);
-    let world_diag = ((self.width as f32).powi(2) + (self.height as f32).powi(2) + (self.depth as f32).powi(2)).sqrt();
-    let max_distance = (world_center - Vector3::new(origin.x, origin.y, origin.z)).magnitude() + world_diag * 1.5;
+    /*
+    Documentación (bloque):
+    Sección: Construcción de la malla a partir de capas
+    Descripción: Infiere el ancho a partir de la línea más larga y la profundidad a partir
+    de la capa con más líneas (sin contar líneas vacías al final). Las capas más pequeñas se rellenan con aire, y se avisa
+    cuando sus dimensiones no coinciden con las del mundo. `state_data` trae, por capa, el
+    contenido de su fichero de estados; los voxels sin estado quedan con el de por defecto.
+    */
+    fn load_from_layer_data(&mut self, layer_data: &[String], state_data: &[Option<String>]) {
+        if layer_data.is_empty() {
+            println!("⚠️ No hay datos de capas para cargar");
+            return;
+        }
 
-        let mut x = origin.x.floor() as i32;
-        let mut y = origin.y.floor() as i32;
-        let mut z = origin.z.floor() as i32;
+        let layer_sizes: Vec<(usize, usize)> = layer_data.iter().map(|layer| {
+            let width = layer.lines().map(|line| line.chars().count()).max().unwrap_or(0);
+            let depth = layer.lines().enumerate().filter(|(_, line)| !line.is_empty()).last().map_or(0, |(z, _)| z + 1);
+            (width, depth)
+        }).collect();
 
-        let step_x = if dir.x >= 0.0 { 1 } else { -1 };
-        let step_y = if dir.y >= 0.0 { 1 } else { -1 };
-        let step_z = if dir.z >= 0.0 { 1 } else { -1 };
+        self.width = layer_sizes.iter().map(|&(w, _)| w).max().unwrap_or(0);
+        self.depth = layer_sizes.iter().map(|&(_, d)| d).max().unwrap_or(0);
+        self.height = layer_data.len();
 
-        let tx_delta = if dir.x.abs() < 1e-6 { f32::INFINITY } else { 1.0 / dir.x.abs() };
-        let ty_delta = if dir.y.abs() < 1e-6 { f32::INFINITY } else { 1.0 / dir.y.abs() };
-        let tz_delta = if dir.z.abs() < 1e-6 { f32::INFINITY } else { 1.0 / dir.z.abs() };
+        for (y, &(w, d)) in layer_sizes.iter().enumerate() {
+            if (w, d) != (self.width, self.depth) {
+                println!("⚠ Capa {}: {}x{} no coincide con el mundo ({}x{}), se rellena con aire",
+                         y, w, d, self.width, self.depth);
+            }
+        }
 
-        let mut tx = if dir.x > 0.0 {
-            (x as f32 + 1.0 - origin.x) / dir.x
-        } else if dir.x < 0.0 {
-            (origin.x - x as f32) / -dir.x
-        } else { f32::INFINITY };
+        self.voxels = ChunkStore::new(self.width, self.height, self.depth);
+        self.occupancy = Occupancy::new(self.width, self.height, self.depth);
+        self.block_light = ChunkGrid::new(self.width, self.height, self.depth);
 
-        let mut ty = if dir.y > 0.0 {
-            (y as f32 + 1.0 - origin.y) / dir.y
-        } else if dir.y < 0.0 {
-            (origin.y - y as f32) / -dir.y
-        } else { f32::INFINITY };
+        let mut blocks_loaded = 0;
 
-        let mut tz = if dir.z > 0.0 {
-            (z as f32 + 1.0 - origin.z) / dir.z
-        } else if dir.z < 0.0 {
-            (origin.z - z as f32) / -dir.z
-        } else { f32::INFINITY };
+        for (y, layer_str) in layer_data.iter().enumerate() {
+            for (z, line) in layer_str.lines().enumerate() {
+                for (x, ch) in line.chars().enumerate() {
+                    let block_type = BlockType::from_char(ch);
+                    if block_type.is_solid() {
+                        blocks_loaded += 1;
+                    }
+                    self.set_block(x as i32, y as i32, z as i32, block_type);
+                }
+            }
+        }
 
+        for (y, states) in state_data.iter().enumerate().take(self.height) {
+            let Some(states) = states else { continue };
+            for (z, line) in states.lines().enumerate().take(self.depth) {
+                for (x, ch) in line.chars().enumerate().take(self.width) {
+                    self.set_state(x as i32, y as i32, z as i32, BlockState::from_char(ch).unwrap_or_default());
+                }
+            }
+        }
 
+        println!("Mundo cargado: {}x{}x{}", self.width, self.height, self.depth);
+        println!("Total de bloques sólidos cargados: {}", blocks_loaded);
+        let (chunks, bytes) = self.voxels.usage();
+        println!("Chunks reservados: {} ({:.1} KiB)", chunks, bytes as f32 / 1024.0);
 
-        loop {
+        println!("Ejemplo de bloques en capa 0:");
+        for z in 0..3.min(self.depth) {
+            for x in 0..5.min(self.width) {
+                let block = self.get_block(x as i32, 0, z as i32);
+                if block.is_solid() {
+                    println!("  Bloque en ({}, 0, {}): {:?}", x, z, block);
+                }
+            }
+        }
+    }
 
-            let min_t = tx.min(ty).min(tz);
+    fn set_block(&mut self, x: i32, y: i32, z: i32, block: BlockType) {
+        if x < 0 || y < 0 || z < 0 ||
+           x >= self.width as i32 || y >= self.height as i32 || z >= self.depth as i32 {
+            return;
+        }
+        self.occupancy.set(x, y, z, block != BlockType::AIRE);
+        let (x, y, z) = (x as usize, y as usize, z as usize);
+        let state = self.voxels.get(x, y, z).1;
+        self.voxels.set(x, y, z, (block, state));
+    }
 
-            if !min_t.is_finite() || min_t > max_distance { break; }
+    fn set_state(&mut self, x: i32, y: i32, z: i32, state: BlockState) {
+        if x < 0 || y < 0 || z < 0 ||
+           x >= self.width as i32 || y >= self.height as i32 || z >= self.depth as i32 {
+            return;
+        }
+        let (x, y, z) = (x as usize, y as usize, z as usize);
+        let block = self.voxels.get(x, y, z).0;
+        self.voxels.set(x, y, z, (block, state));
+    }
 
+    #[inline]
+    fn get_state(&self, x: i32, y: i32, z: i32) -> BlockState {
+        if x < 0 || y < 0 || z < 0 ||
+           x >= self.width as i32 || y >= self.height as i32 || z >= self.depth as i32 {
+            return BlockState::default();
+        }
+        self.voxels.get(x as usize, y as usize, z as usize).1
+    }
 
+    /// Cara de textura de un impacto en `(x, y, z)` con normal `normal`, según la orientación del voxel.
+    fn texture_face(&self, x: i32, y: i32, z: i32, normal: &Vector3<f32>) -> BlockFace {
+        BlockFace::from_normal(&self.get_state(x, y, z).to_canonical(normal))
+    }
 
-            let eps = (1e-5_f32).max(min_t * 1e-6);
-            let mut stepped_x = false;
-            let mut stepped_y = false;
-            let mut stepped_z = false;
+    #[inline]
+    fn get_block(&self, x: i32, y: i32, z: i32) -> BlockType {
+        if x < 0 || y < 0 || z < 0 ||
+           x >= self.width as i32 || y >= self.height as i32 || z >= self.depth as i32 {
+            return BlockType::AIRE;
+        }
+        self.voxels.get(x as usize, y as usize, z as usize).0
+    }
 
-            if (tx - min_t).abs() <= eps {
-                x += step_x;
-                tx += tx_delta;
-                stepped_x = true;
-            }
-            if (ty - min_t).abs() <= eps {
-                y += step_y;
-                ty += ty_delta;
-                stepped_y = true;
-            }
-            if (tz - min_t).abs() <= eps {
-                z += step_z;
-                tz += tz_delta;
-                stepped_z = true;
+    /*
+    Documentación (bloque):
+    Sección: Luz de bloque
+    Descripción: Propaga la luz de los bloques emisivos por inundación, como los niveles de
+    luz de Minecraft: cada emisor parte con su color del registro y en cada paso hacia un
+    vecino la luz se multiplica por `BLOCK_LIGHT_FALLOFF`. Los cubos opacos no la dejan
+    pasar, así que rodea las paredes en lugar de atravesarlas; los translúcidos la tiñen con
+    su absorción. Cada canal guarda el máximo
+    de todos los emisores que llegan al voxel. Se calcula una vez tras cargar el mundo; al
+    sombrear basta con consultar `block_light_at`.
+    */
+    fn bake_block_light(&mut self) {
+        let mut light = ChunkGrid::new(self.width, self.height, self.depth);
+        let mut queue = std::collections::VecDeque::new();
+
+        for ((x, y, z), (block, _)) in self.voxels.occupied() {
+            if block.emits_light() {
+                light.set(x, y, z, block.light_color());
+                queue.push_back((x, y, z));
             }
+        }
+        self.emitters = queue.iter().map(|&(x, y, z)| (x as i32, y as i32, z as i32)).collect();
 
+        const NEIGHBORS: [(i32, i32, i32); 6] = [(1, 0, 0), (-1, 0, 0), (0, 1, 0), (0, -1, 0), (0, 0, 1), (0, 0, -1)];
+        while let Some((x, y, z)) = queue.pop_front() {
+            let spread: Vector3<f32> = light.get(x, y, z) * BLOCK_LIGHT_FALLOFF;
+            if spread.max() < BLOCK_LIGHT_CUTOFF {
+                continue;
+            }
 
-            let hit_point = origin + dir * min_t;
-            let block_center = Point3::new(x as f32 + 0.5, y as f32 + 0.5, z as f32 + 0.5);
-            let to_center = Vector3::new(block_center.x - hit_point.x, block_center.y - hit_point.y, block_center.z - hit_point.z);
-            let inward_eps = 1e-4_f32 + min_t * 1e-6;
+            for (dx, dy, dz) in NEIGHBORS {
+                let (nx, ny, nz) = (x as i32 + dx, y as i32 + dy, z as i32 + dz);
+                if nx < 0 || ny < 0 || nz < 0 || nx >= self.width as i32 || ny >= self.height as i32 || nz >= self.depth as i32 {
+                    continue;
+                }
+                let neighbor = self.get_block(nx, ny, nz);
+                let (nx, ny, nz) = (nx as usize, ny as usize, nz as usize);
+                if neighbor.is_opaque() {
+                    continue;
+                }
+                let spread = if neighbor.is_translucent() { spread.component_mul(&neighbor.transmittance(1.0)) } else { spread };
+                let current = light.get(nx, ny, nz);
+                if spread.x > current.x || spread.y > current.y || spread.z > current.z {
+                    light.set(nx, ny, nz, current.sup(&spread));
+                    queue.push_back((nx, ny, nz));
+                }
+            }
+        }
 
+        self.block_light = light;
+        println!("Luz de bloque calculada: {} emisores", self.emitters.len());
+    }
 
-            let mut face_dir = Vector3::new(0.0, 0.0, 0.0);
-            if stepped_x { face_dir.x = -dir.x.signum(); }
-            if stepped_y { face_dir.y = -dir.y.signum(); }
-            if stepped_z { face_dir.z = -dir.z.signum(); }
-            if face_dir.magnitude() > 0.0 { face_dir = face_dir.normalize(); }
+    /// Luz de bloque en `point`, interpolada entre los centros de los voxels vecinos que no
+    /// son opacos (iluminación suave). Fuera del mundo la luz de bloque es cero.
+    fn block_light_at(&self, point: &Point3<f32>) -> Vector3<f32> {
+        if self.emitters.is_empty() {
+            return Vector3::zeros();
+        }
 
+        let p = point - Vector3::new(0.5, 0.5, 0.5);
+        let (bx, by, bz) = (p.x.floor(), p.y.floor(), p.z.floor());
+        let (fx, fy, fz) = (p.x - bx, p.y - by, p.z - bz);
 
-            let mut hit_point_in = hit_point - dir * inward_eps;
-            let cand_face = if face_dir.magnitude() > 0.0 { Some(hit_point + face_dir * inward_eps) } else { None };
-            let cand_dir = Some(hit_point - dir * inward_eps);
-            let cand_center = if to_center.magnitude() > 1e-6 { Some(hit_point + to_center.normalize() * inward_eps) } else { None };
+        let mut sum = Vector3::zeros();
+        let mut total_weight = 0.0;
+        for corner in 0..8 {
+            let (ox, oy, oz) = (corner & 1, (corner >> 1) & 1, (corner >> 2) & 1);
+            let (x, y, z) = (bx as i32 + ox, by as i32 + oy, bz as i32 + oz);
+            let block = self.get_block(x, y, z);
+            if block.is_opaque() && !block.emits_light() {
+                continue;
+            }
 
+            let weight = (if ox == 1 { fx } else { 1.0 - fx })
+                * (if oy == 1 { fy } else { 1.0 - fy })
+                * (if oz == 1 { fz } else { 1.0 - fz });
+            total_weight += weight;
+            if x >= 0 && y >= 0 && z >= 0 && x < self.width as i32 && y < self.height as i32 && z < self.depth as i32 {
+                sum += self.block_light.get(x as usize, y as usize, z as usize) * weight;
+            }
+        }
 
-            if let Some(cf) = cand_face {
-                if self.check_special_collision(x, y, z, &cf) { hit_point_in = cf; }
-                else if let Some(cd) = cand_dir { if self.check_special_collision(x, y, z, &cd) { hit_point_in = cd; } else if let Some(cc) = cand_center { if self.check_special_collision(x, y, z, &cc) { hit_point_in = cc; } } }
-            } else if let Some(cd) = cand_dir {
-                if self.check_special_collision(x, y, z, &cd) { hit_point_in = cd; }
-                else if let Some(cc) = cand_center { if self.check_special_collision(x, y, z, &cc) { hit_point_in = cc; } }
-            } else if let Some(cc) = cand_center { if self.check_special_collision(x, y, z, &cc) { hit_point_in = cc; } }
+        if total_weight > 1e-6 { sum / total_weight } else { Vector3::zeros() }
+    }
 
+    /*
+    Descripción: Estimación de la irradiancia directa que llega a `point` desde los bloques
+    emisivos (next-event estimation). Elige un emisor y una de sus caras al azar, toma un
+    punto uniforme en ella y lanza un rayo de sombra, que atraviesa los bloques translúcidos
+    (lava bajo un suelo de vidrio); la contribución se divide por la probabilidad de haber
+    elegido ese punto.
+    */
+    fn sample_emitter_irradiance(&self, point: &Point3<f32>, normal: &Vector3<f32>, texture_manager: &TextureManager, rng: &mut Rng) -> Vector3<f32> {
+        if self.emitters.is_empty() {
+            return Vector3::zeros();
+        }
 
-            if x < 0 || y < 0 || z < 0 || x >= self.width as i32 || y >= self.height as i32 || z >= self.depth as i32 {
-                break;
-            }
+        let (ex, ey, ez) = self.emitters[rng.index(self.emitters.len())];
+        let face = rng.index(6);
+        let axis = face / 2;
+        let mut face_normal = Vector3::zeros();
+        face_normal[axis] = if face.is_multiple_of(2) { 1.0 } else { -1.0 };
 
-            let block = self.get_block(x, y, z);
-            if block.is_solid() && self.check_special_collision(x, y, z, &hit_point_in) {
+        let mut target = Point3::new(ex as f32 + 0.5, ey as f32 + 0.5, ez as f32 + 0.5) + face_normal * 0.5;
+        target[(axis + 1) % 3] += rng.next_f32() - 0.5;
+        target[(axis + 2) % 3] += rng.next_f32() - 0.5;
 
+        let to_light = target - point;
+        let dist2 = to_light.magnitude_squared();
+        if dist2 < 1e-6 {
+            return Vector3::zeros();
+        }
+        let dir = to_light / dist2.sqrt();
+        let cos_surface = normal.dot(&dir);
+        let cos_light = -face_normal.dot(&dir);
+        if cos_surface <= 0.0 || cos_light <= 0.0 {
+            return Vector3::zeros();
+        }
 
+        let transmittance = self.transmittance(&(point + normal * SHADOW_BIAS), &(target - dir * SHADOW_BIAS));
+        if transmittance.max() <= 0.0 {
+            return Vector3::zeros();
+        }
 
-                let mut normal = Vector3::new(0.0, 0.0, 0.0);
-                let mut stepped_count = 0;
-                if stepped_x { normal.x = -dir.x.signum(); stepped_count += 1; }
-                if stepped_y { normal.y = -dir.y.signum(); stepped_count += 1; }
-                if stepped_z { normal.z = -dir.z.signum(); stepped_count += 1; }
+        let block = self.get_block(ex, ey, ez);
+        let uv = self.calculate_uv(&target, &face_normal, ex, ey, ez);
+        let emitted = texture_manager.sample(block, self.texture_face(ex, ey, ez, &face_normal), uv, 0.0)
+            .component_mul(&block.light_color()) * EMITTER_RADIANCE;
+        let inverse_pdf = (self.emitters.len() * 6) as f32;
+        emitted.component_mul(&transmittance) * (cos_surface * cos_light / dist2 * inverse_pdf)
+    }
 
-                if stepped_count > 1 {
+    /*
+    Documentación (bloque):
+    Sección: Mapeo UV
+    Descripción: Calcula las coordenadas UV del punto de impacto según la cara hitada,
+    devolviendo un par (u,v) en [0,1]. El punto y la normal se llevan antes al marco
+    canónico del voxel, así que la textura gira con el bloque.
+    */
+    fn calculate_uv(&self, hit_point: &Point3<f32>, normal: &Vector3<f32>, block_x: i32, block_y: i32, block_z: i32) -> (f32, f32) {
+        let state = self.get_state(block_x, block_y, block_z);
+        let local = state.to_canonical_local(&Vector3::new(
+            hit_point.x - block_x as f32,
+            hit_point.y - block_y as f32,
+            hit_point.z - block_z as f32,
+        ));
+        face_uv(&local, &state.to_canonical(normal))
+    }
 
-                    let absx = if stepped_x { dir.x.abs() } else { -1.0 };
-                    let absy = if stepped_y { dir.y.abs() } else { -1.0 };
-                    let absz = if stepped_z { dir.z.abs() } else { -1.0 };
-                    if absx >= absy && absx >= absz {
-                        normal.y = 0.0; normal.z = 0.0;
-                        normal.x = -dir.x.signum();
-                    } else if absy >= absx && absy >= absz {
-                        normal.x = 0.0; normal.z = 0.0;
-                        normal.y = -dir.y.signum();
-                    } else {
-                        normal.x = 0.0; normal.y = 0.0;
-                        normal.z = -dir.z.signum();
+    /*
+    Descripción: Intersección exacta del rayo con las cajas del bloque del voxel
+    `(x, y, z)`. El rayo se lleva al marco canónico del voxel, donde está definida la forma,
+    y se prueba contra cada caja por el método de los slabs. Devuelve el impacto más cercano
+    con `t >= t_min`, con la normal de la cara alcanzada de esa caja (de vuelta en el mundo),
+    su UV y su cara de textura. Un origen dentro de una caja no cuenta como impacto.
+    */
+    fn intersect_voxel(&self, x: i32, y: i32, z: i32, origin: &Point3<f32>, dir: &Vector3<f32>, t_min: f32) -> Option<RayHit> {
+        let block = self.get_block(x, y, z);
+        let state = self.get_state(x, y, z);
+        let local_origin = state.to_canonical_local(&Vector3::new(origin.x - x as f32, origin.y - y as f32, origin.z - z as f32));
+        let local_dir = state.to_canonical(dir);
+
+        let mut nearest: Option<(f32, usize)> = None;
+        'boxes: for (min, max) in block.boxes(state) {
+            let mut t_near = f32::NEG_INFINITY;
+            let mut t_far = f32::INFINITY;
+            let mut axis = 0;
+            for a in 0..3 {
+                if local_dir[a].abs() < 1e-12 {
+                    if local_origin[a] < min[a] || local_origin[a] > max[a] {
+                        continue 'boxes;
                     }
+                    continue;
                 }
-
-                if normal.magnitude() > 0.0 { normal = normal.normalize(); }
-                let uv = self.calculate_uv(&hit_point_in, &normal, x, y, z);
-                return Some((min_t, normal, block, uv));
+                let t0 = (min[a] - local_origin[a]) / local_dir[a];
+                let t1 = (max[a] - local_origin[a]) / local_dir[a];
+                let (t0, t1) = if t0 <= t1 { (t0, t1) } else { (t1, t0) };
+                if t0 > t_near {
+                    t_near = t0;
+                    axis = a;
+                }
+                t_far = t_far.min(t1);
+            }
+            if t_near > t_far || t_near < t_min {
+                continue;
+            }
+            if nearest.is_none_or(|(t, _)| t_near < t) {
+                nearest = Some((t_near, axis));
             }
         }
 
-        None
+        let (t, axis) = nearest?;
+        let mut local_normal = Vector3::zeros();
+        local_normal[axis] = -local_dir[axis].signum();
+        let uv = face_uv(&(local_origin + local_dir * t), &local_normal);
+        Some((t, state.to_world(&local_normal), block, uv, BlockFace::from_normal(&local_normal)))
     }
 
     /*
     Documentación (bloque):
-    Sección: Raymarch simple (fallback)
-    Descripción: Método alternativo que avanza en pasos constantes a lo largo del rayo. Es
-    menos eficiente pero más simple; se usa cuando DDA no encuentra impacto por razones
-    numéricas o para cubrir casos extremos.
+    Sección: Raycasting DDA
+    Descripción: Recorre los voxels que atraviesa un rayo que viaja dentro de `medium`
+    (aire, agua, vidrio...) con un DDA, desde que entra en el volumen del mundo hasta que
+    sale. En cada voxel de un bloque distinto al medio se intersecta el rayo con sus cajas
+    (`intersect_voxel`), así que las formas parciales se resuelven exactamente. Dentro de un
+    medio translúcido el rayo se detiene en el borde del primer voxel distinto al medio; si
+    es aire o una forma parcial, el bloque devuelto es `AIRE` (el rayo sale del medio y
+    sigue por el aire de ese voxel). Devuelve distancia t, normal, bloque, UV y cara.
     */
-    fn raycast_simple(&self, origin: &Point3<f32>, direction: &Vector3<f32>) -> Option<(f32, Vector3<f32>, BlockType, (f32, f32))> {
-
-        let aabb_min = Point3::new(0.0_f32, 0.0_f32, 0.0_f32);
-        let aabb_max = Point3::new(self.width as f32, self.height as f32, self.depth as f32);
-        if let Some((mut tmin, tmax)) = ray_aabb_intersect(origin, direction, &aabb_min, &aabb_max) {
-
-            if tmax < 0.0 { return None; }
-            if tmin < 0.0 { tmin = 0.0; }
-            let mut t = tmin;
-            let step = 0.03_f32;
+    fn raycast_through(&self, origin: &Point3<f32>, direction: &Vector3<f32>, medium: BlockType) -> Option<RayHit> {
+        self.traverse(origin, direction, medium, true)
+    }
 
-            while t <= tmax {
-                let p = origin + direction * t;
-            let x = p.x.floor() as i32;
-            let y = p.y.floor() as i32;
-            let z = p.z.floor() as i32;
+    /*
+    Descripción: El DDA de `raycast_through`. Con `skip_empty`, los rayos que viajan por el
+    aire consultan `occupancy` en cada paso y, si el voxel está dentro de una región o un
+    ladrillo vacíos, saltan directamente a la cara por la que el rayo sale de ese cubo y
+    retoman el DDA en el voxel vecino. Sin él recorre voxel a voxel (lo usa `--bench` como
+    referencia).
+    */
+    fn traverse(&self, origin: &Point3<f32>, direction: &Vector3<f32>, medium: BlockType, skip_empty: bool) -> Option<RayHit> {
+        let dir = *direction;
+        let world_max = Point3::new(self.width as f32, self.height as f32, self.depth as f32);
+        let (t_enter, t_exit) = ray_aabb_intersect(origin, &dir, &Point3::origin(), &world_max)?;
+        if t_exit < 0.0 || t_enter > t_exit {
+            return None;
+        }
 
-            if x >= 0 && y >= 0 && z >= 0 && x < self.width as i32 && y < self.height as i32 && z < self.depth as i32 {
-                let block = self.get_block(x, y, z);
-                if block.is_solid() {
+        let start = origin + dir * t_enter.max(0.0);
+        let size = [self.width as i32, self.height as i32, self.depth as i32];
+        let mut cell = [0i32; 3];
+        let mut step = [0i32; 3];
+        let mut t_next = [f32::INFINITY; 3];
+        let inv_dir = dir.map(|d| 1.0 / d);
+        /*
+        Distancia a la que el rayo cruza el borde de salida de la celda `c` en el eje `a`.
+        Se calcula desde el origen en cada paso en lugar de acumular incrementos, que en
+        recorridos largos se desvían lo bastante como para saltarse aristas rozadas.
+        */
+        let boundary = |a: usize, c: i32| -> f32 {
+            if dir[a] > 0.0 {
+                (c as f32 + 1.0 - origin[a]) * inv_dir[a]
+            } else if dir[a] < 0.0 {
+                (c as f32 - origin[a]) * inv_dir[a]
+            } else {
+                f32::INFINITY
+            }
+        };
+        for a in 0..3 {
+            cell[a] = (start[a].floor() as i32).clamp(0, size[a] - 1);
+            step[a] = if dir[a] > 0.0 { 1 } else if dir[a] < 0.0 { -1 } else { 0 };
+            t_next[a] = boundary(a, cell[a]);
+        }
 
-                    let block_center = Point3::new(x as f32 + 0.5, y as f32 + 0.5, z as f32 + 0.5);
-                    let to_center = Vector3::new(block_center.x - p.x, block_center.y - p.y, block_center.z - p.z);
-                    let inward_eps = 1e-4_f32 + t * 1e-6;
-                    let hit_in = if to_center.magnitude() > 1e-6 {
-                        p + to_center.normalize() * inward_eps
+        // Eje y distancia por los que se entró en el voxel actual; `None` en el primero.
+        let mut entry: Option<(usize, f32)> = None;
+        loop {
+            let [x, y, z] = cell;
+            let extent = if skip_empty && medium == BlockType::AIRE { self.occupancy.empty_extent(x, y, z) } else { 0 };
+
+            if extent > 1 {
+                let base = [x & !(extent - 1), y & !(extent - 1), z & !(extent - 1)];
+                let exit = |a: usize| boundary(a, if step[a] > 0 { base[a] + extent - 1 } else { base[a] });
+                let axis = if exit(0) <= exit(1) && exit(0) <= exit(2) {
+                    0
+                } else if exit(1) <= exit(2) {
+                    1
+                } else {
+                    2
+                };
+                let t = exit(axis);
+                if !t.is_finite() || t > t_exit {
+                    return None;
+                }
+                for a in 0..3 {
+                    cell[a] = if a == axis {
+                        if step[a] > 0 { base[a] + extent } else { base[a] - 1 }
                     } else {
-                        p - direction * inward_eps
+                        ((origin[a] + dir[a] * t).floor() as i32).clamp(base[a], (base[a] + extent).min(size[a]) - 1)
                     };
-                    if self.check_special_collision(x, y, z, &hit_in) {
+                    t_next[a] = boundary(a, cell[a]);
+                }
+                if cell[axis] < 0 || cell[axis] >= size[axis] {
+                    return None;
+                }
+                entry = Some((axis, t));
+                continue;
+            }
 
-                        let mut normal = self.calculate_normal(x, y, z, &hit_in);
-                        if normal.magnitude() == 0.0 { normal = Vector3::new(0.0, 1.0, 0.0); }
-                        let uv = self.calculate_uv(&hit_in, &normal, x, y, z);
-                        return Some((t, normal, block, uv));
+            if extent == 0 {
+                let block = self.get_block(x, y, z);
+                if block != medium {
+                    if medium == BlockType::AIRE {
+                        if let Some(hit) = self.intersect_voxel(x, y, z, origin, &dir, 0.0) {
+                            return Some(hit);
+                        }
+                    } else if let Some((axis, t)) = entry {
+                        let mut normal = Vector3::zeros();
+                        normal[axis] = -step[axis] as f32;
+                        let next = if block.is_full_cube(self.get_state(x, y, z)) { block } else { BlockType::AIRE };
+                        let uv = self.calculate_uv(&(origin + dir * t), &normal, x, y, z);
+                        return Some((t, normal, next, uv, self.texture_face(x, y, z, &normal)));
                     }
                 }
             }
 
-                t += step;
+            let axis = if t_next[0] <= t_next[1] && t_next[0] <= t_next[2] {
+                0
+            } else if t_next[1] <= t_next[2] {
+                1
+            } else {
+                2
+            };
+            let t = t_next[axis];
+            if !t.is_finite() || t > t_exit {
+                return None;
             }
+            cell[axis] += step[axis];
+            if cell[axis] < 0 || cell[axis] >= size[axis] {
+                return None;
+            }
+            t_next[axis] = boundary(axis, cell[axis]);
+            entry = Some((axis, t));
         }
-
-        None
     }
 
-/*
-Nota: `ray_aabb_intersect` se define fuera de esta implementación para mantener la
-organización del archivo. Ver función más arriba.
-*/
-
-    #[allow(dead_code)]
-    fn calculate_normal(&self, x: i32, y: i32, z: i32, hit_point: &Point3<f32>) -> Vector3<f32> {
-        let block_center = Vector3::new(x as f32 + 0.5, y as f32 + 0.5, z as f32 + 0.5);
-        let to_hit = Vector3::new(hit_point.x, hit_point.y, hit_point.z) - block_center;
-
-        let abs_x = to_hit.x.abs();
-        let abs_y = to_hit.y.abs();
-        let abs_z = to_hit.z.abs();
-
-        if abs_x > abs_y && abs_x > abs_z {
-            Vector3::new(to_hit.x.signum(), 0.0, 0.0)
-        } else if abs_y > abs_z {
-            Vector3::new(0.0, to_hit.y.signum(), 0.0)
-        } else {
-            Vector3::new(0.0, 0.0, to_hit.z.signum())
+    /*
+    Descripción: Fracción de luz, por canal, que recorre el segmento entre `from` y `to`
+    (rayo de sombra). Cualquier bloque opaco en el camino la anula; los translúcidos la
+    atenúan según Beer-Lambert con la distancia recorrida dentro de ellos. Los rayos de
+    sombra no se desvían por refracción.
+    */
+    fn transmittance(&self, from: &Point3<f32>, to: &Point3<f32>) -> Vector3<f32> {
+        let offset = to - from;
+        let mut remaining = offset.magnitude();
+        let mut result = Vector3::new(1.0, 1.0, 1.0);
+        if remaining < 1e-6 {
+            return result;
+        }
+        let dir = offset / remaining;
+
+        let start = self.get_block(from.x.floor() as i32, from.y.floor() as i32, from.z.floor() as i32);
+        let mut medium = if start.is_translucent() { start } else { BlockType::AIRE };
+        let mut origin = *from;
+        for _ in 0..MAX_MEDIUM_CROSSINGS {
+            match self.raycast_through(&origin, &dir, medium) {
+                Some((t, _, block, ..)) if t < remaining => {
+                    result = result.component_mul(&medium.transmittance(t));
+                    if block.is_solid() && !block.is_translucent() {
+                        return Vector3::zeros();
+                    }
+                    let advance = t + SHADOW_BIAS;
+                    origin += dir * advance;
+                    remaining -= advance;
+                    medium = block;
+                }
+                _ => return result.component_mul(&medium.transmittance(remaining.max(0.0))),
+            }
         }
+        Vector3::zeros()
     }
 }
 
@@ -885,86 +1668,68 @@ organización del archivo. Ver función más arriba.
 Documentación (bloque):
 Sección: Trazado de rayo y sombreado
 Descripción: `cast_ray` expone la entrada del renderer. Internamente define `shade`, una
-función recursiva que aplica PBR simplificado, fresnel, reflexiones y añade contribución
-emisiva a partir de un escaneo local. Si no hay impacto, solicita color al `Skybox`.
+función recursiva que aplica Cook-Torrance GGX, reflexiones brillantes muestreadas según
+la rugosidad y añade la luz de bloque coloreada precalculada en el mundo (ver
+`bake_block_light`). La luz directa y su especular se atenúan con los rayos de sombra de
+`Light::visibility`. Al llegar a la superficie de un bloque translúcido, `shade_interface`
+reparte el rayo entre reflexión y refracción según Fresnel, y `shade` sigue el rayo
+refractado dentro del bloque (`medium`), atenuándolo por Beer-Lambert con la distancia
+recorrida. Si no hay impacto, solicita color al `Skybox`.
+Devuelve la radiancia lineal del rayo, sin recortar; `Develop` la lleva a la pantalla.
 */
-pub fn cast_ray(ray_origin: &Point3<f32>, ray_direction: &Vector3<f32>, world: &VoxelWorld, texture_manager: &TextureManager, light_pos: &Point3<f32>, is_day: bool) -> u32 {
-
-    fn pack_color(col: Vector3<f32>) -> u32 {
-        let r = (col.x.max(0.0).min(1.0) * 255.0) as u32;
-        let g = (col.y.max(0.0).min(1.0) * 255.0) as u32;
-        let b = (col.z.max(0.0).min(1.0) * 255.0) as u32;
-        0xFF000000 | (r << 16) | (g << 8) | b
-    }
+pub fn cast_ray(ray_origin: &Point3<f32>, ray_direction: &Vector3<f32>, cone: RayCone, world: &VoxelWorld, texture_manager: &TextureManager, light: &Light, sky: &Skybox) -> Vector3<f32> {
 
     let max_bounces = 3usize;
 
 
-    fn shade(origin: Point3<f32>, dir: Vector3<f32>, depth: usize, max_bounces: usize, world: &VoxelWorld, texture_manager: &TextureManager, light_pos: &Point3<f32>, is_day: bool) -> Vector3<f32> {
+    #[allow(clippy::too_many_arguments)]
+    fn shade(origin: Point3<f32>, dir: Vector3<f32>, cone: RayCone, depth: usize, max_bounces: usize, medium: BlockType, world: &VoxelWorld, texture_manager: &TextureManager, light: &Light, sky: &Skybox) -> Vector3<f32> {
 
-        if let Some((t, normal, block_type, uv)) = world.raycast(&origin, &dir).or_else(|| world.raycast_simple(&origin, &dir)) {
+        if let Some((t, normal, block_type, uv, face)) = world.raycast_through(&origin, &dir, medium) {
             let eps = 1e-5_f32;
             let hit_point = origin + dir * t;
             let sample_point = hit_point - dir * eps;
+            let medium_transmittance = medium.transmittance(t);
+
+            if block_type == BlockType::AIRE || block_type.is_translucent() {
+                let color = shade_interface(hit_point, dir, normal, cone.advance(t), depth, max_bounces, medium, block_type, world, texture_manager, light, sky);
+                return color.component_mul(&medium_transmittance);
+            }
 
 
-            let texture = texture_manager.get_texture(block_type);
-            let tex_rgb = texture.sample(uv.0, uv.1);
-            let tex_col = Vector3::new(tex_rgb[0] as f32 / 255.0, tex_rgb[1] as f32 / 255.0, tex_rgb[2] as f32 / 255.0);
+            let tex_col = texture_manager.sample(block_type, face, uv, cone.footprint(t, &dir, &normal));
             let material = get_material(block_type);
             let base_color = tex_col.component_mul(&material.albedo);
 
 
-            let light_dir = (light_pos - sample_point).normalize();
+            let light_dir = (light.position - sample_point).normalize();
             let view_dir = -dir.normalize();
-            let half = (light_dir + view_dir).normalize();
 
             let n_dot_l = normal.dot(&light_dir).max(0.0);
-            let n_dot_h = normal.dot(&half).max(0.0);
-
 
-            let shininess = (1.0 - material.roughness).max(0.01) * 256.0;
-            let spec_term = n_dot_h.powf(shininess);
+            let shadow = if n_dot_l > 0.0 { light.visibility(world, sky, &hit_point, &normal).component_mul(&light.color) } else { Vector3::zeros() };
 
 
-            let fresnel = fresnel_simple(&view_dir, &normal, &material);
+            let f0 = specular_f0(&material);
+            let alpha = ggx_alpha(material.roughness);
+            let fresnel = fresnel_schlick(normal.dot(&view_dir), &f0);
+            let diffuse_weight = 1.0 - material.reflectance * fresnel.mean();
 
 
-            let diffuse = base_color * (1.0 - material.metallic) * n_dot_l;
+            let diffuse = base_color.component_mul(&shadow) * ((1.0 - material.metallic) * diffuse_weight * n_dot_l);
 
 
-            let specular = material.specular_color * (spec_term * material.reflectance * fresnel);
+            let specular = cook_torrance(&normal, &view_dir, &light_dir, alpha, &f0).component_mul(&shadow)
+                * (material.reflectance * n_dot_l * std::f32::consts::PI);
 
 
-            let mut emissive_amount = 0.0_f32;
-
-            let scan_radius = 4i32;
-            let hit_block_pos = Point3::new(hit_point.x.floor(), hit_point.y.floor(), hit_point.z.floor());
-            for oy in -scan_radius..=scan_radius {
-                for oz in -scan_radius..=scan_radius {
-                    for ox in -scan_radius..=scan_radius {
-                        let bx = hit_block_pos.x as i32 + ox;
-                        let by = hit_block_pos.y as i32 + oy;
-                        let bz = hit_block_pos.z as i32 + oz;
-                        if bx < 0 || by < 0 || bz < 0 || bx >= world.width as i32 || by >= world.height as i32 || bz >= world.depth as i32 { continue; }
-                        let b = world.get_block(bx, by, bz);
-                        if b.emits_light() {
-                            let dx = (bx as f32 + 0.5) - hit_point.x;
-                            let dy = (by as f32 + 0.5) - hit_point.y;
-                            let dz = (bz as f32 + 0.5) - hit_point.z;
-                            let dist2 = dx*dx + dy*dy + dz*dz + 1e-4;
-
-                            emissive_amount += 1.5 / dist2;
-                        }
-                    }
-                }
-            }
-            emissive_amount = emissive_amount.min(1.0);
+            let block_light = world.block_light_at(&(hit_point + normal * 0.5));
 
 
-            let base_ambient = if is_day { 0.35 } else { 0.08 };
+            let base_ambient = 0.08 + 0.27 * sky.daylight();
 
-            let ambient = base_ambient + emissive_amount * 1.0 + if block_type.emits_light() { 0.25 } else { 0.0 };
+            let ambient = base_ambient + if block_type.emits_light() { 0.25 } else { 0.0 };
+            let ambient = Vector3::new(ambient, ambient, ambient) + block_light * 1.6;
 
 
             let mut color = Vector3::new(0.0,0.0,0.0);
@@ -973,117 +1738,656 @@ pub fn cast_ray(ray_origin: &Point3<f32>, ray_direction: &Vector3<f32>, world: &
 
 
             if material.reflectance > 0.1 && depth < max_bounces {
-                let reflect_dir = reflect(&dir, &normal).normalize();
                 let reflect_origin = hit_point + normal * 0.001;
-                let reflected = shade(reflect_origin, reflect_dir, depth + 1, max_bounces, world, texture_manager, light_pos, is_day);
-
-                let refl_boost = if material.roughness < 0.1 { 1.2 } else { 1.0 };
-
-                color += reflected * material.reflectance * fresnel * refl_boost;
+                let mut rng = Rng::from_point(&reflect_origin);
+                if let Some((reflect_dir, weight)) = sample_ggx_reflection(&normal, &view_dir, alpha, &f0, &mut rng) {
+                    let reflected = shade(reflect_origin, reflect_dir, cone.advance(t), depth + 1, max_bounces, medium, world, texture_manager, light, sky);
+                    color += reflected.component_mul(&weight) * material.reflectance;
+                }
             }
 
 
-            color += base_color.component_mul(&Vector3::new(ambient,ambient,ambient)) * 0.6;
-            color += material.albedo * material.emissive_strength;
+            color += base_color.component_mul(&ambient) * 0.6;
+            color += tex_col * material.emissive_strength;
 
 
-            if !is_day && material.emissive_strength > 0.5 {
-                let night_boost = match block_type {
-                    BlockType::Lava => 0.2,
-                    BlockType::BloqueMagma => 0.15,
-                    _ => 0.1,
-                };
-                color += material.albedo * material.emissive_strength * night_boost;
+            if material.emissive_strength > 0.5 {
+                let night_boost = registry().get(block_type).night_glow * (1.0 - sky.daylight());
+                color += tex_col * material.emissive_strength * night_boost;
             }
 
-
-            return Vector3::new(color.x.min(1.0).max(0.0), color.y.min(1.0).max(0.0), color.z.min(1.0).max(0.0));
+            return color.component_mul(&medium_transmittance);
         }
 
 
-        let sky = Skybox::new(*light_pos - Point3::new(0.0, 0.0, 0.0));
-        let sky_col = sky.sample(&dir, is_day);
-        return sky_col;
+        sky.sample(&dir, cone.spread)
     }
 
-    let col = shade(*ray_origin, *ray_direction, 0usize, max_bounces, world, texture_manager, light_pos, is_day);
-    pack_color(col)
-}
-
 
-pub struct Skybox {
-    pub day_horizon: Vector3<f32>,
-    pub day_zenith: Vector3<f32>,
-    pub night_horizon: Vector3<f32>,
-    pub night_zenith: Vector3<f32>,
-    pub sun_direction: Vector3<f32>,
-    pub sun_intensity: f32,
-}
+    #[allow(clippy::too_many_arguments)]
+    fn shade_interface(hit_point: Point3<f32>, dir: Vector3<f32>, normal: Vector3<f32>, cone: RayCone, depth: usize, max_bounces: usize, medium: BlockType, next: BlockType, world: &VoxelWorld, texture_manager: &TextureManager, light: &Light, sky: &Skybox) -> Vector3<f32> {
+        let (fresnel, refracted) = dielectric_split(&dir, &normal, medium.ior(), next.ior());
+        let mut color = Vector3::new(0.0, 0.0, 0.0);
 
-impl Skybox {
-    /*
-    Documentación (bloque):
-    Sección: Skybox procedimental
-    Descripción: Genera un color de cielo en función de la dirección del rayo y
-    si es de día o de noche. Incluye contribución del sol (pico estrecho) y
-    estrellas generadas por una función hash para la noche.
-    */
-    pub fn new(sun_dir: Vector3<f32>) -> Self {
-        Skybox {
-            day_horizon: Vector3::new(1.0, 0.6, 0.3),
-            day_zenith: Vector3::new(0.3, 0.7, 1.0),
-            night_horizon: Vector3::new(0.2, 0.1, 0.3),
-            night_zenith: Vector3::new(0.01, 0.01, 0.05),
-            sun_direction: sun_dir.normalize(),
-            sun_intensity: 0.5,
+        if let Some(refract_dir) = refracted {
+            if depth < MAX_REFRACTION_DEPTH {
+                let transmitted = shade(hit_point - normal * 0.001, refract_dir, cone, depth + 1, max_bounces, next, world, texture_manager, light, sky);
+                color += transmitted * (1.0 - fresnel);
+            }
         }
-    }
-
 
-    pub fn sample(&self, direction: &Vector3<f32>, is_day: bool) -> Vector3<f32> {
+        if depth < max_bounces || (refracted.is_none() && depth < MAX_REFRACTION_DEPTH) {
+            let reflect_dir = reflect(&dir, &normal).normalize();
+            let reflected = shade(hit_point + normal * 0.001, reflect_dir, cone, depth + 1, max_bounces, medium, world, texture_manager, light, sky);
+            color += reflected * fresnel;
+        }
 
-        let t = (direction.y * 0.5 + 0.5).max(0.0).min(1.0);
 
+        let light_dir = (light.position - hit_point).normalize();
+        let n_dot_l = normal.dot(&light_dir);
+        if medium == BlockType::AIRE && n_dot_l > 0.0 {
+            let r0 = ((next.ior() - 1.0) / (next.ior() + 1.0)).powi(2);
+            let alpha = ggx_alpha(get_material(next).roughness);
+            let glint = cook_torrance(&normal, &-dir, &light_dir, alpha, &Vector3::new(r0, r0, r0));
+            color += glint.component_mul(&light.visibility(world, sky, &hit_point, &normal)).component_mul(&light.color) * (n_dot_l * std::f32::consts::PI);
+        }
 
-        let horizon = if is_day { self.day_horizon } else { self.night_horizon };
-        let zenith = if is_day { self.day_zenith } else { self.night_zenith };
+        color
+    }
 
+    shade(*ray_origin, *ray_direction, cone, 0usize, max_bounces, BlockType::AIRE, world, texture_manager, light, sky)
+}
 
-        let t_smooth = t * t * (3.0 - 2.0 * t);
-        let mut col = horizon * (1.0 - t_smooth) + zenith * t_smooth;
+/// Límite de superficies translúcidas que sigue un rayo (refracciones y reflexiones totales).
+const MAX_REFRACTION_DEPTH: usize = 8;
+/// Límite de medios que atraviesa un rayo de sombra antes de darlo por bloqueado.
+const MAX_MEDIUM_CROSSINGS: usize = 32;
 
+/// Irradiancia de la luz principal en el path tracer, por unidad de `Light::color`.
+const SUN_IRRADIANCE: f32 = 2.8;
+/// Radiancia de los bloques emisivos en el path tracer, por unidad de `luz` del registro.
+const EMITTER_RADIANCE: f32 = 4.0;
+/// Límite de rebotes de un camino, aunque la ruleta rusa no lo haya terminado antes.
+const PATH_MAX_DEPTH: usize = 12;
+/// Rebote a partir del cual la ruleta rusa puede terminar el camino.
+const RUSSIAN_ROULETTE_DEPTH: usize = 3;
 
-        let sun_dot = direction.normalize().dot(&self.sun_direction).max(0.0);
-        let sun_contrib = sun_dot.powf(16.0) * self.sun_intensity * 0.5;
-        if sun_contrib > 0.0 {
+/*
+Documentación (bloque):
+Sección: Path tracing
+Descripción: Estimador Monte Carlo de un único camino para el modo de path tracing. En
+cada impacto suma la luz directa del sol con la BRDF completa (difusa más Cook-Torrance
+GGX) y la de un bloque emisivo elegido al azar sobre la parte difusa (next-event
+estimation). Después elige entre el lóbulo especular (una microfaceta GGX, con
+probabilidad `reflectancia × fresnel`) y el difuso (rebote ponderado por el coseno). La
+emisión de un bloque solo se suma al verla directamente o tras un rebote especular,
+porque los rebotes difusos ya la han muestreado. Con un mapa de entorno el sol no existe:
+la luz del cielo se muestrea por importancia sobre el mapa en cada impacto (image-based
+lighting, con la parte difusa de la BRDF) y, como con los emisores, un camino que escapa
+solo suma el cielo si el último rebote fue especular. Sin mapa, un camino que escapa
+tras un rebote difuso suma el cielo sin el disco del sol, que ya aportó el muestreo
+directo. En la superficie de un bloque
+translúcido el camino se refleja o se refracta con probabilidad igual a la reflectancia
+de Fresnel, y dentro del bloque el throughput se atenúa por Beer-Lambert. A partir de
+`RUSSIAN_ROULETTE_DEPTH` rebotes el camino sobrevive con probabilidad proporcional a su
+throughput. No hay término ambiente: la luz indirecta sale de los propios rebotes y del
+cielo.
+*/
+#[allow(clippy::too_many_arguments)]
+fn trace_path(origin: Point3<f32>, dir: Vector3<f32>, cone: RayCone, world: &VoxelWorld, texture_manager: &TextureManager, light: &Light, sky: &Skybox, rng: &mut Rng) -> Vector3<f32> {
+    let sun_irradiance = light.color * SUN_IRRADIANCE;
+    let environment = sky.has_environment();
+    let world_extent = Vector3::new(world.width as f32, world.height as f32, world.depth as f32).norm();
+
+    let mut radiance = Vector3::zeros();
+    let mut throughput = Vector3::new(1.0, 1.0, 1.0);
+    let mut origin = origin;
+    let mut dir = dir;
+    let mut cone = cone;
+    let mut medium = BlockType::AIRE;
+    let mut specular_bounce = true;
+
+    for depth in 0..PATH_MAX_DEPTH {
+        let Some((t, normal, block_type, uv, face)) = world.raycast_through(&origin, &dir, medium) else {
+            if specular_bounce {
+                radiance += throughput.component_mul(&sky.sample(&dir, cone.spread));
+            } else if !environment {
+                radiance += throughput.component_mul(&sky.sample_without_sun(&dir, cone.spread));
+            }
+            break;
+        };
 
-            let sun_color = if is_day { Vector3::new(1.0, 0.95, 0.85) } else { Vector3::new(0.8, 0.7, 0.6) * 0.5 };
-            col += sun_color * sun_contrib;
+        let hit_point = origin + dir * t;
+        let footprint = cone.footprint(t, &dir, &normal);
+        cone = cone.advance(t);
+        throughput = throughput.component_mul(&medium.transmittance(t));
+
+        if block_type == BlockType::AIRE || block_type.is_translucent() {
+            let (fresnel, refracted) = dielectric_split(&dir, &normal, medium.ior(), block_type.ior());
+            match refracted {
+                Some(refract_dir) if rng.next_f32() >= fresnel => {
+                    dir = refract_dir;
+                    origin = hit_point - normal * SHADOW_BIAS;
+                    medium = block_type;
+                }
+                _ => {
+                    dir = reflect(&dir, &normal).normalize();
+                    origin = hit_point + normal * SHADOW_BIAS;
+                }
+            }
+            specular_bounce = true;
+            continue;
         }
 
+        let tex_col = texture_manager.sample(block_type, face, uv, footprint);
+        let material = get_material(block_type);
 
-        if !is_day {
-
-            let s = direction.x * 12.9898 + direction.y * 78.233 + direction.z * 37.719;
-            let star_hash = s.sin().abs();
-            if star_hash > 0.9995 {
-
-                let brightness = ((star_hash - 0.9995) / (1.0 - 0.9995)).clamp(0.0, 1.0);
+        if block_type.emits_light() && specular_bounce {
+            radiance += throughput.component_mul(&tex_col.component_mul(&block_type.light_color())) * EMITTER_RADIANCE;
+        }
 
-                let star_col = Vector3::new(1.0, 1.0, 1.05);
-                col += star_col * (0.7 + 0.3 * brightness);
+        let view_dir = -dir;
+        let f0 = specular_f0(&material);
+        let alpha = ggx_alpha(material.roughness);
+        let specular_probability = (material.reflectance * fresnel_schlick(normal.dot(&view_dir), &f0).mean()).clamp(0.0, 0.95);
+        let diffuse_brdf = tex_col.component_mul(&material.albedo) * ((1.0 - material.metallic) * (1.0 - specular_probability) / std::f32::consts::PI);
+
+        let light_dir = (light.position - hit_point).normalize();
+        let n_dot_l = normal.dot(&light_dir);
+        if n_dot_l > 0.0 && !environment {
+            let brdf = diffuse_brdf + cook_torrance(&normal, &view_dir, &light_dir, alpha, &f0) * material.reflectance;
+            let irradiance = sun_irradiance.component_mul(&light.visibility(world, sky, &hit_point, &normal)) * n_dot_l;
+            radiance += throughput.component_mul(&brdf.component_mul(&irradiance));
+        }
+        if let Some((sky_dir, sky_radiance, pdf)) = sky.sample_environment(rng.next_f32(), rng.next_f32()) {
+            let cos = normal.dot(&sky_dir);
+            if cos > 0.0 && pdf > 0.0 {
+                let shadow_origin = hit_point + normal * SHADOW_BIAS;
+                let visibility = world.transmittance(&shadow_origin, &(shadow_origin + sky_dir * world_extent));
+                radiance += throughput.component_mul(&diffuse_brdf.component_mul(&sky_radiance.component_mul(&visibility))) * (cos / pdf);
             }
         }
+        let emitter_irradiance = world.sample_emitter_irradiance(&hit_point, &normal, texture_manager, rng);
+        radiance += throughput.component_mul(&diffuse_brdf.component_mul(&emitter_irradiance));
 
-
-        Vector3::new(col.x.max(0.0).min(1.0), col.y.max(0.0).min(1.0), col.z.max(0.0).min(1.0))
-    }
+        if rng.next_f32() < specular_probability {
+            let Some((reflect_dir, weight)) = sample_ggx_reflection(&normal, &view_dir, alpha, &f0, rng) else {
+                break;
+            };
+            throughput = throughput.component_mul(&weight) * (material.reflectance / specular_probability);
+            dir = reflect_dir;
+            specular_bounce = true;
+        } else {
+            throughput = throughput.component_mul(&diffuse_brdf) * (std::f32::consts::PI / (1.0 - specular_probability));
+            dir = rng.cosine_hemisphere(&normal);
+            specular_bounce = false;
+        }
+        origin = hit_point + normal * SHADOW_BIAS;
+
+        if depth >= RUSSIAN_ROULETTE_DEPTH {
+            let survival = throughput.max().min(0.95);
+            if rng.next_f32() >= survival {
+                break;
+            }
+            throughput /= survival;
+        }
+    }
+
+    radiance
+}
+
+
+/*
+Documentación (bloque):
+Sección: Ciclo día/noche
+Descripción: La hora del día, en horas de 0 a 24, decide dónde están el sol y la luna y
+con qué color iluminan. Sin lugar geográfico el sol sale por +x a las 6, culmina a
+mediodía inclinado hacia +z (`SUN_TILT`) y se pone por -x a las 18; con un `Site` la
+posición sale de la latitud, la longitud y la fecha. La luna sigue el mismo arco con un
+retraso que depende de su fase (opuesta al sol cuando está llena) y alumbra en
+proporción a su parte iluminada. La luz principal de la escena es el sol mientras está sobre el horizonte
+y la luna el resto del tiempo (ver `Skybox::key_light`); ambos se apagan al acercarse al
+horizonte, así que el relevo no da saltos. Cerca del orto y el ocaso el sol se tiñe de
+naranja (`SUNSET_COLOR`).
+*/
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TimeOfDay {
+    hours: f32,
+    site: Option<Site>,
+    /// Fase de la luna: 0 nueva, 0.25 cuarto creciente, 0.5 llena, 0.75 cuarto menguante.
+    moon_phase: f32,
+}
+
+/// Inclinación hacia +z del arco que recorren el sol y la luna, en radianes.
+const SUN_TILT: f32 = 0.6;
+/// Color del sol alto y del sol rasante.
+const NOON_SUN_COLOR: Vector3<f32> = Vector3::new(1.0, 0.95, 0.85);
+const SUNSET_COLOR: Vector3<f32> = Vector3::new(1.0, 0.45, 0.15);
+/// Fase de la luna llena, la que se usa si no se elige otra.
+const FULL_MOON: f32 = 0.5;
+/// Color de la luz de la luna llena, relativa a la del sol.
+const MOON_COLOR: Vector3<f32> = Vector3::new(0.04, 0.05, 0.08);
+
+impl TimeOfDay {
+    /// Media mañana: el sol alto sobre la esquina +x +z del diorama.
+    pub const DAY: TimeOfDay = TimeOfDay { hours: 10.0, site: None, moon_phase: FULL_MOON };
+    /// Doce horas después de `DAY`, con la luna en el lugar del sol.
+    pub const NIGHT: TimeOfDay = TimeOfDay { hours: 22.0, site: None, moon_phase: FULL_MOON };
+
+    pub fn new(hours: f32) -> Self {
+        TimeOfDay { hours: hours.rem_euclid(24.0), site: None, moon_phase: FULL_MOON }
+    }
+
+    /// La misma hora, con el sol en la posición que tendría en `site`.
+    pub fn at(self, site: Option<Site>) -> Self {
+        TimeOfDay { site, ..self }
+    }
+
+    /// La misma hora, con la luna en la fase `moon_phase` (de 0 a 1).
+    pub fn with_moon_phase(self, moon_phase: f32) -> Self {
+        TimeOfDay { moon_phase: moon_phase.rem_euclid(1.0), ..self }
+    }
+
+    pub fn hours(self) -> f32 {
+        self.hours
+    }
+
+    /// La hora `hours` más tarde (o antes, si es negativo).
+    pub fn advance(self, hours: f32) -> Self {
+        TimeOfDay { hours: (self.hours + hours).rem_euclid(24.0), ..self }
+    }
+
+    pub fn sun_direction(self) -> Vector3<f32> {
+        if let Some(site) = self.site {
+            return site.sun_direction(self.hours);
+        }
+        let angle = (self.hours - 6.0) / 24.0 * std::f32::consts::TAU;
+        Vector3::new(angle.cos(), angle.sin() * SUN_TILT.cos(), angle.sin() * SUN_TILT.sin())
+    }
+
+    /// La luna recorre el arco del sol con un retraso de `moon_phase` días: nueva junto al
+    /// sol, llena en el lado opuesto.
+    pub fn moon_direction(self) -> Vector3<f32> {
+        TimeOfDay { hours: self.hours - 24.0 * self.moon_phase, ..self }.sun_direction()
+    }
+
+    /// Fracción iluminada del disco de la luna vista desde el diorama.
+    pub fn moon_illumination(self) -> f32 {
+        (1.0 - self.sun_direction().dot(&self.moon_direction())) * 0.5
+    }
+
+    /// Cuánto es de día: 0 de noche cerrada, 1 con el sol alto, con un crepúsculo suave.
+    pub fn daylight(self) -> f32 {
+        smoothstep(-0.12, 0.15, self.sun_direction().y)
+    }
+
+    /// Intensidad del tono de atardecer: máxima con el sol en el horizonte.
+    pub fn sunset(self) -> f32 {
+        (1.0 - (self.sun_direction().y / 0.3).abs()).max(0.0).powi(2)
+    }
+
+    /// Color del sol, que se apaga al ponerse y enrojece cerca del horizonte.
+    pub fn sun_color(self) -> Vector3<f32> {
+        let elevation = self.sun_direction().y;
+        SUNSET_COLOR.lerp(&NOON_SUN_COLOR, smoothstep(0.0, 0.4, elevation)) * smoothstep(-0.02, 0.08, elevation)
+    }
+
+    /// Color de la luz de la luna, proporcional a su parte iluminada.
+    pub fn moon_color(self) -> Vector3<f32> {
+        MOON_COLOR * (self.moon_illumination() * smoothstep(-0.02, 0.08, self.moon_direction().y))
+    }
+}
+
+/*
+Documentación (bloque):
+Sección: Posición del sol por lugar y fecha
+Descripción: Con latitud, longitud y fecha, la hora (local, con `utc_offset` horas de
+diferencia con UTC) da la posición real del sol según las fórmulas aproximadas de la
+NOAA: declinación y ecuación del tiempo a partir del día del año, y de ahí el ángulo
+horario. El diorama se orienta con el este en +x, el norte en -z y el cénit en +y, así
+que en el hemisferio norte el sol de mediodía queda hacia +z, como sin lugar.
+*/
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Site {
+    /// Grados, positiva al norte.
+    pub latitude: f32,
+    /// Grados, positiva al este.
+    pub longitude: f32,
+    /// Día del año, desde 1 (1 de enero).
+    pub day_of_year: u32,
+    pub utc_offset: f32,
+}
+
+impl Site {
+    pub fn sun_direction(self, hours: f32) -> Vector3<f32> {
+        let gamma = std::f32::consts::TAU / 365.0 * (self.day_of_year as f32 - 1.0 + (hours - self.utc_offset - 12.0) / 24.0);
+        let equation_of_time = 229.18 * (0.000075 + 0.001868 * gamma.cos() - 0.032077 * gamma.sin()
+            - 0.014615 * (2.0 * gamma).cos() - 0.040849 * (2.0 * gamma).sin());
+        let declination = 0.006918 - 0.399912 * gamma.cos() + 0.070257 * gamma.sin() - 0.006758 * (2.0 * gamma).cos()
+            + 0.000907 * (2.0 * gamma).sin() - 0.002697 * (3.0 * gamma).cos() + 0.00148 * (3.0 * gamma).sin();
+
+        let solar_minutes = hours * 60.0 + equation_of_time + 4.0 * self.longitude - 60.0 * self.utc_offset;
+        let hour_angle = (solar_minutes / 4.0 - 180.0).to_radians();
+        let latitude = self.latitude.to_radians();
+
+        let up = latitude.sin() * declination.sin() + latitude.cos() * declination.cos() * hour_angle.cos();
+        let east = -declination.cos() * hour_angle.sin();
+        let north = latitude.cos() * declination.sin() - latitude.sin() * declination.cos() * hour_angle.cos();
+        Vector3::new(east, up, -north).normalize()
+    }
+}
+
+/// Día del año de una fecha `AAAA-MM-DD`.
+fn day_of_year(date: &str) -> Option<u32> {
+    let mut parts = date.split('-').map(|part| part.parse::<u32>().ok());
+    let (year, month, day) = (parts.next()??, parts.next()??, parts.next()??);
+    let leap = year % 4 == 0 && (year % 100 != 0 || year % 400 == 0);
+    let lengths = [31, if leap { 29 } else { 28 }, 31, 30, 31, 30, 31, 31, 30, 31, 30, 31];
+    if parts.next().is_some() || !(1..=12).contains(&month) || day == 0 || day > lengths[month as usize - 1] {
+        return None;
+    }
+    Some(lengths[..month as usize - 1].iter().sum::<u32>() + day)
+}
+
+/// Gira `v` un ángulo `angle` (en radianes) alrededor del eje vertical.
+fn rotate_y(v: &Vector3<f32>, angle: f32) -> Vector3<f32> {
+    let (sin, cos) = angle.sin_cos();
+    Vector3::new(v.x * cos - v.z * sin, v.y, v.x * sin + v.z * cos)
+}
+
+/// Interpolación de Hermite entre 0 (en `edge0`) y 1 (en `edge1`).
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+/// Modelos de cielo: el degradado procedimental original, la atmósfera física o un mapa
+/// de entorno HDR cargado con `--env`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SkyModel {
+    Gradient,
+    Atmosphere,
+    Environment,
 }
 
+impl SkyModel {
+    fn parse(value: &str) -> Option<Self> {
+        match value {
+            "gradient" => Some(SkyModel::Gradient),
+            "atmosphere" => Some(SkyModel::Atmosphere),
+            "environment" => Some(SkyModel::Environment),
+            _ => None,
+        }
+    }
+
+    fn next(self) -> Self {
+        match self {
+            SkyModel::Gradient => SkyModel::Atmosphere,
+            SkyModel::Atmosphere => SkyModel::Environment,
+            SkyModel::Environment => SkyModel::Gradient,
+        }
+    }
+}
+
+/// Parámetros del cielo que no dependen de la hora.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SkySettings {
+    pub model: SkyModel,
+    /// Turbidez de la atmósfera: 1 es aire puro; 2 un día claro, 10 uno brumoso.
+    pub turbidity: f32,
+    /// Albedo del suelo bajo el horizonte, de 0 a 1.
+    pub ground_albedo: f32,
+    /// Giro del mapa de entorno alrededor del eje vertical, en grados.
+    pub environment_rotation: f32,
+    /// Factor por el que se multiplica la radiancia del mapa de entorno.
+    pub environment_intensity: f32,
+    pub clouds: CloudSettings,
+}
+
+/// Con la atmósfera física, la luz del sol que llega al diorama es su transmitancia por
+/// este factor, para que el sol alto con aire limpio dé una luz cercana al blanco.
+const SUN_LIGHT_SCALE: f32 = 1.1;
+/// Radio angular del disco solar en el cielo físico (exagerado para que se vea) y su radiancia.
+const SUN_DISK_RADIUS: f32 = 0.02;
+const SUN_DISK_RADIANCE: f32 = 40.0;
+/// Radiancia de la cara iluminada de una nube por unidad de color del sol.
+const CLOUD_SUN_RADIANCE: f32 = 1.2;
+
+pub struct Skybox {
+    pub time: TimeOfDay,
+    pub horizon: Vector3<f32>,
+    pub zenith: Vector3<f32>,
+    /// Cielo físico; `None` con los otros modelos.
+    atmosphere: Option<Atmosphere>,
+    /// Mapa de entorno, con su giro en radianes y su intensidad; `None` con los otros modelos.
+    environment: Option<(Arc<EnvironmentMap>, f32, f32)>,
+    pub sun_direction: Vector3<f32>,
+    /// Color de la luz del sol que llega al diorama.
+    pub sun_color: Vector3<f32>,
+    pub moon_direction: Vector3<f32>,
+    pub moon_color: Vector3<f32>,
+    /// Visibilidad de las estrellas: 1 de noche cerrada, 0 de día.
+    pub night: f32,
+    clouds: CloudSettings,
+    /// Segundos de viento acumulados, que deciden dónde están las nubes.
+    pub cloud_time: f32,
+    /// Luz del cielo que reciben las nubes desde arriba.
+    cloud_ambient: Vector3<f32>,
+}
+
+impl Skybox {
+    /*
+    Documentación (bloque):
+    Sección: Skybox procedimental
+    Descripción: Genera un color de cielo en función de la dirección del rayo y
+    de la hora, con uno de dos modelos. El degradado pasa de horizonte a cénit
+    con los colores de día o de noche según `TimeOfDay::daylight`, tiñe el
+    horizonte de naranja al amanecer y al atardecer y suma un halo ancho para el
+    sol. La atmósfera (ver `atmosphere.rs`) calcula el cielo y el color del sol
+    por dispersión simple a partir de su elevación, la turbidez y el albedo del
+    suelo, y dibuja el disco solar. Con los dos se suman la luna con su fase y su
+    halo y un catálogo fijo de estrellas (ver `night.rs`), que se encienden al
+    oscurecer y se apagan cerca del horizonte, y encima la capa de nubes (ver
+    `clouds.rs`), iluminada por el sol o la luna y por el cielo del cénit. Los
+    colores del degradado se eligen en sRGB y se guardan en lineal, como las
+    texturas. Con un mapa de entorno (ver `environment.rs`) el cielo es el mapa,
+    girado e intensificado, sin sol, luna ni estrellas, y la hora deja de
+    influir. Se construye una vez por hora, no por rayo.
+    */
+    pub fn new(time: TimeOfDay, settings: &SkySettings, environment: Option<&Arc<EnvironmentMap>>) -> Self {
+        let day_horizon = Vector3::new(1.0, 0.6, 0.3).map(srgb_to_linear);
+        let day_zenith = Vector3::new(0.3, 0.7, 1.0).map(srgb_to_linear);
+        let night_horizon = Vector3::new(0.2, 0.1, 0.3).map(srgb_to_linear);
+        let night_zenith = Vector3::new(0.01, 0.01, 0.05).map(srgb_to_linear);
+        let sunset_horizon = Vector3::new(1.0, 0.4, 0.15).map(srgb_to_linear);
+
+        let atmosphere = (settings.model == SkyModel::Atmosphere)
+            .then(|| Atmosphere::new(time.sun_direction(), settings.turbidity, settings.ground_albedo));
+        let sun_color = match &atmosphere {
+            Some(atmosphere) => atmosphere.sun_transmittance() * SUN_LIGHT_SCALE,
+            None => time.sun_color(),
+        };
+
+        let environment = environment
+            .filter(|_| settings.model == SkyModel::Environment)
+            .map(|map| (Arc::clone(map), settings.environment_rotation.to_radians(), settings.environment_intensity));
+
+        let daylight = time.daylight();
+        let zenith = night_zenith.lerp(&day_zenith, daylight);
+        let cloud_ambient = match &atmosphere {
+            Some(atmosphere) => atmosphere.radiance(&Vector3::new(0.0, 1.0, 0.0)),
+            None => zenith,
+        };
+        Skybox {
+            time,
+            horizon: night_horizon.lerp(&day_horizon, daylight).lerp(&sunset_horizon, time.sunset()),
+            zenith,
+            atmosphere,
+            environment,
+            sun_direction: time.sun_direction(),
+            sun_color,
+            moon_direction: time.moon_direction(),
+            moon_color: time.moon_color(),
+            night: 1.0 - daylight,
+            clouds: settings.clouds,
+            cloud_time: 0.0,
+            cloud_ambient,
+        }
+    }
+
+    /// Dirección y color de la luz principal: el sol de día y la luna de noche. Con un mapa
+    /// de entorno es su zona más brillante, con la irradiancia del mapa expresada en
+    /// unidades de `Light::color`.
+    pub fn key_light(&self) -> (Vector3<f32>, Vector3<f32>) {
+        if let Some((map, rotation, intensity)) = &self.environment {
+            let (direction, irradiance) = map.key_light();
+            return (rotate_y(&direction, *rotation), irradiance * (intensity / SUN_IRRADIANCE));
+        }
+        if self.sun_direction.y >= 0.0 {
+            (self.sun_direction, self.sun_color)
+        } else {
+            (self.moon_direction, self.moon_color)
+        }
+    }
+
+
+    /// Cantidad de luz de día, de 0 a 1: la del sol, o 1 con un mapa de entorno.
+    pub fn daylight(&self) -> f32 {
+        if self.environment.is_some() { 1.0 } else { self.time.daylight() }
+    }
+
+    pub fn has_environment(&self) -> bool {
+        self.environment.is_some()
+    }
+
+    /// Muestrea por importancia la luz del mapa de entorno (ver `EnvironmentMap::sample`):
+    /// dirección, radiancia y densidad de probabilidad, o `None` sin mapa.
+    pub fn sample_environment(&self, u1: f32, u2: f32) -> Option<(Vector3<f32>, Vector3<f32>, f32)> {
+        let (map, rotation, intensity) = self.environment.as_ref()?;
+        let (direction, radiance, pdf) = map.sample(u1, u2);
+        Some((rotate_y(&direction, *rotation), radiance * *intensity, pdf))
+    }
+
+    /// Radiancia del cielo en `direction` vista por un rayo de apertura `spread` radianes,
+    /// que decide cuánto se difuminan las estrellas y el borde de la luna.
+    pub fn sample(&self, direction: &Vector3<f32>, spread: f32) -> Vector3<f32> {
+        self.radiance(direction, spread, true)
+    }
+
+    /// Como `sample`, pero sin el disco del sol: para los rayos de un rebote difuso, cuyo
+    /// camino ya suma la luz del sol con el muestreo directo.
+    pub fn sample_without_sun(&self, direction: &Vector3<f32>, spread: f32) -> Vector3<f32> {
+        self.radiance(direction, spread, false)
+    }
+
+    fn radiance(&self, direction: &Vector3<f32>, spread: f32, sun_disk: bool) -> Vector3<f32> {
+        let direction = direction.normalize();
+        if let Some((map, rotation, intensity)) = &self.environment {
+            return map.radiance(&rotate_y(&direction, -rotation)) * *intensity;
+        }
+        let sun_dot = direction.dot(&self.sun_direction).max(0.0);
+
+        let mut col = match &self.atmosphere {
+            Some(atmosphere) if sun_disk => {
+                let disk = smoothstep(SUN_DISK_RADIUS.cos(), (SUN_DISK_RADIUS * 0.7).cos(), sun_dot);
+                atmosphere.radiance(&direction) + self.sun_color * (disk * SUN_DISK_RADIANCE)
+            }
+            Some(atmosphere) => atmosphere.radiance(&direction),
+            None => {
+                let t = (direction.y * 0.5 + 0.5).clamp(0.0, 1.0);
+
+
+                let t_smooth = t * t * (3.0 - 2.0 * t);
+                self.horizon * (1.0 - t_smooth) + self.zenith * t_smooth + self.sun_color * (sun_dot.powf(16.0) * 0.25)
+            }
+        };
+
+        let moon_dot = direction.dot(&self.moon_direction).max(0.0);
+        col += self.moon_color * (moon_dot.powf(64.0) * 2.0);
+
+        let horizon = smoothstep(-0.02, 0.1, direction.y);
+        let (moon, coverage) = night::moon_radiance(&direction, &self.moon_direction, &self.sun_direction, spread);
+        col += moon * horizon;
+
+        if self.night > 0.0 {
+            let stars = night::star_field().radiance(&direction, spread);
+            col += stars * (self.night * horizon * (1.0 - coverage));
+        }
+
+        let depth = self.clouds.depth_along(&Point3::origin(), &direction, self.cloud_time);
+        if depth > 0.0 {
+            let opacity = (1.0 - (-depth).exp()) * smoothstep(0.0, 0.12, direction.y);
+            let lit = (self.sun_color + self.moon_color) * (CLOUD_SUN_RADIANCE * (0.35 + 0.65 * (-0.5 * depth).exp()));
+            col = col.lerp(&(lit + self.cloud_ambient), opacity);
+        }
+
+        col
+    }
+
+    /// Fracción de la luz que atraviesa las nubes a lo largo de un rayo de sombra.
+    pub fn cloud_transmittance(&self, origin: &Point3<f32>, dir: &Vector3<f32>) -> f32 {
+        if self.environment.is_some() {
+            return 1.0;
+        }
+        self.clouds.transmittance(origin, dir, self.cloud_time)
+    }
+}
+
+/*
+Documentación (bloque):
+Sección: Filtros de reconstrucción
+Descripción: Con varias muestras por píxel, cada una se pondera según su distancia al
+centro del píxel. `Box` solo usa muestras dentro del píxel y las promedia; `Tent` y
+`Gaussian` tienen un soporte más ancho que el píxel, así que mezclan algo de los vecinos
+y suavizan mejor los bordes a cambio de un poco de nitidez.
+*/
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum PixelFilter {
+    Box,
+    Tent,
+    Gaussian,
+}
+
+impl PixelFilter {
+    fn parse(value: &str) -> Option<Self> {
+        match value {
+            "box" => Some(PixelFilter::Box),
+            "tent" => Some(PixelFilter::Tent),
+            "gauss" | "gaussian" => Some(PixelFilter::Gaussian),
+            _ => None,
+        }
+    }
+
+    /// Semiancho del soporte del filtro, en píxeles.
+    fn radius(self) -> f32 {
+        match self {
+            PixelFilter::Box => 0.5,
+            PixelFilter::Tent => 1.0,
+            PixelFilter::Gaussian => 1.5,
+        }
+    }
+
+    /// Peso de una muestra desplazada `(dx, dy)` píxeles del centro.
+    fn weight(self, dx: f32, dy: f32) -> f32 {
+        match self {
+            PixelFilter::Box => 1.0,
+            PixelFilter::Tent => (1.0 - dx.abs()).max(0.0) * (1.0 - dy.abs()).max(0.0),
+            PixelFilter::Gaussian => {
+                let sigma = 0.5_f32;
+                (-(dx * dx + dy * dy) / (2.0 * sigma * sigma)).exp()
+            }
+        }
+    }
+}
+
+/// Lo que, al cambiar, invalida el acumulador del path tracing: cámara, destino, hora y
+/// ajustes del cielo.
+type AccumulatedView = (Point3<f32>, Point3<f32>, TimeOfDay, SkySettings);
+
 struct Scene {
     world: VoxelWorld,
     texture_manager: TextureManager,
-    light_pos: Point3<f32>,
+    light: Light,
     camera_pos: Point3<f32>,
     camera_target: Point3<f32>,
 
@@ -1095,7 +2399,25 @@ struct Scene {
     initial_orbit_yaw: f32,
     initial_orbit_radius: f32,
     initial_orbit_height: f32,
-    is_day: bool,
+    time: TimeOfDay,
+    sky_settings: SkySettings,
+    /// Mapa de entorno cargado con `--env`, si lo hay.
+    environment: Option<Arc<EnvironmentMap>>,
+    /// Cielo de la hora actual; se reconstruye en `set_time`.
+    sky: Skybox,
+    /// Segundos que dura un día completo con el ciclo en marcha.
+    day_length: f32,
+    day_cycle: bool,
+    /// Segundos de viento que llevan las nubes; no avanzan en path tracing.
+    cloud_time: f32,
+
+    pixel_filter: PixelFilter,
+    /// Exposición y tone mapping con los que se revela cada frame.
+    develop: Develop,
+    path_trace: bool,
+    accumulation: Vec<(Vector3<f32>, f32)>,
+    accumulated_frames: u32,
+    accumulated_view: Option<AccumulatedView>,
 }
 
 impl Scene {
@@ -1107,12 +2429,21 @@ impl Scene {
     la cámara desde parámetros de órbita y para renderizar la escena completa
     en un buffer de píxeles usando paralelismo por filas (rayos independientes).
     */
-    fn new() -> Result<Self, Box<dyn std::error::Error>> {
-        let mut world = VoxelWorld::new();
-        world.load_from_files()?;
-
-        let mut texture_manager = TextureManager::new();
-        texture_manager.load_textures()?;
+    fn new(options: &CliOptions) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut world = VoxelWorld::load(options)?;
+        world.bake_block_light();
+
+        let mut texture_manager = TextureManager::new(options.texture_filter);
+        texture_manager.load_textures(&options.texture_dir)?;
+
+        let environment = match &options.environment_path {
+            Some(path) => {
+                let map = EnvironmentMap::load(path)?;
+                println!("✓ Mapa de entorno {} ({}x{})", path, map.width(), map.height());
+                Some(Arc::new(map))
+            }
+            None => None,
+        };
 
 
         let center_x = world.width as f32 / 2.0;
@@ -1124,9 +2455,9 @@ impl Scene {
 
 
         let orbit_center = Point3::new(center_x, center_y, center_z);
-        let orbit_yaw = 0.8_f32;
-        let orbit_radius = 13.0_f32;
-        let orbit_height = center_y + 6.0_f32;
+        let orbit_yaw = options.orbit_yaw;
+        let orbit_radius = options.orbit_radius;
+        let orbit_height = center_y + options.orbit_height;
 
 
         let camera_pos = Point3::new(
@@ -1136,9 +2467,14 @@ impl Scene {
         );
         let camera_target = orbit_center;
 
-        Ok(Scene {
+        let mut scene = Scene {
 
-            light_pos: Point3::new(center_x + 10.0, center_y + 12.0, center_z + 15.0),
+            light: Light {
+                position: orbit_center,
+                color: Vector3::zeros(),
+                radius: options.light_radius,
+                shadow_samples: options.shadow_samples,
+            },
 
             camera_pos,
             camera_target,
@@ -1150,17 +2486,55 @@ impl Scene {
             initial_orbit_yaw: orbit_yaw,
             initial_orbit_radius: orbit_radius,
             initial_orbit_height: orbit_height,
-            is_day: true,
+            time: options.time,
+            sky_settings: options.sky,
+            sky: Skybox::new(options.time, &options.sky, environment.as_ref()),
+            environment,
+            day_length: options.day_length,
+            day_cycle: options.day_cycle,
+            cloud_time: 0.0,
+
+            pixel_filter: options.pixel_filter,
+            develop: options.develop,
+            path_trace: options.path_trace,
+            accumulation: Vec::new(),
+            accumulated_frames: 0,
+            accumulated_view: None,
 
             world,
             texture_manager,
-        })
+        };
+        scene.set_time(options.time.at(options.site));
+        Ok(scene)
     }
 
+    /// Cambia la hora, rehace el cielo y mueve la luz principal con ellos.
+    fn set_time(&mut self, time: TimeOfDay) {
+        self.time = time;
+        self.sky = Skybox::new(time, &self.sky_settings, self.environment.as_ref());
+        self.sky.cloud_time = self.cloud_time;
+        let extent = Vector3::new(self.world.width as f32, self.world.height as f32, self.world.depth as f32).norm() * 0.5;
+        self.light.follow(&self.sky, &self.orbit_center, extent);
+    }
+
+    /// Avanza `seconds` segundos de tiempo real el ciclo día/noche, si está en marcha, y el
+    /// viento de las nubes, salvo en path tracing, donde impediría acumular muestras.
+    fn advance_time(&mut self, seconds: f32) {
+        if !self.path_trace {
+            self.cloud_time += seconds;
+            self.sky.cloud_time = self.cloud_time;
+        }
+        if self.day_cycle && self.day_length > 0.0 {
+            self.set_time(self.time.advance(seconds / self.day_length * 24.0));
+        }
+    }
+
+    #[allow(dead_code)]
     fn move_camera(&mut self, delta: Vector3<f32>) {
         self.camera_pos += delta;
     }
 
+    #[allow(dead_code)]
     fn rotate_camera(&mut self, yaw_delta: f32, pitch_delta: f32) {
         let to_target = self.camera_target - self.camera_pos;
         let distance = to_target.magnitude();
@@ -1169,7 +2543,7 @@ impl Scene {
         let current_pitch = (to_target.y / distance).asin();
 
         let new_yaw = current_yaw + yaw_delta;
-        let new_pitch = (current_pitch + pitch_delta).max(-1.5).min(1.5);
+        let new_pitch = (current_pitch + pitch_delta).clamp(-1.5, 1.5);
 
         let new_direction = Vector3::new(
             new_yaw.cos() * new_pitch.cos(),
@@ -1188,31 +2562,470 @@ impl Scene {
         self.camera_target = self.orbit_center;
     }
 
-    fn render(&self, buffer: &mut Vec<u32>) {
-        let fov = std::f32::consts::PI / 3.0;
-        let aspect_ratio = WIDTH as f32 / HEIGHT as f32;
-
+    /// Vectores `forward`, `right` y `up` de la cámara.
+    fn camera_basis(&self) -> (Vector3<f32>, Vector3<f32>, Vector3<f32>) {
         let forward = (self.camera_target - self.camera_pos).normalize();
         let right = forward.cross(&Vector3::new(0.0, 1.0, 0.0)).normalize();
         let up = right.cross(&forward);
+        (forward, right, up)
+    }
+
+    /*
+    Documentación (bloque):
+    Sección: Render con supermuestreo
+    Descripción: Con `samples <= 1` lanza un rayo por el centro de cada píxel (modo
+    interactivo). Con más muestras las reparte por el soporte del filtro con un muestreo
+    estratificado n-rooks: cada muestra ocupa su propia franja horizontal y vertical, con
+    una posición aleatoria dentro de ella. El color del píxel es la media ponderada por
+    `PixelFilter::weight`. Las semillas dependen solo del píxel, así que el resultado es
+    estable entre frames.
+    */
+    fn render(&self, hdr: &mut [Vector3<f32>], width: usize, height: usize, samples: usize) {
+        let fov = std::f32::consts::PI / 3.0;
+        let aspect_ratio = width as f32 / height as f32;
+
+        let (forward, right, up) = self.camera_basis();
 
         let tan_half_fov = (fov * 0.5).tan();
 
-        let x_factors: Vec<f32> = (0..WIDTH).map(|i| {
-            (2.0 * (i as f32 + 0.5) / WIDTH as f32 - 1.0) * tan_half_fov * aspect_ratio
-        }).collect();
-        let y_factors: Vec<f32> = (0..HEIGHT).map(|j| {
-            -(2.0 * (j as f32 + 0.5) / HEIGHT as f32 - 1.0) * tan_half_fov
-        }).collect();
+        let primary_ray = |px: f32, py: f32| {
+            let x = (2.0 * px / width as f32 - 1.0) * tan_half_fov * aspect_ratio;
+            let y = -(2.0 * py / height as f32 - 1.0) * tan_half_fov;
+            (forward + right * x + up * y).normalize()
+        };
+        let cone = RayCone::primary(tan_half_fov, height);
+        let trace = |ray_dir: Vector3<f32>| {
+            cast_ray(&self.camera_pos, &ray_dir, cone, &self.world, &self.texture_manager, &self.light, &self.sky)
+        };
+
+        if samples <= 1 {
+            hdr.par_chunks_mut(width).enumerate().for_each(|(j, row)| {
+                for (i, pixel) in row.iter_mut().enumerate() {
+                    *pixel = trace(primary_ray(i as f32 + 0.5, j as f32 + 0.5));
+                }
+            });
+            return;
+        }
+
+        let filter = self.pixel_filter;
+        let radius = filter.radius();
+        hdr.par_chunks_mut(width).enumerate().for_each(|(j, row)| {
+            let mut columns: Vec<usize> = (0..samples).collect();
+            for (i, pixel) in row.iter_mut().enumerate() {
+                let mut rng = Rng::new(((j * width + i) as u32).wrapping_mul(0x9E37_79B9));
+                for k in (1..samples).rev() {
+                    columns.swap(k, rng.index(k + 1));
+                }
+
+                let mut sum = Vector3::zeros();
+                let mut weight_sum = 0.0;
+                for (s, &column) in columns.iter().enumerate() {
+                    let dx = ((s as f32 + rng.next_f32()) / samples as f32 * 2.0 - 1.0) * radius;
+                    let dy = ((column as f32 + rng.next_f32()) / samples as f32 * 2.0 - 1.0) * radius;
+                    let weight = filter.weight(dx, dy);
+                    if weight <= 0.0 {
+                        continue;
+                    }
+                    sum += trace(primary_ray(i as f32 + 0.5 + dx, j as f32 + 0.5 + dy)) * weight;
+                    weight_sum += weight;
+                }
+                *pixel = if weight_sum > 0.0 { sum / weight_sum } else { sum };
+            }
+        });
+    }
+
+    /*
+    Documentación (bloque):
+    Sección: Render progresivo (path tracing)
+    Descripción: Traza un camino por píxel, con una posición aleatoria dentro del soporte
+    del filtro de reconstrucción, y lo suma al acumulador junto con su peso; el buffer
+    muestra la media ponderada de todas las muestras. Mientras la
+    cámara y la hora no cambien la imagen converge frame a frame; cualquier
+    cambio vacía el acumulador. La semilla de cada muestra depende del píxel y del número
+    de frame, así que dos renders con los mismos parámetros dan la misma imagen.
+    */
+    fn render_progressive(&mut self, hdr: &mut [Vector3<f32>], width: usize, height: usize) {
+        let view = (self.camera_pos, self.camera_target, self.time, self.sky_settings);
+        if self.accumulated_view != Some(view) || self.accumulation.len() != width * height {
+            self.accumulation = vec![(Vector3::zeros(), 0.0); width * height];
+            self.accumulated_frames = 0;
+            self.accumulated_view = Some(view);
+        }
 
-        buffer.par_chunks_mut(WIDTH).enumerate().for_each(|(j, row)| {
-            let y = y_factors[j];
-            for i in 0..WIDTH {
-                let x = x_factors[i];
+        let fov = std::f32::consts::PI / 3.0;
+        let aspect_ratio = width as f32 / height as f32;
+        let tan_half_fov = (fov * 0.5).tan();
+        let cone = RayCone::primary(tan_half_fov, height);
+        let (forward, right, up) = self.camera_basis();
+
+        let frame = self.accumulated_frames;
+        let filter = self.pixel_filter;
+        let radius = filter.radius();
+        let (world, texture_manager, light) = (&self.world, &self.texture_manager, &self.light);
+        let (camera_pos, sky) = (self.camera_pos, &self.sky);
+
+        self.accumulation.par_chunks_mut(width).zip(hdr.par_chunks_mut(width)).enumerate().for_each(|(j, (sums, row))| {
+            for (i, ((sum, weight_sum), pixel)) in sums.iter_mut().zip(row.iter_mut()).enumerate() {
+                let pixel_index = (j * width + i) as u32;
+                let mut rng = Rng::new(pixel_index.wrapping_mul(0x9E37_79B9) ^ frame.wrapping_mul(0x85EB_CA6B));
+
+                let dx = (rng.next_f32() * 2.0 - 1.0) * radius;
+                let dy = (rng.next_f32() * 2.0 - 1.0) * radius;
+                let x = (2.0 * (i as f32 + 0.5 + dx) / width as f32 - 1.0) * tan_half_fov * aspect_ratio;
+                let y = -(2.0 * (j as f32 + 0.5 + dy) / height as f32 - 1.0) * tan_half_fov;
                 let ray_dir = (forward + right * x + up * y).normalize();
-                row[i] = cast_ray(&self.camera_pos, &ray_dir, &self.world, &self.texture_manager, &self.light_pos, self.is_day);
+
+                let weight = filter.weight(dx, dy);
+                if weight > 0.0 {
+                    let sample = trace_path(camera_pos, ray_dir, cone, world, texture_manager, light, sky, &mut rng);
+                    if sample.iter().all(|c| c.is_finite()) {
+                        *sum += sample * weight;
+                        *weight_sum += weight;
+                    }
+                }
+                if *weight_sum > 0.0 {
+                    *pixel = *sum / *weight_sum;
+                }
             }
         });
+
+        self.accumulated_frames += 1;
+    }
+
+    /*
+    Documentación (bloque):
+    Sección: Render a imagen
+    Descripción: Renderiza un frame con `samples` muestras por píxel (en modo path tracing,
+    acumulando `samples` frames progresivos), lo revela con `develop` y convierte el buffer `0xAARRGGBB` que usa la ventana en una `RgbImage`,
+    lista para guardarse como PNG sin necesidad de display.
+    */
+    fn render_to_image(&mut self, width: usize, height: usize, samples: usize) -> RgbImage {
+        let mut hdr = vec![Vector3::zeros(); width * height];
+        if self.path_trace {
+            for _ in 0..samples.max(1) {
+                self.render_progressive(&mut hdr, width, height);
+            }
+        } else {
+            self.render(&mut hdr, width, height, samples);
+        }
+        let mut buffer = vec![0u32; width * height];
+        self.develop.develop(&hdr, &mut buffer);
+
+        let mut img = RgbImage::new(width as u32, height as u32);
+        for (pixel, &packed) in img.pixels_mut().zip(buffer.iter()) {
+            *pixel = Rgb([(packed >> 16) as u8, (packed >> 8) as u8, packed as u8]);
+        }
+        img
+    }
+}
+
+/*
+Documentación (bloque):
+Sección: Opciones de línea de comandos
+Descripción: Argumentos aceptados por el binario. Permiten elegir la resolución, las
+carpetas de capas y texturas, la órbita inicial de la cámara (la altura es relativa al
+centro del diorama) y la hora de inicio, sin tener que recompilar.
+`--headless <salida.png>` renderiza un frame sin abrir ventana.
+*/
+struct CliOptions {
+    width: usize,
+    height: usize,
+    world_dir: String,
+    texture_dir: String,
+    orbit_yaw: f32,
+    orbit_radius: f32,
+    orbit_height: f32,
+    time: TimeOfDay,
+    day_length: f32,
+    day_cycle: bool,
+    site: Option<Site>,
+    sky: SkySettings,
+    environment_path: Option<String>,
+    import_path: Option<String>,
+    vox_mapping: Option<String>,
+    export_path: Option<String>,
+    strict: bool,
+    validate_only: bool,
+    registry_path: String,
+    headless_output: Option<String>,
+    light_radius: f32,
+    shadow_samples: usize,
+    path_trace: bool,
+    samples: usize,
+    aa_samples: usize,
+    interactive_samples: usize,
+    pixel_filter: PixelFilter,
+    texture_filter: TextureFilter,
+    develop: Develop,
+    bench: bool,
+}
+
+const USAGE: &str = "Uso: cubo [opciones]
+  --width <px>            Ancho de la imagen (por defecto 600)
+  --height <px>           Alto de la imagen (por defecto 450)
+  --capas <dir>           Carpeta con las capas del mundo (por defecto capas/)
+  --textures <dir>        Carpeta de texturas (por defecto textures/)
+  --bloques <archivo>     Definición de bloques (por defecto bloques.txt)
+  --orbit-yaw <rad>       Ángulo inicial de la órbita (por defecto 0.8)
+  --orbit-radius <n>      Radio inicial de la órbita (por defecto 13)
+  --orbit-height <n>      Altura de la cámara sobre el centro (por defecto 6)
+  --day | --night         Arrancar de día (10:00) o de noche (22:00) (por defecto día)
+  --time <h>              Hora de inicio, de 0 a 24
+  --day-cycle             Arrancar con el ciclo día/noche en marcha
+  --day-length <s>        Segundos que dura un día completo del ciclo (por defecto 120)
+  --moon-phase <f>        Fase de la luna: 0 nueva, 0.25 creciente, 0.5 llena (por defecto 0.5)
+  --latitude <grados>     Calcular el sol para esta latitud (norte positiva)
+  --longitude <grados>    Longitud del lugar (este positiva; por defecto 0)
+  --date <AAAA-MM-DD>     Fecha para la posición del sol (por defecto el solsticio de junio)
+  --utc-offset <h>        Diferencia de la hora local con UTC (por defecto longitud / 15)
+  --sky <modelo>          Cielo: gradient | atmosphere | environment (por defecto gradient)
+  --turbidity <t>         Turbidez de la atmósfera, de 1 (aire puro) a 20 (por defecto 2)
+  --ground-albedo <a>     Albedo del suelo para la atmósfera, de 0 a 1 (por defecto 0.2)
+  --env <archivo.hdr>     Usar un mapa de entorno equirectangular HDR como cielo y luz
+  --env-rotation <grados> Girar el mapa de entorno alrededor del eje vertical (por defecto 0)
+  --env-intensity <k>     Multiplicar la radiancia del mapa de entorno (por defecto 1)
+  --cloud-altitude <n>    Altura de la capa de nubes sobre el suelo, en bloques (por defecto 64)
+  --cloud-coverage <c>    Fracción del cielo cubierta por nubes, de 0 a 1 (por defecto 0: sin nubes)
+  --cloud-density <d>     Opacidad de las nubes (por defecto 1)
+  --wind <v>              Velocidad del viento que mueve las nubes, en bloques/s (por defecto 3)
+  --light-radius <n>      Radio de la luz; 0 da sombras duras (por defecto 0)
+  --shadow-samples <n>    Rayos de sombra por punto con luz de radio > 0 (por defecto 8)
+  --path-trace            Arrancar en modo path tracing progresivo
+  --samples <n>           Muestras por píxel del path tracing con --headless (por defecto 64)
+  --aa <n>                Muestras por píxel de las capturas con --headless (por defecto 16)
+  --aa-interactive <n>    Muestras por píxel en la ventana (por defecto 1)
+  --filter <f>            Filtro de reconstrucción: box | tent | gauss (por defecto tent)
+  --texture-filter <f>    Filtro de texturas: nearest | bilinear | trilinear (por defecto trilinear)
+  --tone-map <op>         Tone mapping: reinhard | aces | filmic (por defecto aces)
+  --exposure <ev>         Compensación de exposición en pasos (por defecto 0)
+  --auto-exposure         Ajustar la exposición al histograma de cada frame
+  --import <archivo>      Cargar el mundo desde un fichero (.vox, .schem, .nbt) en lugar de capas
+  --vox-map <archivo>     Tabla índice de paleta -> carácter de bloque para .vox
+  --export <archivo>      Exportar el mundo cargado (.vox, .schem, .nbt) y salir
+  --strict                No arrancar si las capas tienen errores
+  --validate              Revisar las capas, listar los errores y salir
+  --headless <salida.png> Renderizar un frame a PNG sin abrir ventana
+  --bench                 Medir el raycasting en mundos grandes generados y salir
+  --help                  Mostrar esta ayuda";
+
+impl CliOptions {
+    fn parse<I: Iterator<Item = String>>(mut args: I) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut options = CliOptions {
+            width: DEFAULT_WIDTH,
+            height: DEFAULT_HEIGHT,
+            world_dir: "capas".to_string(),
+            texture_dir: "textures".to_string(),
+            orbit_yaw: DEFAULT_ORBIT_YAW,
+            orbit_radius: DEFAULT_ORBIT_RADIUS,
+            orbit_height: DEFAULT_ORBIT_HEIGHT,
+            time: TimeOfDay::DAY,
+            day_length: DEFAULT_DAY_LENGTH,
+            day_cycle: false,
+            site: None,
+            sky: SkySettings {
+                model: SkyModel::Gradient,
+                turbidity: 2.0,
+                ground_albedo: 0.2,
+                environment_rotation: 0.0,
+                environment_intensity: 1.0,
+                clouds: CloudSettings { altitude: 64.0, coverage: 0.0, density: 1.0, wind_speed: 3.0 },
+            },
+            environment_path: None,
+            import_path: None,
+            vox_mapping: None,
+            export_path: None,
+            strict: false,
+            validate_only: false,
+            registry_path: registry::DEFAULT_REGISTRY_PATH.to_string(),
+            headless_output: None,
+            light_radius: 0.0,
+            shadow_samples: 8,
+            path_trace: false,
+            samples: 64,
+            aa_samples: 16,
+            interactive_samples: 1,
+            pixel_filter: PixelFilter::Tent,
+            texture_filter: TextureFilter::Trilinear,
+            develop: Develop { tone_map: ToneMap::Aces, exposure: 0.0, auto_exposure: false },
+            bench: false,
+        };
+
+        let (mut latitude, mut longitude, mut date, mut utc_offset) = (None, None, None, None);
+        let mut moon_phase = FULL_MOON;
+        while let Some(arg) = args.next() {
+            let mut value = |name: &str| args.next().ok_or_else(|| format!("falta el valor de {}", name));
+            match arg.as_str() {
+                "--width" => options.width = value("--width")?.parse()?,
+                "--height" => options.height = value("--height")?.parse()?,
+                "--capas" => options.world_dir = value("--capas")?,
+                "--textures" => options.texture_dir = value("--textures")?,
+                "--orbit-yaw" => options.orbit_yaw = value("--orbit-yaw")?.parse()?,
+                "--orbit-radius" => options.orbit_radius = value("--orbit-radius")?.parse()?,
+                "--orbit-height" => options.orbit_height = value("--orbit-height")?.parse()?,
+                "--day" => options.time = TimeOfDay::DAY,
+                "--night" => options.time = TimeOfDay::NIGHT,
+                "--time" => options.time = TimeOfDay::new(value("--time")?.parse()?),
+                "--day-length" => options.day_length = value("--day-length")?.parse()?,
+                "--day-cycle" => options.day_cycle = true,
+                "--moon-phase" => moon_phase = value("--moon-phase")?.parse()?,
+                "--latitude" => latitude = Some(value("--latitude")?.parse::<f32>()?),
+                "--longitude" => longitude = Some(value("--longitude")?.parse::<f32>()?),
+                "--utc-offset" => utc_offset = Some(value("--utc-offset")?.parse::<f32>()?),
+                "--date" => {
+                    let text = value("--date")?;
+                    date = Some(day_of_year(&text).ok_or_else(|| format!("fecha no válida: {} (usa AAAA-MM-DD)", text))?);
+                }
+                "--sky" => {
+                    let name = value("--sky")?;
+                    options.sky.model = SkyModel::parse(&name)
+                        .ok_or_else(|| format!("cielo desconocido: {} (usa gradient, atmosphere o environment)", name))?;
+                }
+                "--turbidity" => options.sky.turbidity = value("--turbidity")?.parse()?,
+                "--ground-albedo" => options.sky.ground_albedo = value("--ground-albedo")?.parse()?,
+                "--env" => {
+                    options.environment_path = Some(value("--env")?);
+                    options.sky.model = SkyModel::Environment;
+                }
+                "--env-rotation" => options.sky.environment_rotation = value("--env-rotation")?.parse()?,
+                "--env-intensity" => options.sky.environment_intensity = value("--env-intensity")?.parse()?,
+                "--cloud-altitude" => options.sky.clouds.altitude = value("--cloud-altitude")?.parse()?,
+                "--cloud-coverage" => options.sky.clouds.coverage = value("--cloud-coverage")?.parse()?,
+                "--cloud-density" => options.sky.clouds.density = value("--cloud-density")?.parse()?,
+                "--wind" => options.sky.clouds.wind_speed = value("--wind")?.parse()?,
+                "--import" => options.import_path = Some(value("--import")?),
+                "--vox-map" => options.vox_mapping = Some(value("--vox-map")?),
+                "--export" => options.export_path = Some(value("--export")?),
+                "--strict" => options.strict = true,
+                "--validate" => options.validate_only = true,
+                "--bloques" => options.registry_path = value("--bloques")?,
+                "--headless" => options.headless_output = Some(value("--headless")?),
+                "--light-radius" => options.light_radius = value("--light-radius")?.parse()?,
+                "--shadow-samples" => options.shadow_samples = value("--shadow-samples")?.parse()?,
+                "--path-trace" => options.path_trace = true,
+                "--samples" => options.samples = value("--samples")?.parse()?,
+                "--aa" => options.aa_samples = value("--aa")?.parse()?,
+                "--aa-interactive" => options.interactive_samples = value("--aa-interactive")?.parse()?,
+                "--bench" => options.bench = true,
+                "--filter" => {
+                    let name = value("--filter")?;
+                    options.pixel_filter = PixelFilter::parse(&name)
+                        .ok_or_else(|| format!("filtro desconocido: {} (usa box, tent o gauss)", name))?;
+                }
+                "--texture-filter" => {
+                    let name = value("--texture-filter")?;
+                    options.texture_filter = TextureFilter::parse(&name)
+                        .ok_or_else(|| format!("filtro de texturas desconocido: {} (usa nearest, bilinear o trilinear)", name))?;
+                }
+                "--tone-map" => {
+                    let name = value("--tone-map")?;
+                    options.develop.tone_map = ToneMap::parse(&name)
+                        .ok_or_else(|| format!("tone mapping desconocido: {} (usa reinhard, aces o filmic)", name))?;
+                }
+                "--exposure" => options.develop.exposure = value("--exposure")?.parse()?,
+                "--auto-exposure" => options.develop.auto_exposure = true,
+                "--help" | "-h" => {
+                    println!("{}", USAGE);
+                    std::process::exit(0);
+                }
+                other => return Err(format!("argumento desconocido: {}\n{}", other, USAGE).into()),
+            }
+        }
+
+        if options.width == 0 || options.height == 0 {
+            return Err("la resolución debe ser mayor que cero".into());
+        }
+        if latitude.is_some() || longitude.is_some() || date.is_some() {
+            let longitude = longitude.unwrap_or(0.0);
+            options.site = Some(Site {
+                latitude: latitude.unwrap_or(0.0),
+                longitude,
+                day_of_year: date.unwrap_or(SUMMER_SOLSTICE),
+                utc_offset: utc_offset.unwrap_or((longitude / 15.0).round()),
+            });
+        }
+        if !(0.0..=1.0).contains(&moon_phase) {
+            return Err("la fase de la luna debe estar entre 0 y 1".into());
+        }
+        options.time = options.time.with_moon_phase(moon_phase);
+        if !(1.0..=20.0).contains(&options.sky.turbidity) {
+            return Err("la turbidez debe estar entre 1 y 20".into());
+        }
+        if !(0.0..=1.0).contains(&options.sky.ground_albedo) {
+            return Err("el albedo del suelo debe estar entre 0 y 1".into());
+        }
+        if options.sky.model == SkyModel::Environment && options.environment_path.is_none() {
+            return Err("el cielo environment necesita un mapa de entorno (--env <archivo.hdr>)".into());
+        }
+        if !(0.0..=1.0).contains(&options.sky.clouds.coverage) {
+            return Err("la cobertura de nubes debe estar entre 0 y 1".into());
+        }
+        if options.sky.clouds.density < 0.0 {
+            return Err("la densidad de las nubes no puede ser negativa".into());
+        }
+        if options.sky.environment_intensity < 0.0 {
+            return Err("la intensidad del mapa de entorno no puede ser negativa".into());
+        }
+        if options.day_length <= 0.0 {
+            return Err("la duración del día debe ser mayor que cero".into());
+        }
+        if options.light_radius < 0.0 {
+            return Err("el radio de la luz no puede ser negativo".into());
+        }
+
+        Ok(options)
+    }
+}
+
+/// Extensión en minúsculas de una ruta, o cadena vacía si no tiene.
+fn file_extension(path: &str) -> String {
+    std::path::Path::new(path)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .unwrap_or("")
+        .to_ascii_lowercase()
+}
+
+/*
+Documentación (bloque):
+Sección: Modo headless
+Descripción: Carga la escena, renderiza un frame con la cámara elegida y lo guarda
+como PNG. Pensado para servidores sin display; cualquier error de carga o escritura
+se propaga para que `main` termine con estado distinto de cero.
+*/
+fn run_headless(options: &CliOptions, output: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let mut scene = Scene::new(options)?;
+
+    let start = std::time::Instant::now();
+    let samples = if options.path_trace { options.samples } else { options.aa_samples };
+    let img = scene.render_to_image(options.width, options.height, samples);
+    img.save(output).map_err(|e| format!("no se pudo escribir {}: {}", output, e))?;
+
+    println!("✓ Frame guardado en {} ({}x{}, {:.2}s)", output, options.width, options.height, start.elapsed().as_secs_f32());
+    Ok(())
+}
+
+/*
+Documentación (bloque):
+Sección: Teclas de alternancia
+Descripción: Recuerda si una tecla estaba pulsada en el frame anterior para que los
+toggles del bucle principal se disparen una sola vez por pulsación y no en cada frame
+mientras se mantiene apretada.
+*/
+struct KeyLatch {
+    key: Key,
+    was_down: bool,
+}
+
+impl KeyLatch {
+    fn new(key: Key) -> Self {
+        KeyLatch { key, was_down: false }
+    }
+
+    fn pressed(&mut self, window: &Window) -> bool {
+        let down = window.is_key_down(self.key);
+        let pressed = down && !self.was_down;
+        self.was_down = down;
+        pressed
     }
 }
 
@@ -1222,26 +3035,84 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     Sección: Punto de entrada
     Descripción: Inicializa la ventana, carga el `Scene` (mundo + texturas) y
     entra en el bucle principal de eventos. Procesa entrada del usuario para
-    controlar la cámara orbital y el modo día/noche, renderizando cada frame.
+    controlar la cámara orbital y la hora del día, renderizando cada frame.
+    Con `--export` solo se convierte el mundo; con `--headless` se omite la ventana
+    y se delega en `run_headless`; `--bench` ejecuta el benchmark de raycasting.
     */
-    let mut buffer: Vec<u32> = vec![0; WIDTH * HEIGHT];
+    let options = match CliOptions::parse(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("✗ {}", e);
+            std::process::exit(2);
+        }
+    };
+
+    if let Err(e) = registry::install(&options.registry_path) {
+        eprintln!("✗ {}", e);
+        std::process::exit(1);
+    }
+
+    if options.bench {
+        if let Err(e) = bench::run() {
+            eprintln!("✗ {}", e);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    if options.validate_only {
+        match VoxelWorld::validate_dir(&options.world_dir) {
+            Ok(diagnostics) if diagnostics.is_empty() => {
+                println!("✓ Capas de '{}' sin errores", options.world_dir);
+                return Ok(());
+            }
+            Ok(diagnostics) => {
+                for diagnostic in &diagnostics {
+                    println!("{}", diagnostic);
+                }
+                eprintln!("✗ {} errores en las capas de '{}'", diagnostics.len(), options.world_dir);
+            }
+            Err(e) => eprintln!("✗ Validación fallida: {}", e),
+        }
+        std::process::exit(1);
+    }
+
+    if let Some(path) = &options.export_path {
+        if let Err(e) = VoxelWorld::load(&options).and_then(|world| world.save(path)) {
+            eprintln!("✗ Exportación fallida: {}", e);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    if let Some(output) = &options.headless_output {
+        if let Err(e) = run_headless(&options, output) {
+            eprintln!("✗ Render headless fallido: {}", e);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    let (width, height) = (options.width, options.height);
+    let mut buffer: Vec<u32> = vec![0; width * height];
+    let mut hdr: Vec<Vector3<f32>> = vec![Vector3::zeros(); width * height];
 
     let mut window = Window::new(
         "Minecraft Raytracer - Con Texturas",
-        WIDTH,
-        HEIGHT,
+        width,
+        height,
         WindowOptions::default(),
     )?;
 
     window.set_target_fps(60);
 
-    let mut scene = Scene::new()?;
+    let mut scene = Scene::new(&options)?;
     let _move_speed = 1.0;
     let _rotation_speed = 0.05;
 
     println!("==== MINECRAFT RAYTRACER CON TEXTURAS ====");
-    println!("Estructura cargada desde carpeta 'capas/'");
-    println!("Texturas cargadas desde carpeta 'textures/'");
+    println!("Estructura cargada desde carpeta '{}/'", options.world_dir);
+    println!("Texturas cargadas desde carpeta '{}/'", options.texture_dir);
 
 
     println!("\n==== INFORMACIÓN DE DEPURACIÓN ====");
@@ -1265,31 +3136,43 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     if block_count == 0 {
         println!("⚠️  ADVERTENCIA: No se encontraron bloques sólidos!");
-        println!("   Verifica que los archivos en 'capas/' contengan caracteres válidos");
+        println!("   Verifica que los archivos en '{}/' contengan caracteres válidos", options.world_dir);
     }
 
     println!("\n==== TEXTURAS ESPERADAS ====");
-    println!("En la carpeta 'textures/':");
-    println!("• terracota_naranja.png (n)");
-    println!("• netherrack.png (i)");
-    println!("• bloque_magma.png (l)");
-    println!("• terracota_normal.png (t)");
-    println!("• terracota_amarilla.png (a)");
-    println!("• obsidiana.png (o)");
-    println!("• lava.png (p)");
-    println!("• bloque_oro.png (y)");
-    println!("• cofre.png (c)");
-    println!("• piedra.png (e, s) - Compartida para escalera y slab");
-    println!("• crying_obsidian.png (k)");
-    println!("\n💡 Las escaleras y slabs usan la misma textura 'piedra.png'");
-    println!("   pero se mapea automáticamente según la forma del bloque");
+    println!("En la carpeta '{}/':", options.texture_dir);
+    for (_, block) in registry().blocks() {
+        let mut textures: Vec<&str> = Vec::new();
+        for texture in BlockFace::ALL.iter().filter_map(|&face| block.texture_for(face)) {
+            if !textures.contains(&texture) {
+                textures.push(texture);
+            }
+        }
+        if let Some(c) = block.layer_char {
+            if !c.is_whitespace() && !textures.is_empty() {
+                println!("• {} ({}) - {}", textures.join(", "), c, block.name);
+            }
+        }
+    }
+    println!("\n💡 Las escaleras y slabs pueden compartir textura con el bloque completo;");
+    println!("   se mapea automáticamente según la forma del bloque");
 
     println!("\n==== CONTROLES ====");
     println!("A/D - Rotar la cámara alrededor del diorama");
     println!("W/S - Acercar/Alejar la cámara (zoom)");
     println!("Q/E - Subir/bajar la cámara (altura)");
     println!("R - Resetear cámara a órbita inicial");
-    println!("N - Toggle día/noche");
+    println!("N - Saltar 12 horas (día/noche)");
+    println!(",/. - Retrasar/adelantar la hora");
+    println!("C - Poner en marcha/parar el ciclo día/noche");
+    println!("V - Cambiar modelo de cielo (gradient/atmosphere/environment con --env)");
+    println!("[/] - Girar el mapa de entorno");
+    println!("B - Mostrar/ocultar las nubes");
+    println!("P - Toggle path tracing (acumula muestras con la cámara quieta)");
+    println!("T - Cambiar filtro de texturas (nearest/bilinear/trilinear)");
+    println!("M - Cambiar tone mapping (reinhard/aces/filmic)");
+    println!("-/+ - Bajar/subir la exposición");
+    println!("X - Toggle exposición automática");
     println!("ESC - Salir");
 
     println!("\n==== CONSEJOS DE DEPURACIÓN ====");
@@ -1298,8 +3181,17 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     println!("• Presiona R para volver a la posición inicial");
     println!("• El contador de FPS muestra tu posición actual");
 
+    let mut key_n = KeyLatch::new(Key::N);
+    let mut key_p = KeyLatch::new(Key::P);
+    let mut key_t = KeyLatch::new(Key::T);
+    let mut key_m = KeyLatch::new(Key::M);
+    let mut key_x = KeyLatch::new(Key::X);
+    let mut key_c = KeyLatch::new(Key::C);
+    let mut key_v = KeyLatch::new(Key::V);
+    let mut key_b = KeyLatch::new(Key::B);
     let mut frame_count = 0;
     let start_time = std::time::Instant::now();
+    let mut last_frame = start_time;
 
     while window.is_open() && !window.is_key_down(Key::Escape) {
 
@@ -1336,14 +3228,88 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         }
 
 
-        static mut PREV_N: bool = false;
-        let curr_n = window.is_key_down(Key::N);
-        unsafe {
-            if curr_n && !PREV_N {
-                scene.is_day = !scene.is_day;
-                println!("Modo día: {}", scene.is_day);
+        if key_n.pressed(&window) {
+            scene.set_time(scene.time.advance(12.0));
+            println!("Hora: {:.1}", scene.time.hours());
+        }
+
+        let time_delta = 0.1_f32;
+        if window.is_key_down(Key::Comma) {
+            scene.set_time(scene.time.advance(-time_delta));
+        }
+        if window.is_key_down(Key::Period) {
+            scene.set_time(scene.time.advance(time_delta));
+        }
+
+        if key_c.pressed(&window) {
+            scene.day_cycle = !scene.day_cycle;
+            println!("Ciclo día/noche: {}", scene.day_cycle);
+        }
+
+        if key_v.pressed(&window) {
+            scene.sky_settings.model = scene.sky_settings.model.next();
+            if scene.sky_settings.model == SkyModel::Environment && scene.environment.is_none() {
+                scene.sky_settings.model = scene.sky_settings.model.next();
             }
-            PREV_N = curr_n;
+            scene.set_time(scene.time);
+            println!("Cielo: {:?}", scene.sky_settings.model);
+        }
+
+        if key_b.pressed(&window) {
+            let clouds = &mut scene.sky_settings.clouds;
+            clouds.coverage = if clouds.coverage > 0.0 {
+                0.0
+            } else if options.sky.clouds.coverage > 0.0 {
+                options.sky.clouds.coverage
+            } else {
+                DEFAULT_CLOUD_COVERAGE
+            };
+            scene.set_time(scene.time);
+            println!("Cobertura de nubes: {:.2}", scene.sky_settings.clouds.coverage);
+        }
+
+        let rotation_delta = 2.0_f32;
+        if scene.sky.has_environment() && window.is_key_down(Key::LeftBracket) {
+            scene.sky_settings.environment_rotation -= rotation_delta;
+            scene.set_time(scene.time);
+        }
+        if scene.sky.has_environment() && window.is_key_down(Key::RightBracket) {
+            scene.sky_settings.environment_rotation += rotation_delta;
+            scene.set_time(scene.time);
+        }
+
+        let now = std::time::Instant::now();
+        scene.advance_time((now - last_frame).as_secs_f32());
+        last_frame = now;
+
+        if key_p.pressed(&window) {
+            scene.path_trace = !scene.path_trace;
+            scene.accumulated_view = None;
+            println!("Path tracing: {}", scene.path_trace);
+        }
+
+        if key_t.pressed(&window) {
+            scene.texture_manager.filter = scene.texture_manager.filter.next();
+            scene.accumulated_view = None;
+            println!("Filtro de texturas: {:?}", scene.texture_manager.filter);
+        }
+
+        let exposure_delta = 0.05_f32;
+        if window.is_key_down(Key::Minus) {
+            scene.develop.exposure -= exposure_delta;
+        }
+        if window.is_key_down(Key::Equal) {
+            scene.develop.exposure += exposure_delta;
+        }
+
+        if key_m.pressed(&window) {
+            scene.develop.tone_map = scene.develop.tone_map.next();
+            println!("Tone mapping: {:?}", scene.develop.tone_map);
+        }
+
+        if key_x.pressed(&window) {
+            scene.develop.auto_exposure = !scene.develop.auto_exposure;
+            println!("Exposición automática: {}", scene.develop.auto_exposure);
         }
 
 
@@ -1351,17 +3317,483 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
 
 
-        scene.render(&mut buffer);
-        window.update_with_buffer(&buffer, WIDTH, HEIGHT)?;
+        if scene.path_trace {
+            scene.render_progressive(&mut hdr, width, height);
+        } else {
+            scene.render(&mut hdr, width, height, options.interactive_samples);
+        }
+        let exposure = scene.develop.develop(&hdr, &mut buffer);
+        window.update_with_buffer(&buffer, width, height)?;
 
         frame_count += 1;
         if frame_count % 60 == 0 {
             let elapsed = start_time.elapsed().as_secs_f32();
             let fps = frame_count as f32 / elapsed;
-            println!("FPS: {:.1} | Pos: ({:.1}, {:.1}, {:.1})",
-                     fps, scene.camera_pos.x, scene.camera_pos.y, scene.camera_pos.z);
+            println!("FPS: {:.1} | Pos: ({:.1}, {:.1}, {:.1}) | Hora: {:.1}",
+                     fps, scene.camera_pos.x, scene.camera_pos.y, scene.camera_pos.z, scene.time.hours());
+            if scene.path_trace {
+                println!("Muestras acumuladas: {}", scene.accumulated_frames);
+            }
+            if scene.develop.auto_exposure {
+                println!("Exposición automática: x{:.2}", exposure);
+            }
         }
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<CliOptions, Box<dyn std::error::Error>> {
+        CliOptions::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn sin_argumentos_se_usan_los_valores_por_defecto() {
+        let options = parse(&[]).unwrap();
+        assert_eq!((options.width, options.height), (DEFAULT_WIDTH, DEFAULT_HEIGHT));
+        assert_eq!((options.world_dir.as_str(), options.texture_dir.as_str()), ("capas", "textures"));
+        assert_eq!(options.registry_path, registry::DEFAULT_REGISTRY_PATH);
+        assert_eq!(options.orbit_yaw, DEFAULT_ORBIT_YAW);
+        assert_eq!(options.orbit_radius, DEFAULT_ORBIT_RADIUS);
+        assert_eq!(options.orbit_height, DEFAULT_ORBIT_HEIGHT);
+        assert!(options.headless_output.is_none() && options.import_path.is_none());
+        assert!(!options.strict && !options.validate_only && !options.path_trace && !options.bench);
+    }
+
+    #[test]
+    fn cada_opcion_llega_a_su_campo() {
+        let options = parse(&[
+            "--width", "320", "--height", "200", "--capas", "otras", "--textures", "tex",
+            "--orbit-yaw", "1.5", "--orbit-radius", "20", "--orbit-height", "-2",
+            "--headless", "salida.png", "--strict",
+        ]).unwrap();
+        assert_eq!((options.width, options.height), (320, 200));
+        assert_eq!((options.world_dir.as_str(), options.texture_dir.as_str()), ("otras", "tex"));
+        assert_eq!((options.orbit_yaw, options.orbit_radius, options.orbit_height), (1.5, 20.0, -2.0));
+        assert_eq!(options.headless_output.as_deref(), Some("salida.png"));
+        assert!(options.strict);
+    }
+
+    #[test]
+    fn una_opcion_sin_valor_da_error() {
+        for flag in ["--width", "--capas", "--orbit-yaw", "--headless"] {
+            let error = parse(&[flag]).err().unwrap().to_string();
+            assert_eq!(error, format!("falta el valor de {}", flag));
+        }
+    }
+
+    #[test]
+    fn un_numero_mal_escrito_da_error() {
+        assert!(parse(&["--width", "ancho"]).is_err());
+        assert!(parse(&["--width", "-5"]).is_err());
+        assert!(parse(&["--orbit-radius", "1,5"]).is_err());
+    }
+
+    #[test]
+    fn rechaza_opciones_desconocidas_y_resoluciones_nulas() {
+        let error = parse(&["--anchura", "10"]).err().unwrap().to_string();
+        assert!(error.starts_with("argumento desconocido: --anchura"), "{}", error);
+        assert!(parse(&["--width", "0"]).is_err());
+        assert!(parse(&["--height", "0"]).is_err());
+    }
+
+    /// Carpeta temporal propia de la prueba con los ficheros `(nombre, contenido)` dados.
+    fn temp_dir_with(files: &[(&str, &str)]) -> String {
+        let dir = crate::test_util::temp_path("capas");
+        fs::create_dir_all(&dir).unwrap();
+        for (name, content) in files {
+            fs::write(std::path::Path::new(&dir).join(name), content).unwrap();
+        }
+        dir
+    }
+
+    fn layers(contents: &[&str]) -> Vec<String> {
+        contents.iter().map(|layer| layer.to_string()).collect()
+    }
+
+    #[test]
+    fn descubre_las_capas_numeradas_aunque_falten_intermedias() {
+        let dir = temp_dir_with(&[
+            ("0.txt", "i"), ("2.txt", "i"), ("5.txt", "i"),
+            ("1.estado.txt", "n"), ("notas.txt", "x"), ("3.png", ""),
+        ]);
+        assert_eq!(VoxelWorld::discover_layers(&dir).unwrap(), vec![0, 2, 5]);
+
+        let files = VoxelWorld::read_layer_files(&dir).unwrap();
+        fs::remove_dir_all(&dir).ok();
+        let present: Vec<bool> = files.iter().map(|file| file.content.is_some()).collect();
+        assert_eq!(present, [true, false, true, false, false, true]);
+        assert!(files[1].states.is_some());
+    }
+
+    #[test]
+    fn una_carpeta_sin_capas_da_error() {
+        let dir = temp_dir_with(&[("notas.txt", "x")]);
+        let result = VoxelWorld::read_layer_files(&dir);
+        fs::remove_dir_all(&dir).ok();
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn el_ancho_es_el_de_la_linea_mas_larga() {
+        let mut world = VoxelWorld::new();
+        world.load_from_layer_data(&layers(&["ii\niiii\ni", "i"]), &[]);
+        assert_eq!((world.width, world.height, world.depth), (4, 2, 3));
+        assert_eq!(world.get_block(3, 0, 1), BlockType::from_char('i'));
+        assert_eq!(world.get_block(2, 0, 0), BlockType::AIRE);
+        assert_eq!(world.get_block(1, 1, 0), BlockType::AIRE);
+    }
+
+    #[test]
+    fn la_profundidad_es_la_de_la_capa_con_mas_filas() {
+        let mut world = VoxelWorld::new();
+        world.load_from_layer_data(&layers(&["i\ni", "i\ni\ni\ni\n\n\n", ""]), &[]);
+        assert_eq!((world.width, world.height, world.depth), (1, 3, 4));
+        assert_eq!(world.get_block(0, 1, 3), BlockType::from_char('i'));
+        assert_eq!(world.get_block(0, 0, 3), BlockType::AIRE);
+    }
+
+    fn layer_file(path: &str, content: Option<&str>) -> LayerFile {
+        LayerFile { path: path.to_string(), content: content.map(str::to_string), states: None }
+    }
+
+    /// `(fichero, línea, columna)` de cada diagnóstico, en el orden en que se emiten.
+    fn positions(diagnostics: &[LayerDiagnostic]) -> Vec<(&str, usize, usize)> {
+        diagnostics.iter().map(|d| (d.path.as_str(), d.line, d.column)).collect()
+    }
+
+    #[test]
+    fn la_validacion_situa_cada_problema_en_su_fichero_linea_y_columna() {
+        let mut with_states = layer_file("3.txt", Some("iii"));
+        with_states.states = Some(("3.estado.txt".to_string(), "nx".to_string()));
+        let files = [
+            layer_file("0.txt", Some("iii\niii\niiiii")),
+            layer_file("1.txt", None),
+            layer_file("2.txt", Some("i?i\ni\ti")),
+            with_states,
+        ];
+        let diagnostics = validate_layers(&files);
+        assert_eq!(positions(&diagnostics), [
+            ("0.txt", 3, 4),
+            ("1.txt", 0, 0),
+            ("2.txt", 1, 2),
+            ("2.txt", 2, 2),
+            ("3.estado.txt", 1, 2),
+        ]);
+        assert!(matches!(diagnostics[0].problem, LayerProblem::LineTooLong { length: 5, expected: 3 }));
+        assert!(matches!(diagnostics[1].problem, LayerProblem::MissingLayer));
+        assert!(matches!(diagnostics[2].problem, LayerProblem::UnknownChar('?')));
+        assert!(matches!(diagnostics[3].problem, LayerProblem::Tab));
+        assert!(matches!(diagnostics[4].problem, LayerProblem::UnknownChar('x')));
+        assert_eq!(diagnostics[2].to_string(), "2.txt:1:2: carácter desconocido '?'");
+    }
+
+    #[test]
+    fn unas_capas_correctas_no_dan_diagnosticos() {
+        let files = [layer_file("0.txt", Some("ii\n i")), layer_file("1.txt", Some("\nii"))];
+        assert!(validate_layers(&files).is_empty());
+    }
+
+    #[test]
+    fn con_strict_unas_capas_con_errores_no_se_cargan() {
+        let dir = temp_dir_with(&[("0.txt", "ii\ni?"), ("2.txt", "ii")]);
+        let strict = VoxelWorld::new().load_from_files(&dir, true);
+        let lenient = VoxelWorld::new().load_from_files(&dir, false);
+        fs::remove_dir_all(&dir).ok();
+        assert!(strict.is_err());
+        assert!(lenient.is_ok());
+    }
+
+    fn assert_close(a: &Vector3<f32>, b: &Vector3<f32>) {
+        assert!((a - b).norm() < 1e-5, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn to_world_deshace_to_canonical_en_toda_orientacion() {
+        let vectors = [
+            Vector3::new(1.0, 0.0, 0.0), Vector3::new(0.0, 1.0, 0.0), Vector3::new(0.0, 0.0, 1.0),
+            Vector3::new(0.3, -0.7, 0.2),
+        ];
+        for facing in Facing::ALL {
+            for top in [false, true] {
+                for double in [false, true] {
+                    let state = BlockState(BlockState::new(facing, top).0 | if double { BlockState::DOUBLE } else { 0 });
+                    assert_eq!((state.facing(), state.is_top(), state.is_double()), (facing, top, double));
+                    for v in &vectors {
+                        assert_close(&state.to_world(&state.to_canonical(v)), v);
+                        assert_close(&state.to_canonical(&state.to_world(v)), v);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn el_frente_canonico_mira_hacia_la_orientacion_del_voxel() {
+        let north = Vector3::new(0.0, 0.0, -1.0);
+        let expected = [(Facing::North, north), (Facing::East, Vector3::new(1.0, 0.0, 0.0)),
+                        (Facing::South, Vector3::new(0.0, 0.0, 1.0)), (Facing::West, Vector3::new(-1.0, 0.0, 0.0))];
+        for (facing, front) in expected {
+            assert_close(&BlockState::new(facing, false).to_world(&north), &front);
+        }
+        let up = Vector3::new(0.0, 1.0, 0.0);
+        assert_close(&BlockState::new(Facing::North, true).to_world(&up), &-up);
+    }
+
+    #[test]
+    fn los_caracteres_de_estado_fijan_orientacion_inversion_y_slab_doble() {
+        assert_eq!(BlockState::from_char(' '), Some(BlockState::default()));
+        assert_eq!(BlockState::from_char('.'), Some(BlockState::default()));
+        assert_eq!(BlockState::from_char('d'), Some(BlockState::double()));
+        assert_eq!(BlockState::from_char('e'), Some(BlockState::new(Facing::East, false)));
+        assert_eq!(BlockState::from_char('O'), Some(BlockState::new(Facing::West, true)));
+        assert_eq!(BlockState::from_char('x'), None);
+
+        let mut world = VoxelWorld::new();
+        world.load_from_layer_data(&layers(&["eee", "eee"]), &[Some("sN".to_string()), None]);
+        assert_eq!(world.get_state(0, 0, 0), BlockState::new(Facing::South, false));
+        assert_eq!(world.get_state(1, 0, 0), BlockState::new(Facing::North, true));
+        assert_eq!(world.get_state(2, 0, 0), BlockState::default());
+        assert_eq!(world.get_state(0, 1, 0), BlockState::default());
+    }
+
+    /// Primer bloque del registro, sin contar el aire, con la forma `shape`.
+    fn block_with_shape(shape: BlockShape) -> BlockType {
+        registry().blocks().find(|(block, def)| *block != BlockType::AIRE && def.shape == shape).map(|(block, _)| block).unwrap()
+    }
+
+    /// Mundo de un solo voxel con un bloque de forma `shape` y estado `state`.
+    fn single_block(shape: BlockShape, state: BlockState) -> VoxelWorld {
+        let mut world = VoxelWorld::with_dimensions(1, 1, 1);
+        world.set_block(0, 0, 0, block_with_shape(shape));
+        world.set_state(0, 0, 0, state);
+        world
+    }
+
+    /// Comprueba distancia, normal, UV y cara del impacto de un rayo contra el voxel (0, 0, 0).
+    fn assert_hit(world: &VoxelWorld, origin: [f32; 3], dir: [f32; 3], t: f32, normal: [f32; 3], uv: (f32, f32), face: BlockFace) {
+        let (origin, dir) = (Point3::from(origin), Vector3::from(dir));
+        let hit = world.intersect_voxel(0, 0, 0, &origin, &dir, 0.0).expect("el rayo debería impactar");
+        assert!((hit.0 - t).abs() < 1e-5, "t = {} en vez de {}", hit.0, t);
+        assert_close(&hit.1, &Vector3::from(normal));
+        assert!((hit.3.0 - uv.0).abs() < 1e-5 && (hit.3.1 - uv.1).abs() < 1e-5, "uv = {:?} en vez de {:?}", hit.3, uv);
+        assert_eq!(hit.4, face);
+    }
+
+    fn assert_miss(world: &VoxelWorld, origin: [f32; 3], dir: [f32; 3]) {
+        let hit = world.intersect_voxel(0, 0, 0, &Point3::from(origin), &Vector3::from(dir), 0.0);
+        assert!(hit.is_none(), "impacto inesperado en t = {:?}", hit.map(|h| h.0));
+    }
+
+    #[test]
+    fn la_escalera_se_intersecta_por_sus_dos_cajas() {
+        let stairs = single_block(BlockShape::Stairs, BlockState::default());
+        let down = [0.0, -1.0, 0.0];
+        assert_hit(&stairs, [0.5, 2.0, 0.25], down, 1.5, [0.0, 1.0, 0.0], (0.5, 0.75), BlockFace::Top);
+        assert_hit(&stairs, [0.5, 2.0, 0.75], down, 1.0, [0.0, 1.0, 0.0], (0.5, 0.25), BlockFace::Top);
+        assert_hit(&stairs, [0.5, 0.75, -1.0], [0.0, 0.0, 1.0], 1.5, [0.0, 0.0, -1.0], (0.5, 0.25), BlockFace::Front);
+        assert_hit(&stairs, [-1.0, 0.75, 0.75], [1.0, 0.0, 0.0], 1.0, [-1.0, 0.0, 0.0], (0.75, 0.25), BlockFace::Side);
+    }
+
+    #[test]
+    fn un_rayo_rasante_atraviesa_el_cuarto_que_le_falta_a_la_escalera() {
+        let stairs = single_block(BlockShape::Stairs, BlockState::default());
+        assert_miss(&stairs, [-1.0, 0.75, 0.25], [1.0, 0.0, 0.0]);
+        assert_miss(&stairs, [-1.0, 0.51, 0.49], [1.0, 0.0, 0.0]);
+
+        let mut world = VoxelWorld::with_dimensions(2, 1, 1);
+        world.set_block(0, 0, 0, block_with_shape(BlockShape::Stairs));
+        world.set_block(1, 0, 0, block_with_shape(BlockShape::Cube));
+        let hit = world.raycast_through(&Point3::new(-1.0, 0.75, 0.25), &Vector3::new(1.0, 0.0, 0.0), BlockType::AIRE).unwrap();
+        assert!((hit.0 - 2.0).abs() < 1e-4, "t = {}", hit.0);
+        assert_eq!(hit.2, block_with_shape(BlockShape::Cube));
+    }
+
+    #[test]
+    fn la_escalera_girada_sube_hacia_su_orientacion() {
+        let stairs = single_block(BlockShape::Stairs, BlockState::new(Facing::East, false));
+        let down = [0.0, -1.0, 0.0];
+        // Las UV se calculan en el marco canónico: la textura gira con el bloque.
+        assert_hit(&stairs, [0.25, 2.0, 0.5], down, 1.0, [0.0, 1.0, 0.0], (0.5, 0.25), BlockFace::Top);
+        assert_hit(&stairs, [0.75, 2.0, 0.5], down, 1.5, [0.0, 1.0, 0.0], (0.5, 0.75), BlockFace::Top);
+    }
+
+    #[test]
+    fn panel_alfombra_y_boton_se_intersectan_exactamente() {
+        let pane = single_block(BlockShape::Pane, BlockState::default());
+        assert_hit(&pane, [0.5, 0.5, -1.0], [0.0, 0.0, 1.0], 1.4375, [0.0, 0.0, -1.0], (0.5, 0.5), BlockFace::Front);
+        assert_hit(&pane, [-1.0, 0.5, 0.5], [1.0, 0.0, 0.0], 1.0, [-1.0, 0.0, 0.0], (0.5, 0.5), BlockFace::Side);
+        assert_miss(&pane, [-1.0, 0.5, 0.2], [1.0, 0.0, 0.0]);
+
+        let carpet = single_block(BlockShape::Carpet, BlockState::default());
+        assert_hit(&carpet, [0.5, 2.0, 0.5], [0.0, -1.0, 0.0], 1.9375, [0.0, 1.0, 0.0], (0.5, 0.5), BlockFace::Top);
+        assert_miss(&carpet, [-1.0, 0.5, 0.5], [1.0, 0.0, 0.0]);
+
+        let button = single_block(BlockShape::Button, BlockState::default());
+        assert_hit(&button, [0.5, 0.5, -1.0], [0.0, 0.0, 1.0], 1.875, [0.0, 0.0, -1.0], (0.5, 0.5), BlockFace::Front);
+        assert_miss(&button, [0.2, 0.5, -1.0], [0.0, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn saltar_el_vacio_no_cambia_los_impactos() {
+        let (cube, stairs) = (block_with_shape(BlockShape::Cube), block_with_shape(BlockShape::Stairs));
+        // Unos 400 bloques sueltos en 51 300 voxels: casi todo el recorrido cruza regiones y
+        // ladrillos vacíos, que es donde el salto y el paso a paso podrían discrepar.
+        let mut world = VoxelWorld::with_dimensions(45, 30, 38);
+        let mut rng = Rng::new(5);
+        for _ in 0..400 {
+            let [x, y, z] = [45, 30, 38].map(|side| (rng.next_u32() % side) as i32);
+            world.set_block(x, y, z, if rng.next_f32() < 0.5 { cube } else { stairs });
+            world.set_state(x, y, z, BlockState::new(Facing::ALL[(rng.next_u32() % 4) as usize], false));
+        }
+
+        let mut hits = 0;
+        for _ in 0..4000 {
+            let origin = Point3::new(rng.next_f32() * 65.0 - 10.0, rng.next_f32() * 50.0 - 10.0, rng.next_f32() * 58.0 - 10.0);
+            let dir = Vector3::new(rng.next_f32() - 0.5, rng.next_f32() - 0.5, rng.next_f32() - 0.5).normalize();
+            let skip = world.traverse(&origin, &dir, BlockType::AIRE, true);
+            let step = world.traverse(&origin, &dir, BlockType::AIRE, false);
+            match (skip, step) {
+                (Some(a), Some(b)) => {
+                    assert!((a.0 - b.0).abs() < 1e-4, "t = {} con salto, {} sin él", a.0, b.0);
+                    assert_eq!((a.1, a.2, a.4), (b.1, b.2, b.4));
+                    hits += 1;
+                }
+                (None, None) => {}
+                (a, b) => panic!("origen {:?}, dirección {:?}: {:?} con salto, {:?} sin él", origin, dir, a.map(|h| h.0), b.map(|h| h.0)),
+            }
+        }
+        assert!(hits > 200, "solo {} rayos impactan", hits);
+    }
+
+    /// Textura de 2×2 en escala de grises con la columna izquierda negra y la derecha blanca.
+    fn black_and_white() -> Texture {
+        Texture::from_image(RgbImage::from_fn(2, 2, |x, _| if x == 0 { Rgb([0, 0, 0]) } else { Rgb([255, 255, 255]) }))
+    }
+
+    #[test]
+    fn la_cadena_de_mipmaps_baja_hasta_un_texel_promediando() {
+        let sizes: Vec<_> = Texture::new(8, 2, Rgb([0, 0, 0])).levels.iter().map(|level| (level.width, level.height)).collect();
+        assert_eq!(sizes, [(8, 2), (4, 1), (2, 1), (1, 1)]);
+
+        // Damero 4×4: cada bloque de 2×2 promedia a gris medio, en lineal y no en sRGB.
+        let checker = Texture::from_image(RgbImage::from_fn(4, 4, |x, y| if (x + y) % 2 == 0 { Rgb([255, 255, 255]) } else { Rgb([0, 0, 0]) }));
+        assert_eq!(checker.levels.len(), 3);
+        for level in &checker.levels[1..] {
+            for texel in &level.texels {
+                assert_close(texel, &Vector3::new(0.5, 0.5, 0.5));
+            }
+        }
+
+        let columns = black_and_white();
+        assert_close(&columns.levels[1].texels[0], &Vector3::new(0.5, 0.5, 0.5));
+    }
+
+    #[test]
+    fn nearest_toma_un_texel_y_bilinear_interpola() {
+        let level = &black_and_white().levels[0];
+        let gray = |c: f32| Vector3::new(c, c, c);
+        // En el centro de cada texel ambos coinciden.
+        assert_close(&level.nearest(0.25, 0.5), &gray(0.0));
+        assert_close(&level.bilinear(0.25, 0.5), &gray(0.0));
+        assert_close(&level.bilinear(0.75, 0.5), &gray(1.0));
+        // Entre texels, nearest salta y bilinear mezcla en proporción a la distancia.
+        assert_close(&level.nearest(0.49, 0.5), &gray(0.0));
+        assert_close(&level.nearest(0.51, 0.5), &gray(1.0));
+        assert_close(&level.bilinear(0.5, 0.5), &gray(0.5));
+        assert_close(&level.bilinear(0.375, 0.1), &gray(0.25));
+        // Los bordes se extienden en lugar de repetir la columna del otro lado.
+        assert_close(&level.bilinear(0.0, 0.5), &gray(0.0));
+        assert_close(&level.bilinear(1.0, 0.5), &gray(1.0));
+    }
+
+    #[test]
+    fn la_huella_elige_el_nivel_de_mipmap() {
+        let texture = black_and_white();
+        let gray = |c: f32| Vector3::new(c, c, c);
+        for filter in [TextureFilter::Nearest, TextureFilter::Bilinear, TextureFilter::Trilinear] {
+            // Sin huella o con un texel por píxel, siempre el texel más cercano del nivel 0.
+            assert_close(&texture.sample(0.4, 0.5, 0.0, filter), &gray(0.0));
+            assert_close(&texture.sample(0.4, 0.5, 0.5, filter), &gray(0.0));
+        }
+        // Una huella de un bloque cubre los dos texels: lod 1, el nivel de 1×1.
+        assert_close(&texture.sample(0.4, 0.5, 1.0, TextureFilter::Nearest), &gray(0.0));
+        assert_close(&texture.sample(0.4, 0.5, 1.0, TextureFilter::Bilinear), &gray(0.5));
+        // A medio camino entre niveles (lod 0.5), trilinear mezcla el bilineal de ambos.
+        let fine = texture.levels[0].bilinear(0.4, 0.5);
+        assert_close(&texture.sample(0.4, 0.5, 2f32.sqrt() / 2.0, TextureFilter::Trilinear), &fine.lerp(&gray(0.5), 0.5));
+    }
+
+    #[test]
+    fn la_hora_recorre_el_arco_del_sol() {
+        let (sin, cos) = SUN_TILT.sin_cos();
+        let expected = [
+            (0.0, Vector3::new(0.0, -cos, -sin)),
+            (6.0, Vector3::new(1.0, 0.0, 0.0)),
+            (12.0, Vector3::new(0.0, cos, sin)),
+            (18.0, Vector3::new(-1.0, 0.0, 0.0)),
+        ];
+        for (hours, direction) in expected {
+            assert_close(&TimeOfDay::new(hours).sun_direction(), &direction);
+        }
+        assert!(TimeOfDay::DAY.daylight() > 0.99 && TimeOfDay::NIGHT.daylight() < 0.01);
+        // La luna llena va doce horas por detrás: justo enfrente del sol.
+        assert_close(&TimeOfDay::DAY.moon_direction(), &-TimeOfDay::DAY.sun_direction());
+    }
+
+    #[test]
+    fn avanzar_la_hora_da_la_vuelta_al_dia() {
+        assert_eq!(TimeOfDay::new(23.0).advance(2.0).hours(), 1.0);
+        assert_eq!(TimeOfDay::new(1.0).advance(-3.0).hours(), 22.0);
+        assert_eq!(TimeOfDay::new(-1.0).hours(), 23.0);
+        assert_eq!(TimeOfDay::new(30.0).hours(), 6.0);
+        assert_eq!(TimeOfDay::new(5.0).advance(-48.0).hours(), 5.0);
+
+        // La posición del sol no salta al cruzar la medianoche.
+        let mut time = TimeOfDay::new(23.5);
+        for _ in 0..10 {
+            let next = time.advance(0.1);
+            assert!((next.sun_direction() - time.sun_direction()).norm() < 0.03, "{} -> {}", time.hours(), next.hours());
+            time = next;
+        }
+        assert!((time.hours() - 0.5).abs() < 1e-4);
+    }
+
+    #[test]
+    fn el_sol_del_equinoccio_pasa_por_el_cenit_en_el_ecuador() {
+        let equator = Site { latitude: 0.0, longitude: 0.0, day_of_year: day_of_year("2023-03-20").unwrap(), utc_offset: 0.0 };
+        // Ecuación del tiempo y declinación de ese día apartan el sol poco más de 2° del cénit.
+        assert!(equator.sun_direction(12.0).y > 0.999, "{:?}", equator.sun_direction(12.0));
+
+        let morning = equator.sun_direction(8.0);
+        assert!(morning.x > 0.8 && morning.y > 0.4, "el sol de la mañana debería estar al este: {:?}", morning);
+        let evening = equator.sun_direction(16.0);
+        assert!(evening.x < -0.8 && evening.y > 0.4, "el de la tarde, al oeste: {:?}", evening);
+        assert!(equator.sun_direction(0.0).y < -0.99);
+
+        // En el hemisferio norte el sol de mediodía queda al sur (+z), como sin lugar.
+        let madrid = Site { latitude: 40.4, longitude: -3.7, day_of_year: 172, utc_offset: 2.0 };
+        let noon = madrid.sun_direction(14.0);
+        assert!(noon.z > 0.2 && noon.y > 0.9, "{:?}", noon);
+    }
+
+    #[test]
+    fn el_dia_del_ano_respeta_los_bisiestos() {
+        assert_eq!(day_of_year("2023-01-01"), Some(1));
+        assert_eq!(day_of_year("2023-03-01"), Some(60));
+        assert_eq!(day_of_year("2024-03-01"), Some(61));
+        assert_eq!(day_of_year("2023-12-31"), Some(365));
+        assert_eq!(day_of_year("2024-12-31"), Some(366));
+        assert_eq!(day_of_year("2024-02-29"), Some(60));
+        assert_eq!(day_of_year("2000-02-29"), Some(60));
+        assert_eq!(day_of_year("2023-02-29"), None);
+        assert_eq!(day_of_year("1900-02-29"), None);
+        for date in ["2023-04-31", "2023-13-01", "2023-00-10", "2023-01-00", "2023-01", "2023-01-01-01", "hoy"] {
+            assert_eq!(day_of_year(date), None, "{}", date);
+        }
+    }
+}
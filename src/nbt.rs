@@ -0,0 +1,429 @@
+/*
+Documentación (bloque):
+Sección: Lector/escritor NBT
+Descripción: Implementación mínima del formato binario NBT de Minecraft (big-endian),
+suficiente para los ficheros de estructuras y schematics. Los ficheros comprimidos con
+gzip se detectan por su cabecera al leer; al escribir siempre se comprime, que es lo
+que esperan tanto el juego como WorldEdit.
+*/
+
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::fs;
+use std::io::{Read, Write};
+
+const TAG_END: u8 = 0;
+const TAG_BYTE: u8 = 1;
+const TAG_SHORT: u8 = 2;
+const TAG_INT: u8 = 3;
+const TAG_LONG: u8 = 4;
+const TAG_FLOAT: u8 = 5;
+const TAG_DOUBLE: u8 = 6;
+const TAG_BYTE_ARRAY: u8 = 7;
+const TAG_STRING: u8 = 8;
+const TAG_LIST: u8 = 9;
+const TAG_COMPOUND: u8 = 10;
+const TAG_INT_ARRAY: u8 = 11;
+const TAG_LONG_ARRAY: u8 = 12;
+
+/// Límite de anidamiento para no desbordar la pila con ficheros corruptos.
+const MAX_DEPTH: usize = 512;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Tag {
+    Byte(i8),
+    Short(i16),
+    Int(i32),
+    Long(i64),
+    Float(f32),
+    Double(f64),
+    ByteArray(Vec<i8>),
+    String(String),
+    List(Vec<Tag>),
+    Compound(Vec<(String, Tag)>),
+    IntArray(Vec<i32>),
+    LongArray(Vec<i64>),
+}
+
+impl Tag {
+    fn id(&self) -> u8 {
+        match self {
+            Tag::Byte(_) => TAG_BYTE,
+            Tag::Short(_) => TAG_SHORT,
+            Tag::Int(_) => TAG_INT,
+            Tag::Long(_) => TAG_LONG,
+            Tag::Float(_) => TAG_FLOAT,
+            Tag::Double(_) => TAG_DOUBLE,
+            Tag::ByteArray(_) => TAG_BYTE_ARRAY,
+            Tag::String(_) => TAG_STRING,
+            Tag::List(_) => TAG_LIST,
+            Tag::Compound(_) => TAG_COMPOUND,
+            Tag::IntArray(_) => TAG_INT_ARRAY,
+            Tag::LongArray(_) => TAG_LONG_ARRAY,
+        }
+    }
+
+    /// Busca una clave dentro de un compound; `None` si no existe o no es un compound.
+    pub fn get(&self, key: &str) -> Option<&Tag> {
+        match self {
+            Tag::Compound(entries) => entries.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    /// Valor entero de cualquier tag numérico entero.
+    pub fn as_int(&self) -> Option<i64> {
+        match *self {
+            Tag::Byte(v) => Some(v as i64),
+            Tag::Short(v) => Some(v as i64),
+            Tag::Int(v) => Some(v as i64),
+            Tag::Long(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Tag::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_list(&self) -> Option<&[Tag]> {
+        match self {
+            Tag::List(items) => Some(items),
+            _ => None,
+        }
+    }
+
+    pub fn as_compound(&self) -> Option<&[(String, Tag)]> {
+        match self {
+            Tag::Compound(entries) => Some(entries),
+            _ => None,
+        }
+    }
+
+    pub fn as_byte_array(&self) -> Option<&[i8]> {
+        match self {
+            Tag::ByteArray(bytes) => Some(bytes),
+            _ => None,
+        }
+    }
+
+    pub fn as_int_array(&self) -> Option<Vec<i64>> {
+        match self {
+            Tag::IntArray(values) => Some(values.iter().map(|&v| v as i64).collect()),
+            Tag::List(items) => items.iter().map(Tag::as_int).collect(),
+            _ => None,
+        }
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, n: usize) -> Result<&'a [u8], Box<dyn std::error::Error>> {
+        let end = self.pos.checked_add(n).filter(|&end| end <= self.data.len())
+            .ok_or("fichero NBT truncado")?;
+        let slice = &self.data[self.pos..end];
+        self.pos = end;
+        Ok(slice)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], Box<dyn std::error::Error>> {
+        let mut out = [0u8; N];
+        out.copy_from_slice(self.bytes(N)?);
+        Ok(out)
+    }
+
+    fn u8(&mut self) -> Result<u8, Box<dyn std::error::Error>> {
+        Ok(self.array::<1>()?[0])
+    }
+
+    fn len(&mut self) -> Result<usize, Box<dyn std::error::Error>> {
+        let len = i32::from_be_bytes(self.array()?);
+        usize::try_from(len).map_err(|_| "longitud negativa en fichero NBT".into())
+    }
+
+    fn string(&mut self) -> Result<String, Box<dyn std::error::Error>> {
+        let len = u16::from_be_bytes(self.array()?) as usize;
+        Ok(String::from_utf8_lossy(self.bytes(len)?).into_owned())
+    }
+
+    fn payload(&mut self, id: u8, depth: usize) -> Result<Tag, Box<dyn std::error::Error>> {
+        if depth > MAX_DEPTH {
+            return Err("fichero NBT demasiado anidado".into());
+        }
+
+        Ok(match id {
+            TAG_BYTE => Tag::Byte(i8::from_be_bytes(self.array()?)),
+            TAG_SHORT => Tag::Short(i16::from_be_bytes(self.array()?)),
+            TAG_INT => Tag::Int(i32::from_be_bytes(self.array()?)),
+            TAG_LONG => Tag::Long(i64::from_be_bytes(self.array()?)),
+            TAG_FLOAT => Tag::Float(f32::from_be_bytes(self.array()?)),
+            TAG_DOUBLE => Tag::Double(f64::from_be_bytes(self.array()?)),
+            TAG_BYTE_ARRAY => {
+                let len = self.len()?;
+                Tag::ByteArray(self.bytes(len)?.iter().map(|&b| b as i8).collect())
+            }
+            TAG_STRING => Tag::String(self.string()?),
+            TAG_LIST => {
+                let item_id = self.u8()?;
+                let len = self.len()?;
+                let mut items = Vec::with_capacity(len.min(4096));
+                for _ in 0..len {
+                    items.push(self.payload(item_id, depth + 1)?);
+                }
+                Tag::List(items)
+            }
+            TAG_COMPOUND => {
+                let mut entries = Vec::new();
+                loop {
+                    let child_id = self.u8()?;
+                    if child_id == TAG_END {
+                        break;
+                    }
+                    let name = self.string()?;
+                    entries.push((name, self.payload(child_id, depth + 1)?));
+                }
+                Tag::Compound(entries)
+            }
+            TAG_INT_ARRAY => {
+                let len = self.len()?;
+                let mut values = Vec::with_capacity(len.min(4096));
+                for _ in 0..len {
+                    values.push(i32::from_be_bytes(self.array()?));
+                }
+                Tag::IntArray(values)
+            }
+            TAG_LONG_ARRAY => {
+                let len = self.len()?;
+                let mut values = Vec::with_capacity(len.min(4096));
+                for _ in 0..len {
+                    values.push(i64::from_be_bytes(self.array()?));
+                }
+                Tag::LongArray(values)
+            }
+            other => return Err(format!("tipo de tag NBT desconocido: {}", other).into()),
+        })
+    }
+}
+
+fn write_string(out: &mut Vec<u8>, s: &str) {
+    out.extend_from_slice(&(s.len() as u16).to_be_bytes());
+    out.extend_from_slice(s.as_bytes());
+}
+
+fn write_payload(out: &mut Vec<u8>, tag: &Tag) {
+    match tag {
+        Tag::Byte(v) => out.extend_from_slice(&v.to_be_bytes()),
+        Tag::Short(v) => out.extend_from_slice(&v.to_be_bytes()),
+        Tag::Int(v) => out.extend_from_slice(&v.to_be_bytes()),
+        Tag::Long(v) => out.extend_from_slice(&v.to_be_bytes()),
+        Tag::Float(v) => out.extend_from_slice(&v.to_be_bytes()),
+        Tag::Double(v) => out.extend_from_slice(&v.to_be_bytes()),
+        Tag::ByteArray(bytes) => {
+            out.extend_from_slice(&(bytes.len() as i32).to_be_bytes());
+            out.extend(bytes.iter().map(|&b| b as u8));
+        }
+        Tag::String(s) => write_string(out, s),
+        Tag::List(items) => {
+            out.push(items.first().map_or(TAG_END, Tag::id));
+            out.extend_from_slice(&(items.len() as i32).to_be_bytes());
+            for item in items {
+                write_payload(out, item);
+            }
+        }
+        Tag::Compound(entries) => {
+            for (name, value) in entries {
+                out.push(value.id());
+                write_string(out, name);
+                write_payload(out, value);
+            }
+            out.push(TAG_END);
+        }
+        Tag::IntArray(values) => {
+            out.extend_from_slice(&(values.len() as i32).to_be_bytes());
+            for v in values {
+                out.extend_from_slice(&v.to_be_bytes());
+            }
+        }
+        Tag::LongArray(values) => {
+            out.extend_from_slice(&(values.len() as i32).to_be_bytes());
+            for v in values {
+                out.extend_from_slice(&v.to_be_bytes());
+            }
+        }
+    }
+}
+
+/*
+Descripción: Lee un fichero NBT (comprimido o no) y devuelve el nombre y el valor del
+compound raíz.
+*/
+pub fn read_file(path: &str) -> Result<(String, Tag), Box<dyn std::error::Error>> {
+    let raw = fs::read(path).map_err(|e| format!("no se pudo leer {}: {}", path, e))?;
+
+    let data = if raw.starts_with(&[0x1f, 0x8b]) {
+        let mut decoded = Vec::new();
+        GzDecoder::new(raw.as_slice()).read_to_end(&mut decoded)
+            .map_err(|e| format!("{}: gzip inválido: {}", path, e))?;
+        decoded
+    } else {
+        raw
+    };
+
+    let mut reader = Reader { data: &data, pos: 0 };
+    if reader.u8()? != TAG_COMPOUND {
+        return Err(format!("{}: la raíz NBT no es un compound", path).into());
+    }
+    let name = reader.string()?;
+    let root = reader.payload(TAG_COMPOUND, 0)
+        .map_err(|e| format!("{}: {}", path, e))?;
+    Ok((name, root))
+}
+
+/// Escribe `root` como compound raíz con nombre `name`, comprimido con gzip.
+pub fn write_file(path: &str, name: &str, root: &Tag) -> Result<(), Box<dyn std::error::Error>> {
+    let mut data = Vec::new();
+    data.push(TAG_COMPOUND);
+    write_string(&mut data, name);
+    write_payload(&mut data, root);
+
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(&data)?;
+    let compressed = encoder.finish()?;
+
+    fs::write(path, compressed).map_err(|e| format!("no se pudo escribir {}: {}", path, e))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::temp_path;
+
+    /// Compound raíz con un valor de cada tipo, incluidos los casos límite.
+    fn every_tag() -> Tag {
+        Tag::Compound(vec![
+            ("byte".to_string(), Tag::Byte(-128)),
+            ("short".to_string(), Tag::Short(i16::MIN)),
+            ("int".to_string(), Tag::Int(i32::MAX)),
+            ("long".to_string(), Tag::Long(i64::MIN)),
+            ("float".to_string(), Tag::Float(-0.5)),
+            ("double".to_string(), Tag::Double(1e300)),
+            ("bytes".to_string(), Tag::ByteArray(vec![-1, 0, 127])),
+            ("cadena".to_string(), Tag::String("magma_ñandú".to_string())),
+            ("".to_string(), Tag::String(String::new())),
+            ("vacía".to_string(), Tag::List(Vec::new())),
+            ("lista".to_string(), Tag::List(vec![
+                Tag::Compound(vec![("a".to_string(), Tag::Int(1))]),
+                Tag::Compound(Vec::new()),
+            ])),
+            ("ints".to_string(), Tag::IntArray(vec![i32::MIN, 0, -1])),
+            ("longs".to_string(), Tag::LongArray(vec![i64::MAX])),
+        ])
+    }
+
+    fn encode(tag: &Tag) -> Vec<u8> {
+        let mut out = Vec::new();
+        write_payload(&mut out, tag);
+        out
+    }
+
+    fn decode(data: &[u8], id: u8) -> Result<Tag, Box<dyn std::error::Error>> {
+        Reader { data, pos: 0 }.payload(id, 0)
+    }
+
+    #[test]
+    fn cada_tipo_de_tag_se_lee_como_se_escribio() {
+        let tag = every_tag();
+        assert_eq!(decode(&encode(&tag), TAG_COMPOUND).unwrap(), tag);
+    }
+
+    #[test]
+    fn el_fichero_comprimido_conserva_nombre_y_raiz() {
+        let path = temp_path("ida_y_vuelta.nbt");
+        write_file(&path, "raíz", &every_tag()).unwrap();
+        let (name, root) = read_file(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+        assert_eq!(name, "raíz");
+        assert_eq!(root, every_tag());
+    }
+
+    #[test]
+    fn tambien_se_lee_sin_comprimir() {
+        let mut data = vec![TAG_COMPOUND];
+        write_string(&mut data, "");
+        data.extend(encode(&every_tag()));
+        let path = temp_path("sin_comprimir.nbt");
+        std::fs::write(&path, &data).unwrap();
+        let root = read_file(&path).unwrap().1;
+        std::fs::remove_file(&path).ok();
+        assert_eq!(root, every_tag());
+    }
+
+    #[test]
+    fn un_payload_truncado_da_error() {
+        let data = encode(&every_tag());
+        for len in 0..data.len() {
+            assert!(decode(&data[..len], TAG_COMPOUND).is_err(), "{} de {} bytes", len, data.len());
+        }
+    }
+
+    #[test]
+    fn un_fichero_truncado_o_corrupto_da_error() {
+        let path = temp_path("corrupto.nbt");
+        write_file(&path, "", &every_tag()).unwrap();
+        let compressed = std::fs::read(&path).unwrap();
+        let cases = [
+            compressed[..compressed.len() / 2].to_vec(),
+            vec![0x1f, 0x8b, 0, 0],
+            vec![TAG_LIST, 0, 0],
+            Vec::new(),
+        ];
+        for (i, data) in cases.iter().enumerate() {
+            std::fs::write(&path, data).unwrap();
+            assert!(read_file(&path).is_err(), "caso {}", i);
+        }
+        std::fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn rechaza_longitudes_negativas_y_tipos_desconocidos() {
+        let negative = (-1i32).to_be_bytes();
+        assert!(decode(&negative, TAG_BYTE_ARRAY).is_err());
+        assert!(decode(&negative, TAG_INT_ARRAY).is_err());
+        assert!(decode(&[TAG_INT, 0x80, 0, 0, 0], TAG_LIST).is_err());
+        assert!(decode(&[13, 0, 0], TAG_COMPOUND).is_err());
+        // Una lista no vacía de TAG_END no tiene payload que leer.
+        assert!(decode(&[TAG_END, 0, 0, 0, 1], TAG_LIST).is_err());
+    }
+
+    #[test]
+    fn una_longitud_enorme_no_reserva_memoria_antes_de_leer() {
+        let mut data = vec![TAG_LONG];
+        data.extend_from_slice(&i32::MAX.to_be_bytes());
+        assert!(decode(&data, TAG_LIST).is_err());
+        assert!(decode(&i32::MAX.to_be_bytes(), TAG_LONG_ARRAY).is_err());
+    }
+
+    #[test]
+    fn limita_el_anidamiento() {
+        // `depth` listas de un único elemento, cada una dentro de la anterior, y una vacía al fondo.
+        let nested = |depth: usize| {
+            let mut data = Vec::new();
+            for _ in 0..depth {
+                data.extend_from_slice(&[TAG_LIST, 0, 0, 0, 1]);
+            }
+            data.extend_from_slice(&[TAG_END, 0, 0, 0, 0]);
+            data
+        };
+        assert!(decode(&nested(MAX_DEPTH), TAG_LIST).is_ok());
+        assert!(decode(&nested(MAX_DEPTH + 1), TAG_LIST).is_err());
+    }
+}
@@ -0,0 +1,269 @@
+/*
+Documentación (bloque):
+Sección: Cielo nocturno (estrellas y luna)
+Descripción: Las estrellas son un catálogo fijo generado una sola vez con una semilla
+constante: cada una tiene una dirección en el cielo, una magnitud (muchas más débiles que
+brillantes, como en el cielo real) y un color según su temperatura. No se dibujan como
+píxeles sueltos sino como pequeñas gaussianas de área unidad con la anchura del píxel que
+las mira (`spread`), de modo que cada estrella aporta siempre la misma luz total a la
+imagen, sin parpadear ni moverse al cambiar la cámara, la resolución o al verla reflejada.
+Para no recorrer todo el catálogo en cada rayo, las estrellas se reparten en una rejilla
+3D sobre la esfera unidad y solo se miran las celdas cercanas a la dirección del rayo.
+
+La luna es un disco con una textura procedimental de mares (manchas oscuras fijas en su
+cara visible) y cráteres (ruido de valor), iluminado por el sol con Lambert sobre una
+esfera: la fase sale sola de la geometría, llena con el sol a la espalda y nueva con el
+sol detrás de ella. La parte en sombra conserva un leve brillo de luz cenicienta.
+*/
+
+use nalgebra::Vector3;
+use std::sync::OnceLock;
+
+use crate::noise::value_noise;
+use crate::tonemap::luminance;
+use crate::Rng;
+
+const STAR_COUNT: usize = 6000;
+const STAR_SEED: u32 = 0x5EED_57A2;
+/// Magnitudes de la estrella más brillante y de la más débil del catálogo.
+const BRIGHTEST_MAGNITUDE: f32 = -1.5;
+const FAINTEST_MAGNITUDE: f32 = 6.5;
+/// Pendiente de la cuenta de estrellas: hay `10^STAR_COUNT_SLOPE` veces más por magnitud.
+const STAR_COUNT_SLOPE: f32 = 0.45;
+/// Irradiancia de una estrella de magnitud 0.
+const MAGNITUDE_ZERO_FLUX: f32 = 1.5e-5;
+/// Anchura de la gaussiana de una estrella, en fracciones de la apertura del píxel, y límites.
+const STAR_BLUR: f32 = 0.6;
+const STAR_MIN_SIZE: f32 = 3e-4;
+const STAR_MAX_SIZE: f32 = 0.02;
+/// Celdas por eje de la rejilla que reparte las estrellas sobre [-1, 1]³.
+const GRID: usize = 24;
+/// Colores de estrellas frías a calientes (rojizas, amarillas, blancas y azuladas).
+const STAR_COLORS: [Vector3<f32>; 5] = [
+    Vector3::new(1.0, 0.6, 0.35),
+    Vector3::new(1.0, 0.82, 0.65),
+    Vector3::new(1.0, 0.95, 0.9),
+    Vector3::new(0.88, 0.92, 1.0),
+    Vector3::new(0.7, 0.8, 1.0),
+];
+
+/// Radio angular del disco de la luna (exagerado para que se vea, como el del sol).
+const MOON_RADIUS: f32 = 0.03;
+/// Radiancia de las tierras altas de la luna llena.
+const MOON_DISK_RADIANCE: f32 = 0.6;
+/// Brillo relativo de los mares y de la luz cenicienta en la parte en sombra.
+const MARE_ALBEDO: f32 = 0.55;
+const EARTHSHINE: f32 = 0.015;
+/// Mares de la cara visible: centro en el disco (x a la derecha del observador, y hacia arriba) y radio.
+const MARIA: [(f32, f32, f32); 7] = [
+    (-0.35, 0.45, 0.22),
+    (0.15, 0.42, 0.15),
+    (0.35, 0.15, 0.2),
+    (0.7, 0.3, 0.1),
+    (-0.6, 0.05, 0.35),
+    (-0.2, -0.4, 0.17),
+    (0.6, -0.1, 0.13),
+];
+
+struct Star {
+    direction: Vector3<f32>,
+    /// Irradiancia que llega de la estrella, por canal.
+    flux: Vector3<f32>,
+}
+
+pub struct StarField {
+    stars: Vec<Star>,
+    /// Para cada celda, el rango de `stars` que cae en ella (`GRID³ + 1` valores).
+    cell_start: Vec<usize>,
+}
+
+static STAR_FIELD: OnceLock<StarField> = OnceLock::new();
+
+/// Celda de la rejilla de una coordenada en [-1, 1].
+fn grid_coordinate(c: f32) -> usize {
+    (((c + 1.0) * 0.5 * GRID as f32) as usize).min(GRID - 1)
+}
+
+fn cell_index(x: usize, y: usize, z: usize) -> usize {
+    (z * GRID + y) * GRID + x
+}
+
+/// El catálogo de estrellas, que se genera la primera vez que se pide.
+pub fn star_field() -> &'static StarField {
+    STAR_FIELD.get_or_init(StarField::generate)
+}
+
+impl StarField {
+    fn generate() -> Self {
+        let mut rng = Rng::new(STAR_SEED);
+        let mut stars: Vec<Star> = (0..STAR_COUNT)
+            .map(|_| {
+                let y = rng.next_f32() * 2.0 - 1.0;
+                let angle = rng.next_f32() * std::f32::consts::TAU;
+                let radius = (1.0 - y * y).max(0.0).sqrt();
+                let direction = Vector3::new(radius * angle.cos(), y, radius * angle.sin());
+
+                let magnitude = (FAINTEST_MAGNITUDE + (1.0 - rng.next_f32()).log10() / STAR_COUNT_SLOPE).max(BRIGHTEST_MAGNITUDE);
+                let temperature = rng.next_f32() * (STAR_COLORS.len() - 1) as f32;
+                let index = (temperature as usize).min(STAR_COLORS.len() - 2);
+                let color = STAR_COLORS[index].lerp(&STAR_COLORS[index + 1], temperature - index as f32);
+                let flux = color / luminance(&color) * (MAGNITUDE_ZERO_FLUX * 10f32.powf(-0.4 * magnitude));
+                Star { direction, flux }
+            })
+            .collect();
+
+        let cell = |star: &Star| {
+            let d = star.direction;
+            cell_index(grid_coordinate(d.x), grid_coordinate(d.y), grid_coordinate(d.z))
+        };
+        stars.sort_by_key(cell);
+        let mut cell_start = vec![0; GRID * GRID * GRID + 1];
+        for star in &stars {
+            cell_start[cell(star) + 1] += 1;
+        }
+        for i in 1..cell_start.len() {
+            cell_start[i] += cell_start[i - 1];
+        }
+        StarField { stars, cell_start }
+    }
+
+    /// Radiancia de las estrellas en la dirección unitaria `dir` vista por un píxel de
+    /// apertura `spread` radianes.
+    pub fn radiance(&self, dir: &Vector3<f32>, spread: f32) -> Vector3<f32> {
+        let size = (spread * STAR_BLUR).clamp(STAR_MIN_SIZE, STAR_MAX_SIZE);
+        let reach = 3.0 * size;
+        let range = |c: f32| grid_coordinate(c - reach)..=grid_coordinate(c + reach);
+        let normalization = 1.0 / (std::f32::consts::TAU * size * size);
+
+        let mut total = Vector3::zeros();
+        for z in range(dir.z) {
+            for y in range(dir.y) {
+                for x in range(dir.x) {
+                    let cell = cell_index(x, y, z);
+                    for star in &self.stars[self.cell_start[cell]..self.cell_start[cell + 1]] {
+                        let distance2 = (star.direction - dir).norm_squared();
+                        if distance2 < reach * reach {
+                            total += star.flux * ((-distance2 / (2.0 * size * size)).exp() * normalization);
+                        }
+                    }
+                }
+            }
+        }
+        total
+    }
+}
+
+/// Brillo relativo de la superficie de la luna en el punto `(x, y, z)` de su cara visible.
+fn moon_albedo(x: f32, y: f32, z: f32) -> f32 {
+    let mare = MARIA
+        .iter()
+        .map(|&(cx, cy, radius)| {
+            let distance = ((x - cx).powi(2) + (y - cy).powi(2)).sqrt();
+            1.0 - ((distance - radius * 0.7) / (radius * 0.6)).clamp(0.0, 1.0)
+        })
+        .fold(0.0f32, f32::max);
+    let p = Vector3::new(x, y, z);
+    let craters = 0.6 * value_noise(&(p * 9.0)) + 0.4 * value_noise(&(p * 23.0));
+    (1.0 - (1.0 - MARE_ALBEDO) * mare) * (0.75 + 0.5 * craters)
+}
+
+/*
+Descripción: Radiancia del disco de la luna en la dirección unitaria `dir` y fracción del
+píxel (de apertura `spread`) que cubre, para que oculte las estrellas de detrás. El disco
+se orienta con su vertical hacia el cénit, así que la textura no gira con la cámara.
+*/
+pub fn moon_radiance(dir: &Vector3<f32>, moon_direction: &Vector3<f32>, sun_direction: &Vector3<f32>, spread: f32) -> (Vector3<f32>, f32) {
+    let angle = dir.dot(moon_direction).clamp(-1.0, 1.0).acos();
+    let coverage = ((MOON_RADIUS - angle) / spread.max(1e-4) + 0.5).clamp(0.0, 1.0);
+    if coverage <= 0.0 {
+        return (Vector3::zeros(), 0.0);
+    }
+
+    let side = moon_direction.cross(&Vector3::new(0.0, 1.0, 0.0));
+    let right = if side.norm() > 1e-4 { side.normalize() } else { Vector3::new(1.0, 0.0, 0.0) };
+    let up = right.cross(moon_direction);
+    let offset = (dir - moon_direction) / MOON_RADIUS;
+    let (x, y) = (offset.dot(&right), offset.dot(&up));
+    let z = (1.0 - x * x - y * y).max(0.0).sqrt();
+
+    let normal = right * x + up * y - moon_direction * z;
+    let lit = normal.dot(sun_direction).max(0.0) + EARTHSHINE;
+    let radiance = Vector3::new(1.0, 0.97, 0.92) * (MOON_DISK_RADIANCE * moon_albedo(x, y, z) * lit * coverage);
+    (radiance, coverage)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Dos direcciones perpendiculares a `dir` y entre sí.
+    fn tangents(dir: &Vector3<f32>) -> (Vector3<f32>, Vector3<f32>) {
+        let helper = if dir.x.abs() < 0.9 { Vector3::new(1.0, 0.0, 0.0) } else { Vector3::new(0.0, 1.0, 0.0) };
+        let t1 = dir.cross(&helper).normalize();
+        (t1, dir.cross(&t1))
+    }
+
+    /// Integra `radiance` sobre el cono de radio `radius` alrededor de `center`.
+    fn integrate(center: &Vector3<f32>, radius: f32, radiance: impl Fn(&Vector3<f32>) -> Vector3<f32>) -> Vector3<f32> {
+        let (t1, t2) = tangents(center);
+        let steps = 400;
+        let cell = 2.0 * radius / steps as f32;
+        let mut total = Vector3::zeros();
+        for i in 0..steps {
+            for j in 0..steps {
+                let (a, b) = ((i as f32 + 0.5) * cell - radius, (j as f32 + 0.5) * cell - radius);
+                if a * a + b * b < radius * radius {
+                    total += radiance(&(center + t1 * a + t2 * b).normalize()) * (cell * cell);
+                }
+            }
+        }
+        total
+    }
+
+    #[test]
+    fn la_luz_de_una_estrella_no_depende_de_la_apertura() {
+        let field = star_field();
+        // La estrella más brillante sin vecinas a menos de 0.03, para medir solo su luz.
+        let isolated = |star: &&Star| field.stars.iter().all(|other| std::ptr::eq(other, *star) || (other.direction - star.direction).norm() > 0.03);
+        let star = field.stars.iter().filter(isolated).max_by(|a, b| a.flux.x.total_cmp(&b.flux.x)).unwrap();
+
+        // Desde una apertura tan fina que la anchura se queda en su mínimo hasta la de un
+        // píxel de una imagen pequeña; el cono abarca tres anchuras de la mayor.
+        for spread in [1e-4, 1e-3, 5e-3] {
+            let total = integrate(&star.direction, 0.012, |dir| field.radiance(dir, spread));
+            // La gaussiana se corta a tres anchuras, donde ya solo queda un 1 % de su área.
+            let ratio = total.component_div(&star.flux);
+            assert!(ratio.iter().all(|r| (r - 0.989).abs() < 0.01), "apertura {}: {:?} de la luz", spread, ratio);
+        }
+    }
+
+    #[test]
+    fn el_catalogo_es_siempre_el_mismo() {
+        let (a, b) = (StarField::generate(), StarField::generate());
+        assert_eq!(a.cell_start, b.cell_start);
+        assert!(a.stars.iter().zip(&b.stars).all(|(a, b)| a.direction == b.direction && a.flux == b.flux));
+        assert_eq!(a.stars.len(), STAR_COUNT);
+        assert!(std::ptr::eq(star_field(), star_field()));
+    }
+
+    #[test]
+    fn la_fase_sale_de_la_posicion_del_sol() {
+        let moon = Vector3::new(0.3, 0.5, -0.8).normalize();
+        let disk = |sun: &Vector3<f32>| integrate(&moon, MOON_RADIUS, |dir| moon_radiance(dir, &moon, sun, 1e-4).0);
+        let area = std::f32::consts::PI * MOON_RADIUS * MOON_RADIUS;
+
+        // Con el sol a la espalda la cara visible está iluminada entera; con el sol detrás
+        // de la luna solo queda la luz cenicienta.
+        let full = disk(&-moon) / area;
+        let new = disk(&moon) / area;
+        assert!(full.x > 0.25 && full.x < MOON_DISK_RADIANCE * 1.25, "luna llena {:?}", full);
+        assert!(new.x > 0.0 && new.x < full.x / 20.0, "luna nueva {:?}", new);
+
+        // En cuarto, con el sol a un lado, la mitad del disco que mira al sol brilla más.
+        let (side, _) = tangents(&moon);
+        let (lit, dark) = (moon + side * (MOON_RADIUS * 0.5), moon - side * (MOON_RADIUS * 0.5));
+        assert!(moon_radiance(&lit.normalize(), &moon, &side, 1e-4).0.x > 10.0 * moon_radiance(&dark.normalize(), &moon, &side, 1e-4).0.x);
+
+        assert_eq!(moon_radiance(&Vector3::new(0.0, 1.0, 0.0), &moon, &-moon, 1e-3), (Vector3::zeros(), 0.0));
+    }
+}
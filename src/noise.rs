@@ -0,0 +1,84 @@
+/*
+Documentación (bloque):
+Sección: Ruido de valor
+Descripción: Ruido procedimental estable para texturas que no vienen de fichero: cada
+vértice de la rejilla entera recibe un valor pseudoaleatorio a partir de un hash de sus
+coordenadas, y entre vértices se interpola con una curva suave. El resultado depende solo
+del punto, así que no cambia entre frames ni con la resolución.
+*/
+
+use nalgebra::Vector3;
+
+/// Valor pseudoaleatorio en [0, 1) del vértice `(x, y, z)` de la rejilla.
+fn hash(x: i32, y: i32, z: i32) -> f32 {
+    let mut h = (x as u32).wrapping_mul(0x8DA6_B343) ^ (y as u32).wrapping_mul(0xD816_3841) ^ (z as u32).wrapping_mul(0xCB1A_B31F);
+    h ^= h >> 15;
+    h = h.wrapping_mul(0x2C1B_3C6D);
+    h ^= h >> 12;
+    h = h.wrapping_mul(0x297A_2D39);
+    h ^= h >> 15;
+    (h >> 8) as f32 / (1u32 << 24) as f32
+}
+
+/// Ruido de valor 3D en [0, 1), con periodo de una unidad entre vértices.
+pub fn value_noise(p: &Vector3<f32>) -> f32 {
+    let cell = p.map(f32::floor);
+    let f = p - cell;
+    let w = f.map(|t| t * t * (3.0 - 2.0 * t));
+    let (x, y, z) = (cell.x as i32, cell.y as i32, cell.z as i32);
+
+    let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+    let face = |dz: i32| {
+        let bottom = lerp(hash(x, y, z + dz), hash(x + 1, y, z + dz), w.x);
+        let top = lerp(hash(x, y + 1, z + dz), hash(x + 1, y + 1, z + dz), w.x);
+        lerp(bottom, top, w.y)
+    };
+    lerp(face(0), face(1), w.z)
+}
+
+/// Suma de `octaves` capas de ruido de valor, cada una al doble de frecuencia y la mitad
+/// de amplitud que la anterior, normalizada a [0, 1).
+pub fn fbm(p: &Vector3<f32>, octaves: usize) -> f32 {
+    let (mut sum, mut amplitude, mut total, mut frequency) = (0.0, 1.0, 0.0, 1.0);
+    for octave in 0..octaves {
+        // Un desplazamiento por octava evita que todas coincidan en el origen.
+        let offset = Vector3::repeat(octave as f32 * 17.31);
+        sum += value_noise(&(p * frequency + offset)) * amplitude;
+        total += amplitude;
+        amplitude *= 0.5;
+        frequency *= 2.0;
+    }
+    sum / total
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Rng;
+
+    /// Puntos al azar en un cubo amplio, con coordenadas negativas y lejos del origen.
+    fn points(count: usize) -> Vec<Vector3<f32>> {
+        let mut rng = Rng::new(3);
+        (0..count).map(|_| Vector3::new(rng.next_f32(), rng.next_f32(), rng.next_f32()).map(|c| (c - 0.5) * 2000.0)).collect()
+    }
+
+    #[test]
+    fn el_ruido_queda_en_el_intervalo_unidad() {
+        for p in points(20_000) {
+            let (noise, layered) = (value_noise(&p), fbm(&p, 5));
+            assert!((0.0..1.0).contains(&noise), "value_noise({:?}) = {}", p, noise);
+            assert!((0.0..1.0).contains(&layered), "fbm({:?}) = {}", p, layered);
+        }
+    }
+
+    #[test]
+    fn el_ruido_pasa_por_los_vertices_y_es_continuo() {
+        for (x, y, z) in [(0, 0, 0), (-3, 7, 12), (1000, -1000, 5)] {
+            assert_eq!(value_noise(&Vector3::new(x as f32, y as f32, z as f32)), hash(x, y, z));
+        }
+        for p in points(2000) {
+            let step = Vector3::new(1e-3, -1e-3, 1e-3);
+            assert!((value_noise(&p) - value_noise(&(p + step))).abs() < 0.01, "salto en {:?}", p);
+        }
+    }
+}
@@ -0,0 +1,178 @@
+/*
+Documentación (bloque):
+Sección: Ocupación jerárquica para saltar el espacio vacío
+Descripción: Resumen de qué voxels no son aire, en dos niveles. El mundo se divide en
+ladrillos de 4³ voxels, cada uno con una máscara de 64 bits (un bit por voxel), y los
+ladrillos se agrupan en regiones de 4³ ladrillos (16³ voxels, como los chunks). Una región
+vacía no tiene ladrillos reservados, así que la memoria crece con lo construido. El DDA de
+`VoxelWorld` consulta `empty_extent` para cruzar de un salto una región o un ladrillo
+vacíos en lugar de recorrerlos voxel a voxel. `set` mantiene ambos niveles al día cuando
+cambia un bloque.
+*/
+
+/// Lado en voxels de un ladrillo.
+pub const BRICK: i32 = 4;
+/// Lado en voxels de una región (4³ ladrillos).
+pub const REGION: i32 = BRICK * 4;
+
+pub struct Occupancy {
+    /// Por región, las máscaras de sus 64 ladrillos; `None` si está vacía.
+    bricks: Vec<Option<Box<[u64; 64]>>>,
+    region_dims: [usize; 3],
+}
+
+/// Bit de la celda `(x, y, z)` dentro de su grupo de 4³.
+#[inline]
+fn bit(x: i32, y: i32, z: i32) -> u64 {
+    1 << ((x & 3) | (y & 3) << 2 | (z & 3) << 4)
+}
+
+impl Occupancy {
+    pub fn new(width: usize, height: usize, depth: usize) -> Self {
+        let side = REGION as usize;
+        let region_dims = [width.div_ceil(side), height.div_ceil(side), depth.div_ceil(side)];
+        let count = region_dims.iter().product();
+        Occupancy {
+            bricks: std::iter::repeat_with(|| None).take(count).collect(),
+            region_dims,
+        }
+    }
+
+    /// Posición del ladrillo de `(x, y, z)` dentro de su región (0..64).
+    #[inline]
+    fn brick_slot(x: i32, y: i32, z: i32) -> usize {
+        bit(x / BRICK, y / BRICK, z / BRICK).trailing_zeros() as usize
+    }
+
+    #[inline]
+    fn region_index(&self, x: i32, y: i32, z: i32) -> usize {
+        let (rx, ry, rz) = ((x / REGION) as usize, (y / REGION) as usize, (z / REGION) as usize);
+        (ry * self.region_dims[2] + rz) * self.region_dims[0] + rx
+    }
+
+    /// Marca el voxel `(x, y, z)`, que debe estar dentro del mundo, como ocupado o vacío.
+    pub fn set(&mut self, x: i32, y: i32, z: i32, occupied: bool) {
+        let region = self.region_index(x, y, z);
+        let bricks = match &mut self.bricks[region] {
+            Some(bricks) => bricks,
+            None if !occupied => return,
+            slot => slot.insert(Box::new([0; 64])),
+        };
+
+        let slot = Self::brick_slot(x, y, z);
+        if occupied {
+            bricks[slot] |= bit(x, y, z);
+        } else {
+            bricks[slot] &= !bit(x, y, z);
+        }
+
+        if !occupied && bricks.iter().all(|&brick| brick == 0) {
+            self.bricks[region] = None;
+        }
+    }
+
+    /// Lado del mayor cubo vacío alineado que contiene al voxel `(x, y, z)`: `REGION`,
+    /// `BRICK` o 1 si solo está vacío el voxel, y 0 si está ocupado.
+    #[inline]
+    pub fn empty_extent(&self, x: i32, y: i32, z: i32) -> i32 {
+        let region = self.region_index(x, y, z);
+        match &self.bricks[region] {
+            None => REGION,
+            Some(bricks) => {
+                let brick = bricks[Self::brick_slot(x, y, z)];
+                if brick == 0 {
+                    BRICK
+                } else if brick & bit(x, y, z) == 0 {
+                    1
+                } else {
+                    0
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Rng;
+
+    const SIZE: [i32; 3] = [37, 18, 40];
+
+    #[test]
+    fn cada_voxel_tiene_su_propio_bit_y_cada_ladrillo_su_hueco() {
+        let mut bits = 0u64;
+        let mut slots = 0u64;
+        for y in 0..BRICK {
+            for z in 0..BRICK {
+                for x in 0..BRICK {
+                    assert_eq!(bits & bit(x, y, z), 0, "bit repetido en ({}, {}, {})", x, y, z);
+                    bits |= bit(x, y, z);
+                    let slot = Occupancy::brick_slot(x * BRICK, y * BRICK, z * BRICK);
+                    assert_eq!(slots & 1 << slot, 0, "hueco repetido para el ladrillo ({}, {}, {})", x, y, z);
+                    slots |= 1 << slot;
+                    // El ladrillo vecino de otra región cae en el mismo hueco.
+                    assert_eq!(Occupancy::brick_slot(x * BRICK + REGION, y * BRICK, z * BRICK + REGION), slot);
+                }
+            }
+        }
+        assert_eq!((bits, slots), (u64::MAX, u64::MAX));
+    }
+
+    #[test]
+    fn las_mascaras_solo_guardan_lo_ocupado() {
+        let mut occupancy = Occupancy::new(SIZE[0] as usize, SIZE[1] as usize, SIZE[2] as usize);
+        let mut occupied = std::collections::HashSet::new();
+        let mut rng = Rng::new(11);
+
+        // Rondas cada vez más vacías, para que haya regiones y ladrillos que se vacíen.
+        for fill in [0.02, 0.3, 0.05, 0.0] {
+            for _ in 0..4000 {
+                let [x, y, z] = SIZE.map(|side| (rng.next_u32() % side as u32) as i32);
+                if rng.next_f32() < fill {
+                    occupancy.set(x, y, z, true);
+                    occupied.insert((x, y, z));
+                } else {
+                    occupancy.set(x, y, z, false);
+                    occupied.remove(&(x, y, z));
+                }
+            }
+
+            let set_bits: u32 = occupancy.bricks.iter().flatten().flat_map(|bricks| bricks.iter()).map(|brick| brick.count_ones()).sum();
+            assert_eq!(set_bits as usize, occupied.len());
+            for &(x, y, z) in &occupied {
+                let bricks = occupancy.bricks[occupancy.region_index(x, y, z)].as_ref().expect("región ocupada sin reservar");
+                assert_ne!(bricks[Occupancy::brick_slot(x, y, z)] & bit(x, y, z), 0, "voxel ({}, {}, {})", x, y, z);
+                assert_eq!(occupancy.empty_extent(x, y, z), 0);
+            }
+            // Ninguna región reservada está vacía.
+            assert!(occupancy.bricks.iter().flatten().all(|bricks| bricks.iter().any(|&brick| brick != 0)));
+        }
+    }
+
+    #[test]
+    fn una_region_vacia_se_libera() {
+        let mut occupancy = Occupancy::new(SIZE[0] as usize, SIZE[1] as usize, SIZE[2] as usize);
+        let voxels = [(0, 0, 0), (3, 3, 3), (15, 15, 15), (36, 17, 39)];
+        for &(x, y, z) in &voxels {
+            occupancy.set(x, y, z, true);
+        }
+        assert_eq!(occupancy.bricks.iter().flatten().count(), 2);
+        assert_eq!(occupancy.empty_extent(1, 1, 1), 1);
+        assert_eq!(occupancy.empty_extent(4, 0, 0), BRICK);
+
+        occupancy.set(0, 0, 0, false);
+        occupancy.set(3, 3, 3, false);
+        assert_eq!(occupancy.bricks.iter().flatten().count(), 2);
+        assert_eq!(occupancy.empty_extent(1, 1, 1), BRICK);
+
+        occupancy.set(15, 15, 15, false);
+        occupancy.set(36, 17, 39, false);
+        assert_eq!(occupancy.bricks.iter().flatten().count(), 0);
+        assert_eq!(occupancy.empty_extent(1, 1, 1), REGION);
+
+        // Vaciar un voxel de una región sin reservar no la reserva.
+        occupancy.set(20, 0, 0, false);
+        assert_eq!(occupancy.bricks.iter().flatten().count(), 0);
+    }
+}
@@ -0,0 +1,366 @@
+/*
+Documentación (bloque):
+Sección: Registro de bloques
+Descripción: Tabla de bloques cargada desde un fichero de definición (`bloques.txt`) en
+lugar de estar fijada en el código. Cada entrada reúne lo que antes vivía repartido en
+varias tablas paralelas: nombre, carácter de capa, textura, color de fallback, si emite
+luz, forma y parámetros del material. El identificador de un `BlockType` es su posición
+en el fichero. Si no se encuentra el fichero por defecto se usa la copia incluida en el
+binario, de modo que el programa sigue arrancando sin él.
+*/
+
+use crate::{BlockFace, BlockType, MaterialProperties};
+use nalgebra::Vector3;
+use std::collections::HashMap;
+use std::fs;
+use std::sync::OnceLock;
+
+pub const DEFAULT_REGISTRY_PATH: &str = "bloques.txt";
+/// Nombre de la sección del aire, que debe ser la primera: su identificador es 0
+/// (`BlockType::AIRE`).
+const AIR_NAME: &str = "aire";
+const BUILTIN_DEFINITIONS: &str = include_str!("../bloques.txt");
+
+static REGISTRY: OnceLock<BlockRegistry> = OnceLock::new();
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BlockShape {
+    Cube,
+    Slab,
+    Stairs,
+    FencePost,
+    Pane,
+    Carpet,
+    Button,
+}
+
+/// Caja alineada con los ejes, `(mínimo, máximo)` en coordenadas locales del voxel ([0,1]³).
+pub type ShapeBox = ([f32; 3], [f32; 3]);
+
+impl BlockShape {
+    fn parse(value: &str) -> Option<Self> {
+        match value {
+            "cubo" => Some(BlockShape::Cube),
+            "slab" => Some(BlockShape::Slab),
+            "escalera" => Some(BlockShape::Stairs),
+            "poste" => Some(BlockShape::FencePost),
+            "panel" => Some(BlockShape::Pane),
+            "alfombra" => Some(BlockShape::Carpet),
+            "boton" => Some(BlockShape::Button),
+            _ => None,
+        }
+    }
+
+    /*
+    Descripción: Geometría de cada forma como lista de cajas, en el marco canónico del
+    voxel: frente hacia -z y apoyada abajo (ver `BlockState`). La escalera tiene la mitad
+    alta hacia +z, el panel se extiende a lo largo de x y el botón está pegado a la cara +z.
+    */
+    pub fn boxes(self) -> &'static [ShapeBox] {
+        match self {
+            BlockShape::Cube => &[([0.0, 0.0, 0.0], [1.0, 1.0, 1.0])],
+            BlockShape::Slab => &[([0.0, 0.0, 0.0], [1.0, 0.5, 1.0])],
+            BlockShape::Stairs => &[
+                ([0.0, 0.0, 0.0], [1.0, 0.5, 1.0]),
+                ([0.0, 0.5, 0.5], [1.0, 1.0, 1.0]),
+            ],
+            BlockShape::FencePost => &[([0.375, 0.0, 0.375], [0.625, 1.0, 0.625])],
+            BlockShape::Pane => &[([0.0, 0.0, 0.4375], [1.0, 1.0, 0.5625])],
+            BlockShape::Carpet => &[([0.0, 0.0, 0.0], [1.0, 0.0625, 1.0])],
+            BlockShape::Button => &[([0.3125, 0.375, 0.875], [0.6875, 0.625, 1.0])],
+        }
+    }
+}
+
+pub struct BlockDef {
+    pub name: String,
+    pub layer_char: Option<char>,
+    pub texture: Option<String>,
+    pub top_texture: Option<String>,
+    pub bottom_texture: Option<String>,
+    pub side_texture: Option<String>,
+    pub front_texture: Option<String>,
+    pub color: (f32, f32, f32),
+    pub emissive: bool,
+    pub light: Option<(f32, f32, f32)>,
+    pub shape: BlockShape,
+    pub material: MaterialProperties,
+    /// Índice de refracción de los bloques translúcidos; `None` en los opacos.
+    pub ior: Option<f32>,
+    /// Coeficientes de absorción de Beer-Lambert por bloque recorrido.
+    pub absorption: Vector3<f32>,
+    pub night_glow: f32,
+    pub minecraft_ids: Vec<String>,
+}
+
+impl BlockDef {
+    /// Fichero de textura de una cara: el específico de esa cara si existe y, si no,
+    /// `textura`.
+    pub fn texture_for(&self, face: BlockFace) -> Option<&str> {
+        let specific = match face {
+            BlockFace::Top => &self.top_texture,
+            BlockFace::Bottom => &self.bottom_texture,
+            BlockFace::Side => &self.side_texture,
+            BlockFace::Front => &self.front_texture,
+        };
+        specific.as_ref().or(self.texture.as_ref()).map(String::as_str)
+    }
+
+    fn new(name: String) -> Self {
+        BlockDef {
+            name,
+            layer_char: None,
+            texture: None,
+            top_texture: None,
+            bottom_texture: None,
+            side_texture: None,
+            front_texture: None,
+            color: (0.5, 0.5, 0.5),
+            emissive: false,
+            light: None,
+            shape: BlockShape::Cube,
+            material: MaterialProperties::new(
+                Vector3::new(0.8, 0.8, 0.8),
+                0.0,
+                0.9,
+                0.04,
+                0.0,
+                Vector3::new(0.04, 0.04, 0.04),
+            ),
+            ior: None,
+            absorption: Vector3::zeros(),
+            night_glow: 0.1,
+            minecraft_ids: Vec::new(),
+        }
+    }
+}
+
+pub struct BlockRegistry {
+    blocks: Vec<BlockDef>,
+    by_char: HashMap<char, BlockType>,
+    by_minecraft_id: HashMap<String, BlockType>,
+}
+
+/// Registro activo. Hasta que se llama a `install` se usa la definición incluida.
+pub fn registry() -> &'static BlockRegistry {
+    REGISTRY.get_or_init(|| {
+        BlockRegistry::parse(BUILTIN_DEFINITIONS, "<bloques incluidos>")
+            .expect("la definición de bloques incluida es válida")
+    })
+}
+
+/*
+Descripción: Carga el registro desde `path` y lo deja activo para todo el programa. Si la
+ruta es la de por defecto y no existe, se usa la definición incluida en el binario.
+*/
+pub fn install(path: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let registry = match fs::read_to_string(path) {
+        Ok(content) => {
+            let registry = BlockRegistry::parse(&content, path)?;
+            println!("✓ Registro de bloques cargado: {} ({} bloques)", path, registry.len());
+            registry
+        }
+        Err(_) if path == DEFAULT_REGISTRY_PATH => {
+            println!("ℹ No se encontró {}, usando los bloques incluidos", path);
+            BlockRegistry::parse(BUILTIN_DEFINITIONS, "<bloques incluidos>")?
+        }
+        Err(e) => return Err(format!("no se pudo leer el registro de bloques {}: {}", path, e).into()),
+    };
+
+    REGISTRY.set(registry).map_err(|_| "el registro de bloques ya estaba inicializado".into())
+}
+
+fn parse_f32(value: &str, location: &str) -> Result<f32, String> {
+    value.parse().map_err(|_| format!("{}: número inválido '{}'", location, value))
+}
+
+fn parse_rgb(value: &str, location: &str) -> Result<(f32, f32, f32), String> {
+    let parts: Vec<&str> = value.split_whitespace().collect();
+    if parts.len() != 3 {
+        return Err(format!("{}: se esperaban tres componentes \"r g b\"", location));
+    }
+    Ok((parse_f32(parts[0], location)?, parse_f32(parts[1], location)?, parse_f32(parts[2], location)?))
+}
+
+fn parse_vector(value: &str, location: &str) -> Result<Vector3<f32>, String> {
+    let (r, g, b) = parse_rgb(value, location)?;
+    Ok(Vector3::new(r, g, b))
+}
+
+impl BlockRegistry {
+    /*
+    Descripción: Formato tipo INI: una sección `[nombre]` por bloque seguida de líneas
+    `clave = valor`. Los valores entre comillas dobles se toman literalmente (así se
+    puede declarar el espacio como carácter del aire). `#` inicia un comentario. La
+    primera sección tiene que ser `[aire]`, porque el resto del programa trata el
+    identificador 0 como vacío, y cada sección necesita su `caracter` para poder usarse
+    en las capas.
+    */
+    pub fn parse(content: &str, source: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut blocks: Vec<BlockDef> = Vec::new();
+
+        for (line_no, raw_line) in content.lines().enumerate() {
+            let location = format!("{}:{}", source, line_no + 1);
+            let line = raw_line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                let name = name.trim();
+                if blocks.iter().any(|b| b.name == name) {
+                    return Err(format!("{}: bloque '{}' duplicado", location, name).into());
+                }
+                if blocks.is_empty() && name != AIR_NAME {
+                    return Err(format!("{}: la primera sección debe ser [{}], no [{}]", location, AIR_NAME, name).into());
+                }
+                blocks.push(BlockDef::new(name.to_string()));
+                continue;
+            }
+
+            let (key, value) = line.split_once('=')
+                .ok_or_else(|| format!("{}: se esperaba 'clave = valor' o '[nombre]'", location))?;
+            let key = key.trim();
+            let value = value.trim();
+            let value = value.strip_prefix('"').and_then(|v| v.strip_suffix('"')).unwrap_or(value);
+
+            let block = blocks.last_mut()
+                .ok_or_else(|| format!("{}: clave fuera de una sección [bloque]", location))?;
+
+            match key {
+                "caracter" => {
+                    let mut chars = value.chars();
+                    match (chars.next(), chars.next()) {
+                        (Some(c), None) => block.layer_char = Some(c),
+                        _ => return Err(format!("{}: 'caracter' debe ser un único carácter", location).into()),
+                    }
+                }
+                "textura" => block.texture = Some(value.to_string()),
+                "textura_arriba" => block.top_texture = Some(value.to_string()),
+                "textura_abajo" => block.bottom_texture = Some(value.to_string()),
+                "textura_lado" => block.side_texture = Some(value.to_string()),
+                "textura_frente" => block.front_texture = Some(value.to_string()),
+                "color" => block.color = parse_rgb(value, &location)?,
+                "emisivo" => block.emissive = match value {
+                    "si" | "sí" | "true" => true,
+                    "no" | "false" => false,
+                    _ => return Err(format!("{}: 'emisivo' debe ser si o no", location).into()),
+                },
+                "luz" => block.light = Some(parse_rgb(value, &location)?),
+                "forma" => block.shape = BlockShape::parse(value)
+                    .ok_or_else(|| format!("{}: forma desconocida '{}'", location, value))?,
+                "albedo" => block.material.albedo = parse_vector(value, &location)?,
+                "metalico" => block.material.metallic = parse_f32(value, &location)?,
+                "rugosidad" => block.material.roughness = parse_f32(value, &location)?,
+                "reflectancia" => block.material.reflectance = parse_f32(value, &location)?,
+                "emision" => block.material.emissive_strength = parse_f32(value, &location)?,
+                "especular" => block.material.specular_color = parse_vector(value, &location)?,
+                "refraccion" => {
+                    let ior = parse_f32(value, &location)?;
+                    if ior < 1.0 {
+                        return Err(format!("{}: el índice de refracción debe ser >= 1", location).into());
+                    }
+                    block.ior = Some(ior);
+                }
+                "absorcion" => block.absorption = parse_vector(value, &location)?,
+                "realce_noche" => block.night_glow = parse_f32(value, &location)?,
+                "minecraft" => block.minecraft_ids = value.split_whitespace().map(str::to_string).collect(),
+                other => return Err(format!("{}: clave desconocida '{}'", location, other).into()),
+            }
+        }
+
+        if blocks.is_empty() {
+            return Err(format!("{}: no define ningún bloque", source).into());
+        }
+        if blocks.len() > u16::MAX as usize {
+            return Err(format!("{}: demasiados bloques ({})", source, blocks.len()).into());
+        }
+
+        let mut by_char = HashMap::new();
+        let mut by_minecraft_id = HashMap::new();
+        for (i, block) in blocks.iter().enumerate() {
+            let id = BlockType(i as u16);
+            let c = block.layer_char
+                .ok_or_else(|| format!("{}: el bloque [{}] no tiene 'caracter'", source, block.name))?;
+            if by_char.insert(c, id).is_some() {
+                return Err(format!("{}: el carácter {:?} está asignado a más de un bloque", source, c).into());
+            }
+            for minecraft_id in &block.minecraft_ids {
+                by_minecraft_id.entry(minecraft_id.clone()).or_insert(id);
+            }
+        }
+
+        Ok(BlockRegistry { blocks, by_char, by_minecraft_id })
+    }
+
+    pub fn len(&self) -> usize {
+        self.blocks.len()
+    }
+
+    pub fn blocks(&self) -> impl Iterator<Item = (BlockType, &BlockDef)> {
+        self.blocks.iter().enumerate().map(|(i, def)| (BlockType(i as u16), def))
+    }
+
+    #[inline]
+    pub fn get(&self, block: BlockType) -> &BlockDef {
+        &self.blocks[block.0 as usize]
+    }
+
+    pub fn by_index(&self, index: usize) -> Option<BlockType> {
+        (index < self.blocks.len()).then_some(BlockType(index as u16))
+    }
+
+    #[inline]
+    pub fn by_char(&self, c: char) -> Option<BlockType> {
+        self.by_char.get(&c).copied()
+    }
+
+    pub fn by_minecraft_id(&self, id: &str) -> Option<BlockType> {
+        self.by_minecraft_id.get(id).copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn la_definicion_incluida_empieza_por_el_aire() {
+        let registry = BlockRegistry::parse(BUILTIN_DEFINITIONS, "<bloques incluidos>").unwrap();
+        assert_eq!(registry.get(BlockType::AIRE).name, AIR_NAME);
+    }
+
+    fn parse_error(content: &str) -> String {
+        BlockRegistry::parse(content, "prueba.txt").err().unwrap().to_string()
+    }
+
+    #[test]
+    fn rechaza_un_registro_que_no_empieza_por_el_aire() {
+        let error = parse_error("[piedra]\ncaracter = p\n\n[aire]\ncaracter = \" \"\n");
+        assert!(error.starts_with("prueba.txt:1:"), "{}", error);
+    }
+
+    #[test]
+    fn rechaza_bloques_duplicados() {
+        let error = parse_error("[aire]\ncaracter = \" \"\n[piedra]\ncaracter = p\n[piedra]\ncaracter = q\n");
+        assert_eq!(error, "prueba.txt:5: bloque 'piedra' duplicado");
+    }
+
+    #[test]
+    fn rechaza_claves_desconocidas() {
+        let error = parse_error("[aire]\ncaracter = \" \"\ndureza = 3\n");
+        assert_eq!(error, "prueba.txt:3: clave desconocida 'dureza'");
+    }
+
+    #[test]
+    fn rechaza_indices_de_refraccion_menores_que_uno() {
+        let error = parse_error("[aire]\ncaracter = \" \"\n[vidrio]\ncaracter = v\nrefraccion = 0.9\n");
+        assert_eq!(error, "prueba.txt:5: el índice de refracción debe ser >= 1");
+    }
+
+    #[test]
+    fn rechaza_secciones_sin_caracter() {
+        let error = parse_error("[aire]\ncaracter = \" \"\n[piedra]\ncolor = 0.5 0.5 0.5\n");
+        assert_eq!(error, "prueba.txt: el bloque [piedra] no tiene 'caracter'");
+    }
+}
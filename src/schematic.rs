@@ -0,0 +1,626 @@
+/*
+Documentación (bloque):
+Sección: Schematics de Sponge (.schem) y estructuras vanilla (.nbt)
+Descripción: Convierte entre `VoxelWorld` y los dos formatos de Minecraft más usados para
+mover construcciones: el schematic de Sponge (WorldEdit, versiones 2 y 3) y el fichero
+del bloque de estructuras del juego. Los IDs como `minecraft:magma_block` se traducen a
+`BlockType` con las claves `minecraft` del registro de bloques, y las propiedades
+`facing`, `half` y `type` del estado (`[facing=north]`) a la orientación del voxel. Los IDs
+sin equivalente se cargan como aire y se listan al terminar la importación.
+*/
+
+use crate::nbt::{self, Tag};
+use crate::registry::{registry, BlockShape};
+use crate::{BlockState, BlockType, Facing, VoxelWorld};
+use std::collections::BTreeMap;
+
+/// DataVersion de Minecraft 1.20.1, con la que se marcan los ficheros exportados.
+const DATA_VERSION: i32 = 3465;
+/// Volumen máximo de un mundo importado, para que unas dimensiones corruptas no reserven
+/// una tabla de chunks imposible.
+const MAX_VOLUME: usize = 1 << 30;
+
+/// Traduce un estado de bloque (`minecraft:chest[facing=east]`) a `BlockType`, según
+/// las claves `minecraft` del registro de bloques.
+fn block_from_state(state: &str) -> Option<BlockType> {
+    let id = state.split('[').next().unwrap_or(state);
+    if id.contains(':') {
+        registry().by_minecraft_id(id)
+    } else {
+        registry().by_minecraft_id(&format!("minecraft:{}", id))
+    }
+}
+
+/// ID con el que se exporta un bloque: el primero de su lista `minecraft`.
+fn minecraft_id(block: BlockType) -> &'static str {
+    registry().get(block).minecraft_ids.first().map_or("minecraft:air", |id| id.as_str())
+}
+
+/// Propiedades de un estado escrito como `minecraft:oak_stairs[facing=east,half=top]`.
+fn parse_properties(state: &str) -> Vec<(&str, &str)> {
+    state.split_once('[')
+        .and_then(|(_, rest)| rest.strip_suffix(']'))
+        .map_or_else(Vec::new, |list| {
+            list.split(',').filter_map(|p| p.split_once('=')).map(|(k, v)| (k.trim(), v.trim())).collect()
+        })
+}
+
+/*
+Descripción: Orientación del voxel a partir de las propiedades `facing`, `half` y `type`
+de un estado de Minecraft. Allí una escalera mira hacia su lado alto, mientras que aquí su
+frente es el escalón bajo, así que su `facing` se invierte. Los paneles no tienen
+`facing`: se orientan según los lados a los que se conectan.
+*/
+fn orientation(block: BlockType, properties: &[(&str, &str)]) -> BlockState {
+    let property = |key: &str| properties.iter().find(|(k, _)| *k == key).map(|&(_, v)| v);
+    match block.shape() {
+        BlockShape::Slab => match property("type") {
+            Some("double") => BlockState::double(),
+            Some("top") => BlockState::new(Facing::North, true),
+            _ => BlockState::default(),
+        },
+        BlockShape::Stairs => {
+            let facing = property("facing").and_then(Facing::parse).unwrap_or(Facing::North);
+            BlockState::new(facing.opposite(), property("half") == Some("top"))
+        }
+        BlockShape::Pane if property("east") != Some("true") && property("west") != Some("true")
+            && (property("north") == Some("true") || property("south") == Some("true")) => {
+            BlockState::new(Facing::East, false)
+        }
+        BlockShape::Pane | BlockShape::FencePost | BlockShape::Carpet => BlockState::default(),
+        BlockShape::Cube | BlockShape::Button => property("facing")
+            .and_then(Facing::parse)
+            .map_or_else(BlockState::default, |facing| BlockState::new(facing, false)),
+    }
+}
+
+/// Propiedades con las que se exporta la orientación de un voxel; inversa de `orientation`.
+fn properties(block: BlockType, state: BlockState) -> Vec<(&'static str, &'static str)> {
+    match block.shape() {
+        BlockShape::Slab => {
+            let kind = if state.is_double() { "double" } else if state.is_top() { "top" } else { "bottom" };
+            vec![("type", kind)]
+        }
+        BlockShape::Stairs => vec![
+            ("facing", state.facing().opposite().name()),
+            ("half", if state.is_top() { "top" } else { "bottom" }),
+        ],
+        BlockShape::Pane => match state.facing() {
+            Facing::North | Facing::South => vec![("east", "true"), ("west", "true")],
+            Facing::East | Facing::West => vec![("north", "true"), ("south", "true")],
+        },
+        BlockShape::FencePost | BlockShape::Carpet => Vec::new(),
+        BlockShape::Cube | BlockShape::Button if state.facing() != Facing::North => vec![("facing", state.facing().name())],
+        BlockShape::Cube | BlockShape::Button => Vec::new(),
+    }
+}
+
+/// Estado completo con el que se exporta un voxel a .schem: `minecraft:chest[facing=east]`.
+fn state_string(block: BlockType, state: BlockState) -> String {
+    let id = minecraft_id(block);
+    let properties = properties(block, state);
+    if properties.is_empty() {
+        return id.to_string();
+    }
+    let list: Vec<String> = properties.iter().map(|(k, v)| format!("{}={}", k, v)).collect();
+    format!("{}[{}]", id, list.join(","))
+}
+
+/*
+Descripción: Acumula los IDs sin equivalente durante una importación para mostrar un
+único informe con el número de bloques afectados por cada uno.
+*/
+#[derive(Default)]
+struct UnmappedReport {
+    counts: BTreeMap<String, usize>,
+}
+
+impl UnmappedReport {
+    fn resolve(&mut self, state: &str) -> BlockType {
+        block_from_state(state).unwrap_or_else(|| {
+            let id = state.split('[').next().unwrap_or(state).to_string();
+            *self.counts.entry(id).or_insert(0) += 1;
+            BlockType::AIRE
+        })
+    }
+
+    fn print(&self, path: &str) {
+        if self.counts.is_empty() {
+            return;
+        }
+        println!("⚠ {}: {} IDs de bloque sin equivalente (cargados como aire):", path, self.counts.len());
+        for (id, count) in &self.counts {
+            println!("   {} ×{}", id, count);
+        }
+    }
+}
+
+/// Dimensión de un schematic: un `Short` se lee sin signo, como lo guarda Sponge; cualquier
+/// otro entero debe caber en `0..=u16::MAX`.
+fn dimension(tag: &Tag, key: &str, path: &str) -> Result<usize, Box<dyn std::error::Error>> {
+    let value = match tag.get(key) {
+        Some(&Tag::Short(v)) => Some(v as u16 as i64),
+        Some(other) => other.as_int(),
+        None => None,
+    }.ok_or_else(|| format!("{}: falta {}", path, key))?;
+    u16::try_from(value)
+        .map(usize::from)
+        .map_err(|_| format!("{}: {} fuera de rango ({})", path, key, value).into())
+}
+
+/// Mundo vacío de las dimensiones leídas de `path`, o error si superan `MAX_VOLUME`.
+fn new_world(width: usize, height: usize, depth: usize, path: &str) -> Result<VoxelWorld, Box<dyn std::error::Error>> {
+    let volume = width.checked_mul(height).and_then(|v| v.checked_mul(depth));
+    if volume.is_none_or(|v| v > MAX_VOLUME) {
+        return Err(format!("{}: {}x{}x{} supera el máximo de {} bloques", path, width, height, depth, MAX_VOLUME).into());
+    }
+    Ok(VoxelWorld::with_dimensions(width, height, depth))
+}
+
+fn read_varints(data: &[i8], path: &str) -> Result<Vec<u32>, Box<dyn std::error::Error>> {
+    let mut values = Vec::with_capacity(data.len());
+    let mut value = 0u32;
+    let mut shift = 0;
+    for &byte in data {
+        let byte = byte as u8;
+        value |= ((byte & 0x7f) as u32) << shift;
+        if byte & 0x80 == 0 {
+            values.push(value);
+            value = 0;
+            shift = 0;
+        } else {
+            shift += 7;
+            if shift > 28 {
+                return Err(format!("{}: varint demasiado largo en BlockData", path).into());
+            }
+        }
+    }
+    if shift > 0 {
+        return Err(format!("{}: varint incompleto al final de BlockData", path).into());
+    }
+    Ok(values)
+}
+
+fn write_varint(out: &mut Vec<i8>, mut value: u32) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte as i8);
+            return;
+        }
+        out.push((byte | 0x80) as i8);
+    }
+}
+
+/*
+Documentación (bloque):
+Sección: Lectura de .schem
+Descripción: Acepta la versión 2 (datos en la raíz) y la 3 (todo dentro de `Schematic`
+y los bloques en `Blocks`). El índice de cada voxel es `(y * Length + z) * Width + x`,
+que coincide con la disposición por capas de `VoxelWorld`. Unos datos de bloques con más o
+menos entradas que `Width × Height × Length` son un error.
+*/
+pub fn load_schem(path: &str) -> Result<VoxelWorld, Box<dyn std::error::Error>> {
+    let (_, root) = nbt::read_file(path)?;
+    let schematic = root.get("Schematic").unwrap_or(&root);
+
+    let version = schematic.get("Version").and_then(Tag::as_int).unwrap_or(2);
+    let (palette, data) = if version >= 3 {
+        let blocks = schematic.get("Blocks").ok_or_else(|| format!("{}: falta Blocks", path))?;
+        (blocks.get("Palette"), blocks.get("Data"))
+    } else {
+        (schematic.get("Palette"), schematic.get("BlockData"))
+    };
+    let palette = palette.and_then(Tag::as_compound)
+        .ok_or_else(|| format!("{}: falta la paleta", path))?;
+    let data = data.and_then(Tag::as_byte_array)
+        .ok_or_else(|| format!("{}: faltan los datos de bloques", path))?;
+
+    let width = dimension(schematic, "Width", path)?;
+    let height = dimension(schematic, "Height", path)?;
+    let length = dimension(schematic, "Length", path)?;
+    let mut world = new_world(width, height, length, path)?;
+
+    let mut states: BTreeMap<u32, &str> = BTreeMap::new();
+    for (state, index) in palette {
+        let index = index.as_int().ok_or_else(|| format!("{}: índice de paleta inválido", path))?;
+        states.insert(index as u32, state.as_str());
+    }
+
+    let indices = read_varints(data, path)?;
+    if indices.len() != width * height * length {
+        return Err(format!("{}: {} bloques en BlockData, se esperaban {}", path, indices.len(), width * height * length).into());
+    }
+
+    let mut report = UnmappedReport::default();
+    for (i, index) in indices.iter().enumerate() {
+        let x = i % width;
+        let z = (i / width) % length;
+        let y = i / (width * length);
+        let state = states.get(index).copied().unwrap_or("minecraft:air");
+        let block = report.resolve(state);
+        world.set_block(x as i32, y as i32, z as i32, block);
+        world.set_state(x as i32, y as i32, z as i32, orientation(block, &parse_properties(state)));
+    }
+
+    println!("✓ Importado {}: {}x{}x{}", path, world.width, world.height, world.depth);
+    report.print(path);
+    Ok(world)
+}
+
+/*
+Documentación (bloque):
+Sección: Escritura de .schem
+Descripción: Exporta en la versión 2 del formato, que leen tanto WorldEdit como las
+versiones antiguas de los plugins compatibles.
+*/
+pub fn save_schem(world: &VoxelWorld, path: &str) -> Result<(), Box<dyn std::error::Error>> {
+    check_dimensions(world)?;
+
+    let mut palette: Vec<String> = Vec::new();
+    let mut data = Vec::with_capacity(world.width * world.height * world.depth);
+    for y in 0..world.height as i32 {
+        for z in 0..world.depth as i32 {
+            for x in 0..world.width as i32 {
+                let id = state_string(world.get_block(x, y, z), world.get_state(x, y, z));
+                let index = palette.iter().position(|p| *p == id).unwrap_or_else(|| {
+                    palette.push(id);
+                    palette.len() - 1
+                });
+                write_varint(&mut data, index as u32);
+            }
+        }
+    }
+
+    let root = Tag::Compound(vec![
+        ("Version".to_string(), Tag::Int(2)),
+        ("DataVersion".to_string(), Tag::Int(DATA_VERSION)),
+        ("Width".to_string(), Tag::Short(world.width as u16 as i16)),
+        ("Height".to_string(), Tag::Short(world.height as u16 as i16)),
+        ("Length".to_string(), Tag::Short(world.depth as u16 as i16)),
+        ("Offset".to_string(), Tag::IntArray(vec![0, 0, 0])),
+        ("PaletteMax".to_string(), Tag::Int(palette.len() as i32)),
+        ("Palette".to_string(), Tag::Compound(
+            palette.iter().enumerate().map(|(i, id)| (id.clone(), Tag::Int(i as i32))).collect(),
+        )),
+        ("BlockData".to_string(), Tag::ByteArray(data)),
+        ("BlockEntities".to_string(), Tag::List(Vec::new())),
+    ]);
+
+    nbt::write_file(path, "Schematic", &root)?;
+    println!("✓ Exportado {} ({} estados de bloque)", path, palette.len());
+    Ok(())
+}
+
+/*
+Documentación (bloque):
+Sección: Lectura de estructuras vanilla (.nbt)
+Descripción: Lee el fichero que guarda el bloque de estructuras: `size`, una paleta de
+estados (`Name` + `Properties`) y la lista de bloques con su `pos`. Si la estructura
+tiene varias paletas (`palettes`, usadas por los naufragios) se toma la primera.
+*/
+pub fn load_structure(path: &str) -> Result<VoxelWorld, Box<dyn std::error::Error>> {
+    let (_, root) = nbt::read_file(path)?;
+
+    let size = root.get("size").and_then(Tag::as_int_array)
+        .filter(|size| size.len() == 3)
+        .ok_or_else(|| format!("{}: falta size", path))?;
+    let palette = root.get("palette")
+        .or_else(|| root.get("palettes").and_then(Tag::as_list).and_then(|p| p.first()))
+        .and_then(Tag::as_list)
+        .ok_or_else(|| format!("{}: falta la paleta", path))?;
+    let blocks = root.get("blocks").and_then(Tag::as_list)
+        .ok_or_else(|| format!("{}: falta blocks", path))?;
+
+    let mut report = UnmappedReport::default();
+    let names: Vec<(&str, Vec<(&str, &str)>)> = palette.iter()
+        .map(|entry| {
+            let name = entry.get("Name").and_then(Tag::as_str).unwrap_or("minecraft:air");
+            let properties = entry.get("Properties").and_then(Tag::as_compound).unwrap_or(&[]).iter()
+                .filter_map(|(key, value)| Some((key.as_str(), value.as_str()?)))
+                .collect();
+            (name, properties)
+        })
+        .collect();
+
+    let size: Vec<usize> = size.iter()
+        .map(|&v| usize::try_from(v).map_err(|_| format!("{}: size fuera de rango ({})", path, v)))
+        .collect::<Result<_, _>>()?;
+    let (width, height, depth) = (size[0], size[1], size[2]);
+    let mut world = new_world(width, height, depth, path)?;
+    for block in blocks {
+        let state = block.get("state").and_then(Tag::as_int)
+            .and_then(|s| names.get(s as usize))
+            .ok_or_else(|| format!("{}: bloque con estado inválido", path))?;
+        let pos = block.get("pos").and_then(Tag::as_int_array)
+            .filter(|pos| pos.len() == 3)
+            .ok_or_else(|| format!("{}: bloque sin pos", path))?;
+        if pos.iter().zip(&size).any(|(&p, &limit)| p < 0 || p as u64 >= limit as u64) {
+            return Err(format!("{}: pos {:?} fuera de size {:?}", path, pos, size).into());
+        }
+        let (x, y, z) = (pos[0] as i32, pos[1] as i32, pos[2] as i32);
+        let block_type = report.resolve(state.0);
+        world.set_block(x, y, z, block_type);
+        world.set_state(x, y, z, orientation(block_type, &state.1));
+    }
+
+    println!("✓ Importado {}: {}x{}x{}", path, world.width, world.height, world.depth);
+    report.print(path);
+    Ok(world)
+}
+
+/*
+Documentación (bloque):
+Sección: Escritura de estructuras vanilla (.nbt)
+Descripción: Incluye también las posiciones de aire para que, al cargar la estructura
+en el juego, el volumen quede exactamente como en el diorama.
+*/
+pub fn save_structure(world: &VoxelWorld, path: &str) -> Result<(), Box<dyn std::error::Error>> {
+    check_dimensions(world)?;
+
+    let mut palette: Vec<(&'static str, Vec<(&'static str, &'static str)>)> = Vec::new();
+    let mut blocks = Vec::with_capacity(world.width * world.height * world.depth);
+    for y in 0..world.height as i32 {
+        for z in 0..world.depth as i32 {
+            for x in 0..world.width as i32 {
+                let block = world.get_block(x, y, z);
+                let id = (minecraft_id(block), properties(block, world.get_state(x, y, z)));
+                let state = palette.iter().position(|p| *p == id).unwrap_or_else(|| {
+                    palette.push(id);
+                    palette.len() - 1
+                });
+                blocks.push(Tag::Compound(vec![
+                    ("state".to_string(), Tag::Int(state as i32)),
+                    ("pos".to_string(), Tag::List(vec![Tag::Int(x), Tag::Int(y), Tag::Int(z)])),
+                ]));
+            }
+        }
+    }
+
+    let root = Tag::Compound(vec![
+        ("DataVersion".to_string(), Tag::Int(DATA_VERSION)),
+        ("size".to_string(), Tag::List(vec![
+            Tag::Int(world.width as i32),
+            Tag::Int(world.height as i32),
+            Tag::Int(world.depth as i32),
+        ])),
+        ("palette".to_string(), Tag::List(
+            palette.iter().map(|(id, properties)| {
+                let mut entry = vec![("Name".to_string(), Tag::String(id.to_string()))];
+                if !properties.is_empty() {
+                    entry.push(("Properties".to_string(), Tag::Compound(
+                        properties.iter().map(|(k, v)| (k.to_string(), Tag::String(v.to_string()))).collect(),
+                    )));
+                }
+                Tag::Compound(entry)
+            }).collect(),
+        )),
+        ("blocks".to_string(), Tag::List(blocks)),
+        ("entities".to_string(), Tag::List(Vec::new())),
+    ]);
+
+    nbt::write_file(path, "", &root)?;
+    println!("✓ Exportado {} ({} estados de bloque)", path, palette.len());
+    Ok(())
+}
+
+fn check_dimensions(world: &VoxelWorld) -> Result<(), Box<dyn std::error::Error>> {
+    if world.width > u16::MAX as usize || world.height > u16::MAX as usize || world.depth > u16::MAX as usize {
+        return Err(format!(
+            "el mundo ({}x{}x{}) supera el máximo de 65535 bloques por eje",
+            world.width, world.height, world.depth
+        ).into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::temp_path;
+
+    /// Mundo con todos los bloques del registro y las orientaciones que ambos formatos conservan.
+    fn sample_world() -> VoxelWorld {
+        let mut world = VoxelWorld::with_dimensions(5, 4, 7);
+        for (i, (block, _)) in registry().blocks().skip(1).enumerate() {
+            world.set_block((i % 5) as i32, (i / 5 % 4) as i32, (i / 20) as i32, block);
+        }
+        let states = [
+            ('e', BlockState::new(Facing::East, true)),
+            ('e', BlockState::new(Facing::West, false)),
+            ('s', BlockState::new(Facing::North, true)),
+            ('s', BlockState::double()),
+            ('c', BlockState::new(Facing::South, false)),
+            ('b', BlockState::new(Facing::East, false)),
+            ('u', BlockState::new(Facing::West, false)),
+        ];
+        for (z, (c, state)) in states.into_iter().enumerate() {
+            world.set_block(z as i32 % 5, 3, 2 + z as i32 / 5, BlockType::from_char(c));
+            world.set_state(z as i32 % 5, 3, 2 + z as i32 / 5, state);
+        }
+        world
+    }
+
+    fn assert_same_world(a: &VoxelWorld, b: &VoxelWorld) {
+        assert_eq!((a.width, a.height, a.depth), (b.width, b.height, b.depth));
+        for y in 0..a.height as i32 {
+            for z in 0..a.depth as i32 {
+                for x in 0..a.width as i32 {
+                    assert_eq!(a.get_block(x, y, z), b.get_block(x, y, z), "bloque en ({}, {}, {})", x, y, z);
+                    assert_eq!(a.get_state(x, y, z), b.get_state(x, y, z), "estado en ({}, {}, {})", x, y, z);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn exportar_e_importar_schem_conserva_bloques_y_estados() {
+        let world = sample_world();
+        let path = temp_path("schem_ida_y_vuelta.schem");
+        save_schem(&world, &path).unwrap();
+        let loaded = load_schem(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+        assert_same_world(&world, &loaded);
+    }
+
+    #[test]
+    fn exportar_e_importar_estructura_conserva_bloques_y_estados() {
+        let world = sample_world();
+        let path = temp_path("estructura_ida_y_vuelta.nbt");
+        save_structure(&world, &path).unwrap();
+        let loaded = load_structure(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+        assert_same_world(&world, &loaded);
+    }
+
+    #[test]
+    fn lee_un_schem_de_version_3() {
+        let mut data = Vec::new();
+        for index in [0, 1, 1, 2, 0, 0] {
+            write_varint(&mut data, index);
+        }
+        let root = Tag::Compound(vec![("Schematic".to_string(), Tag::Compound(vec![
+            ("Version".to_string(), Tag::Int(3)),
+            ("Width".to_string(), Tag::Short(3)),
+            ("Height".to_string(), Tag::Short(2)),
+            ("Length".to_string(), Tag::Short(1)),
+            ("Blocks".to_string(), Tag::Compound(vec![
+                ("Palette".to_string(), Tag::Compound(vec![
+                    ("minecraft:air".to_string(), Tag::Int(0)),
+                    ("minecraft:magma_block".to_string(), Tag::Int(1)),
+                    ("minecraft:stone_stairs[facing=north,half=top]".to_string(), Tag::Int(2)),
+                ])),
+                ("Data".to_string(), Tag::ByteArray(data)),
+            ])),
+        ]))]);
+        let path = temp_path("v3.schem");
+        nbt::write_file(&path, "", &root).unwrap();
+        let world = load_schem(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+
+        assert_eq!((world.width, world.height, world.depth), (3, 2, 1));
+        assert_eq!(world.get_block(0, 0, 0), BlockType::AIRE);
+        assert_eq!(world.get_block(1, 0, 0), BlockType::from_char('l'));
+        assert_eq!(world.get_block(2, 0, 0), BlockType::from_char('l'));
+        assert_eq!(world.get_block(0, 1, 0), BlockType::from_char('e'));
+        assert_eq!(world.get_state(0, 1, 0), BlockState::new(Facing::South, true));
+        assert_eq!(world.get_block(2, 1, 0), BlockType::AIRE);
+    }
+
+    #[test]
+    fn rechaza_datos_de_bloques_que_no_llenan_el_volumen() {
+        let path = temp_path("schem_incompleto.schem");
+        for count in [0, 5, 7] {
+            let mut data = Vec::new();
+            for _ in 0..count {
+                write_varint(&mut data, 1);
+            }
+            let root = Tag::Compound(vec![
+                ("Version".to_string(), Tag::Int(2)),
+                ("Width".to_string(), Tag::Short(3)),
+                ("Height".to_string(), Tag::Short(2)),
+                ("Length".to_string(), Tag::Short(1)),
+                ("Palette".to_string(), Tag::Compound(vec![
+                    ("minecraft:air".to_string(), Tag::Int(0)),
+                    ("minecraft:magma_block".to_string(), Tag::Int(1)),
+                ])),
+                ("BlockData".to_string(), Tag::ByteArray(data)),
+            ]);
+            nbt::write_file(&path, "Schematic", &root).unwrap();
+            let error = load_schem(&path).err().map(|e| e.to_string());
+            assert_eq!(error, Some(format!("{}: {} bloques en BlockData, se esperaban 6", path, count)));
+        }
+        std::fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn los_varints_se_leen_como_se_escribieron() {
+        let values = [0, 1, 127, 128, 255, 16_383, 16_384, 2_097_151, 2_097_152, u32::MAX];
+        let mut data = Vec::new();
+        for value in values {
+            write_varint(&mut data, value);
+        }
+        assert_eq!(data.len(), 1 + 1 + 1 + 2 + 2 + 2 + 3 + 3 + 4 + 5);
+        assert_eq!(read_varints(&data, "prueba").unwrap(), values);
+        assert_eq!(read_varints(&[], "prueba").unwrap(), Vec::<u32>::new());
+    }
+
+    #[test]
+    fn rechaza_varints_demasiado_largos_o_incompletos() {
+        assert!(read_varints(&[-1, -1, -1, -1, -1, 0x01], "prueba").is_err());
+        assert!(read_varints(&[0x05, -128], "prueba").is_err());
+    }
+
+    #[test]
+    fn las_dimensiones_son_cortos_sin_signo() {
+        let tag = Tag::Compound(vec![
+            ("Width".to_string(), Tag::Short(-1)),
+            ("Height".to_string(), Tag::Int(-1)),
+            ("Length".to_string(), Tag::Long(65_536)),
+            ("Offset".to_string(), Tag::Int(65_535)),
+        ]);
+        assert_eq!(dimension(&tag, "Width", "prueba").unwrap(), 65_535);
+        assert_eq!(dimension(&tag, "Offset", "prueba").unwrap(), 65_535);
+        assert!(dimension(&tag, "Height", "prueba").is_err());
+        assert!(dimension(&tag, "Length", "prueba").is_err());
+        assert!(dimension(&tag, "Falta", "prueba").is_err());
+    }
+
+    #[test]
+    fn un_fichero_truncado_o_corrupto_da_error() {
+        let world = sample_world();
+        let (schem, structure) = (temp_path("schem_corrupto.schem"), temp_path("estructura_corrupta.nbt"));
+        save_schem(&world, &schem).unwrap();
+        save_structure(&world, &structure).unwrap();
+        for path in [&schem, &structure] {
+            let data = std::fs::read(path).unwrap();
+            for len in [0, 1, 10, data.len() / 2, data.len() - 1] {
+                std::fs::write(path, &data[..len]).unwrap();
+                assert!(load_schem(path).is_err() && load_structure(path).is_err(), "{}: {} bytes", path, len);
+            }
+        }
+
+        let huge = Tag::Compound(vec![
+            ("Width".to_string(), Tag::Short(-1)),
+            ("Height".to_string(), Tag::Short(-1)),
+            ("Length".to_string(), Tag::Short(-1)),
+            ("Palette".to_string(), Tag::Compound(Vec::new())),
+            ("BlockData".to_string(), Tag::ByteArray(Vec::new())),
+        ]);
+        nbt::write_file(&schem, "Schematic", &huge).unwrap();
+        assert!(load_schem(&schem).is_err());
+
+        let cases = [
+            vec![("size".to_string(), Tag::List(vec![Tag::Int(i32::MAX); 3]))],
+            vec![("size".to_string(), Tag::List(vec![Tag::Int(2); 2]))],
+            vec![
+                ("size".to_string(), Tag::List(vec![Tag::Int(2), Tag::Int(-2), Tag::Int(2)])),
+                ("palette".to_string(), Tag::List(Vec::new())),
+                ("blocks".to_string(), Tag::List(Vec::new())),
+            ],
+            vec![
+                ("size".to_string(), Tag::List(vec![Tag::Int(2); 3])),
+                ("palette".to_string(), Tag::List(vec![Tag::Compound(Vec::new())])),
+                ("blocks".to_string(), Tag::List(vec![Tag::Compound(vec![
+                    ("state".to_string(), Tag::Int(0)),
+                    ("pos".to_string(), Tag::List(vec![Tag::Long(1 << 32), Tag::Long(0), Tag::Long(0)])),
+                ])])),
+            ],
+            vec![
+                ("size".to_string(), Tag::List(vec![Tag::Int(2); 3])),
+                ("palette".to_string(), Tag::List(Vec::new())),
+                ("blocks".to_string(), Tag::List(vec![Tag::Compound(vec![
+                    ("state".to_string(), Tag::Int(-1)),
+                    ("pos".to_string(), Tag::List(vec![Tag::Int(0); 3])),
+                ])])),
+            ],
+        ];
+        for (i, entries) in cases.into_iter().enumerate() {
+            nbt::write_file(&structure, "", &Tag::Compound(entries)).unwrap();
+            assert!(load_structure(&structure).is_err(), "caso {}", i);
+        }
+        std::fs::remove_file(&schem).ok();
+        std::fs::remove_file(&structure).ok();
+    }
+}
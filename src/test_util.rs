@@ -0,0 +1,17 @@
+/*
+Documentación (bloque):
+Sección: Utilidades de prueba
+Descripción: Ayudas compartidas por los módulos de pruebas. Las pruebas de `cargo test`
+corren en paralelo dentro de un mismo proceso, así que cada fichero temporal lleva,
+además del id del proceso, un contador propio para que dos pruebas nunca compartan ruta.
+*/
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static NEXT_TEMP: AtomicUsize = AtomicUsize::new(0);
+
+/// Ruta temporal única en todo el proceso; `name` solo aporta la extensión y legibilidad.
+pub fn temp_path(name: &str) -> String {
+    let n = NEXT_TEMP.fetch_add(1, Ordering::Relaxed);
+    std::env::temp_dir().join(format!("cubo_{}_{}_{}", std::process::id(), n, name)).to_string_lossy().into_owned()
+}
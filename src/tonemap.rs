@@ -0,0 +1,198 @@
+/*
+Documentación (bloque):
+Sección: Revelado HDR (exposición, tone mapping y sRGB)
+Descripción: El sombreado trabaja en radiancia lineal sin límite superior, así que la lava
+o el sol pueden valer mucho más que 1. Antes de mostrar el frame, `develop` escala cada
+píxel por la exposición, lo comprime a [0,1] con el operador elegido y lo codifica en sRGB
+para la ventana o el PNG. La exposición es `2^ev`, multiplicada, con la auto-exposición
+activa, por el factor que lleva la luminancia media del frame al gris medio (`KEY`). Esa
+media sale del histograma de `log2` de la luminancia, descartando los píxeles más oscuros
+y los más brillantes para que ni el cielo ni un charco de lava la dominen.
+*/
+
+use nalgebra::Vector3;
+use rayon::prelude::*;
+
+/// Gris medio al que la auto-exposición lleva la luminancia media del frame.
+const KEY: f32 = 0.18;
+/// Rango del histograma de la auto-exposición, en `log2` de luminancia.
+const MIN_LOG_LUMINANCE: f32 = -12.0;
+const MAX_LOG_LUMINANCE: f32 = 6.0;
+const HISTOGRAM_BINS: usize = 64;
+/// Fracción de píxeles que se ignoran por abajo y por arriba al promediar el histograma.
+const LOW_PERCENTILE: f32 = 0.5;
+const HIGH_PERCENTILE: f32 = 0.95;
+
+/// Operadores de tone mapping. Todos llevan el negro a 0 y comprimen las luces altas.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ToneMap {
+    /// Reinhard sobre la luminancia, conservando el tono: `L / (1 + L)`.
+    Reinhard,
+    /// Ajuste de la curva ACES de Narkowicz, por canal: más contraste y blancos saturados.
+    Aces,
+    /// Curva filmica de Hable (Uncharted 2), con blanco en `FILMIC_WHITE`.
+    Filmic,
+}
+
+/// Radiancia que la curva filmica lleva al blanco.
+const FILMIC_WHITE: f32 = 11.2;
+
+impl ToneMap {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "reinhard" => Some(ToneMap::Reinhard),
+            "aces" => Some(ToneMap::Aces),
+            "filmic" => Some(ToneMap::Filmic),
+            _ => None,
+        }
+    }
+
+    /// Siguiente operador, para alternarlos desde la ventana.
+    pub fn next(self) -> Self {
+        match self {
+            ToneMap::Reinhard => ToneMap::Aces,
+            ToneMap::Aces => ToneMap::Filmic,
+            ToneMap::Filmic => ToneMap::Reinhard,
+        }
+    }
+
+    /// Comprime un color lineal ya expuesto a [0,1], todavía lineal.
+    pub fn apply(self, color: Vector3<f32>) -> Vector3<f32> {
+        match self {
+            ToneMap::Reinhard => color / (1.0 + luminance(&color)),
+            ToneMap::Aces => color.map(|x| (x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14)),
+            ToneMap::Filmic => {
+                fn hable(x: f32) -> f32 {
+                    let (a, b, c, d, e, f) = (0.15, 0.50, 0.10, 0.20, 0.02, 0.30);
+                    (x * (a * x + c * b) + d * e) / (x * (a * x + b) + d * f) - e / f
+                }
+                color.map(|x| hable(2.0 * x) / hable(FILMIC_WHITE))
+            }
+        }
+        .map(|x| x.clamp(0.0, 1.0))
+    }
+}
+
+/// Luminancia relativa de un color lineal (coeficientes de Rec. 709).
+pub fn luminance(color: &Vector3<f32>) -> f32 {
+    0.2126 * color.x + 0.7152 * color.y + 0.0722 * color.z
+}
+
+/// Decodifica un canal sRGB en [0,1] a lineal.
+pub fn srgb_to_linear(c: f32) -> f32 {
+    if c <= 0.04045 { c / 12.92 } else { ((c + 0.055) / 1.055).powf(2.4) }
+}
+
+/// Codifica un canal lineal en [0,1] a sRGB.
+pub fn linear_to_srgb(c: f32) -> f32 {
+    if c <= 0.003_130_8 { c * 12.92 } else { 1.055 * c.powf(1.0 / 2.4) - 0.055 }
+}
+
+/// Factor que lleva la luminancia media del frame a `KEY`, a partir de su histograma.
+pub fn auto_exposure(hdr: &[Vector3<f32>]) -> f32 {
+    let mut histogram = [0usize; HISTOGRAM_BINS];
+    let range = MAX_LOG_LUMINANCE - MIN_LOG_LUMINANCE;
+    for color in hdr {
+        let log_luminance = luminance(color).max(1e-6).log2();
+        let bin = ((log_luminance - MIN_LOG_LUMINANCE) / range * HISTOGRAM_BINS as f32) as usize;
+        histogram[bin.min(HISTOGRAM_BINS - 1)] += 1;
+    }
+
+    let low = hdr.len() as f32 * LOW_PERCENTILE;
+    let high = hdr.len() as f32 * HIGH_PERCENTILE;
+    let (mut seen, mut sum, mut count) = (0.0, 0.0, 0.0);
+    for (bin, &pixels) in histogram.iter().enumerate() {
+        let start = seen;
+        seen += pixels as f32;
+        let used = seen.min(high) - start.max(low);
+        if used > 0.0 {
+            sum += used * (MIN_LOG_LUMINANCE + (bin as f32 + 0.5) / HISTOGRAM_BINS as f32 * range);
+            count += used;
+        }
+    }
+
+    if count > 0.0 { KEY / (sum / count).exp2() } else { 1.0 }
+}
+
+/// Ajustes del revelado de un frame.
+#[derive(Debug, Clone, Copy)]
+pub struct Develop {
+    pub tone_map: ToneMap,
+    /// Compensación de exposición en pasos (EV): el frame se multiplica por `2^ev`.
+    pub exposure: f32,
+    pub auto_exposure: bool,
+}
+
+impl Develop {
+    /// Revela `hdr` en `buffer` con el formato `0xAARRGGBB` de la ventana y devuelve el
+    /// factor de exposición aplicado.
+    pub fn develop(&self, hdr: &[Vector3<f32>], buffer: &mut [u32]) -> f32 {
+        let mut scale = self.exposure.exp2();
+        if self.auto_exposure {
+            scale *= auto_exposure(hdr);
+        }
+
+        buffer.par_iter_mut().zip(hdr.par_iter()).for_each(|(pixel, color)| {
+            let mapped = self.tone_map.apply(color * scale);
+            *pixel = pack_color(mapped.map(linear_to_srgb));
+        });
+        scale
+    }
+}
+
+/// Empaqueta un color sRGB en [0,1] en el formato `0xAARRGGBB` de la ventana.
+fn pack_color(col: Vector3<f32>) -> u32 {
+    let r = (col.x.clamp(0.0, 1.0) * 255.0 + 0.5) as u32;
+    let g = (col.y.clamp(0.0, 1.0) * 255.0 + 0.5) as u32;
+    let b = (col.z.clamp(0.0, 1.0) * 255.0 + 0.5) as u32;
+    0xFF000000 | (r << 16) | (g << 8) | b
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn un_frame_uniforme_queda_en_el_gris_medio() {
+        // El histograma agrupa por intervalos, así que la media se conoce con medio intervalo de error.
+        let half_bin = ((MAX_LOG_LUMINANCE - MIN_LOG_LUMINANCE) / HISTOGRAM_BINS as f32 / 2.0).exp2();
+        for level in [0.001, 0.05, 0.18, 1.0, 20.0] {
+            let color = Vector3::new(level, level * 0.8, level * 1.3);
+            let exposed = luminance(&color) * auto_exposure(&vec![color; 500]);
+            assert!(exposed > KEY / half_bin && exposed < KEY * half_bin, "luminancia {} expuesta a {}", luminance(&color), exposed);
+        }
+    }
+
+    #[test]
+    fn un_frame_vacio_no_cambia_la_exposicion() {
+        assert_eq!(auto_exposure(&[]), 1.0);
+    }
+
+    #[test]
+    fn srgb_ida_y_vuelta() {
+        for i in 0..=1000 {
+            let x = i as f32 / 1000.0;
+            assert!((srgb_to_linear(linear_to_srgb(x)) - x).abs() < 1e-5, "{}", x);
+            assert!((linear_to_srgb(srgb_to_linear(x)) - x).abs() < 1e-5, "{}", x);
+        }
+    }
+
+    #[test]
+    fn los_operadores_son_monotonos_y_acotados() {
+        let colors = [Vector3::new(1.0, 1.0, 1.0), Vector3::new(1.0, 0.2, 0.05), Vector3::new(0.0, 0.3, 2.0)];
+        for tone_map in [ToneMap::Reinhard, ToneMap::Aces, ToneMap::Filmic] {
+            assert!(tone_map.apply(Vector3::zeros()).max() < 1e-6, "{:?} no lleva el negro a 0", tone_map);
+            for color in &colors {
+                let mut previous = Vector3::zeros();
+                for step in -40..=40 {
+                    let mapped = tone_map.apply(color * (step as f32 / 4.0).exp2());
+                    for c in 0..3 {
+                        assert!((0.0..=1.0).contains(&mapped[c]), "{:?}: {:?}", tone_map, mapped);
+                        assert!(mapped[c] >= previous[c], "{:?} baja de {:?} a {:?}", tone_map, previous, mapped);
+                    }
+                    previous = mapped;
+                }
+            }
+        }
+    }
+}
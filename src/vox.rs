@@ -0,0 +1,420 @@
+/*
+Documentación (bloque):
+Sección: Importación/exportación MagicaVoxel (.vox)
+Descripción: Lector y escritor del formato de chunks de MagicaVoxel (versión 150).
+Solo se interpretan los chunks `SIZE`, `XYZI` y `RGBA`; el resto (transformaciones,
+materiales, capas del editor) se ignora. MagicaVoxel usa Z hacia arriba, así que el
+eje `z` del fichero se convierte en el eje `y` (altura) del `VoxelWorld` y viceversa.
+*/
+
+use crate::registry::registry;
+use crate::{BlockState, BlockType, VoxelWorld};
+use std::fs;
+
+const VOX_VERSION: i32 = 150;
+
+/// Tabla índice de paleta (1..=255) → `BlockType`. Los índices sin entrada se descartan.
+pub struct VoxMapping {
+    table: [Option<BlockType>; 256],
+}
+
+impl VoxMapping {
+    /*
+    Descripción: Mapeo por defecto, el mismo que escribe `save_vox`: el índice de paleta
+    coincide con la posición del bloque en el registro.
+    */
+    pub fn identity() -> Self {
+        let mut table = [None; 256];
+        for (i, entry) in table.iter_mut().enumerate().skip(1) {
+            *entry = BlockType::from_index(i);
+        }
+        VoxMapping { table }
+    }
+
+    /*
+    Descripción: Asigna a cada color de la paleta el bloque cuyo color de fallback
+    (del registro) esté más cerca en RGB. Útil para modelos hechos con la paleta
+    propia del artista cuando no hay una tabla explícita. Si varios bloques comparten
+    el color más cercano se prefiere el de la misma posición en el registro, para que
+    bloques distintos con el mismo color no se fundan en uno al reimportar.
+    */
+    pub fn nearest_color(palette: &[[u8; 4]; 256]) -> Self {
+        let mut table = [None; 256];
+        for (i, entry) in table.iter_mut().enumerate().skip(1) {
+            let color = palette[i - 1];
+            *entry = registry().blocks().skip(1)
+                .min_by_key(|&(block, def)| (color_distance(color, palette_color(def.color)), block.0 as usize != i))
+                .map(|(block, _)| block);
+        }
+        VoxMapping { table }
+    }
+
+    /*
+    Descripción: Carga una tabla desde un fichero de texto con una entrada por línea,
+    `<índice> <carácter de capa>` (por ejemplo `12 n`). Las líneas vacías y las que
+    empiezan por `#` se ignoran; un carácter que no es de ningún bloque del registro es
+    un error. Asignar el carácter del aire descarta a propósito los voxels de ese índice.
+    */
+    pub fn from_file(path: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let content = fs::read_to_string(path)
+            .map_err(|e| format!("no se pudo leer la tabla de paleta {}: {}", path, e))?;
+
+        let mut table = [None; 256];
+        for (line_no, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut parts = line.split_whitespace();
+            let index: u8 = parts.next().and_then(|p| p.parse().ok()).filter(|&i| i > 0)
+                .ok_or_else(|| format!("{}:{}: índice de paleta inválido", path, line_no + 1))?;
+            let ch = parts.next().and_then(|p| p.chars().next())
+                .ok_or_else(|| format!("{}:{}: falta el carácter del bloque", path, line_no + 1))?;
+            let block = BlockType::parse_char(ch)
+                .ok_or_else(|| format!("{}:{}: carácter de bloque desconocido {:?}", path, line_no + 1, ch))?;
+            table[index as usize] = Some(block);
+        }
+
+        Ok(VoxMapping { table })
+    }
+
+    fn get(&self, index: u8) -> Option<BlockType> {
+        self.table[index as usize]
+    }
+}
+
+fn color_distance(a: [u8; 4], b: [u8; 4]) -> u32 {
+    (0..3).map(|c| (a[c] as i32 - b[c] as i32).pow(2) as u32).sum()
+}
+
+/// Entrada de paleta .vox de un color de fallback del registro.
+fn palette_color((r, g, b): (f32, f32, f32)) -> [u8; 4] {
+    [(r * 255.0) as u8, (g * 255.0) as u8, (b * 255.0) as u8, 255]
+}
+
+/// Paleta que escribe `save_vox`: la entrada `i - 1` es el color del bloque `i` del registro.
+fn registry_palette() -> [[u8; 4]; 256] {
+    let mut palette = [[0u8; 4]; 256];
+    for (block, def) in registry().blocks().skip(1).take(255) {
+        palette[block.0 as usize - 1] = palette_color(def.color);
+    }
+    palette
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, n: usize) -> Result<&'a [u8], Box<dyn std::error::Error>> {
+        let end = self.pos.checked_add(n).filter(|&end| end <= self.data.len())
+            .ok_or("fichero .vox truncado")?;
+        let slice = &self.data[self.pos..end];
+        self.pos = end;
+        Ok(slice)
+    }
+
+    fn i32(&mut self) -> Result<i32, Box<dyn std::error::Error>> {
+        let b = self.bytes(4)?;
+        Ok(i32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+}
+
+/*
+Documentación (bloque):
+Sección: Lectura de .vox
+Descripción: Recorre los chunks hijos de `MAIN`. Si el fichero contiene varios modelos
+solo se importa el primero (con aviso). Cuando no se proporciona tabla, se usa el mapeo
+identidad si no hay chunk `RGBA` o si su paleta es la del registro (un fichero exportado
+con `save_vox`), y el mapeo por color más cercano en caso contrario.
+*/
+pub fn load_vox(path: &str, mapping: Option<&VoxMapping>) -> Result<VoxelWorld, Box<dyn std::error::Error>> {
+    let data = fs::read(path).map_err(|e| format!("no se pudo leer {}: {}", path, e))?;
+    let mut reader = Reader { data: &data, pos: 0 };
+
+    if reader.bytes(4)? != b"VOX " {
+        return Err(format!("{} no es un fichero MagicaVoxel", path).into());
+    }
+    let version = reader.i32()?;
+    if version != VOX_VERSION {
+        println!("⚠ {}: versión .vox {} (se esperaba {}), se intenta leer igualmente", path, version, VOX_VERSION);
+    }
+
+    if reader.bytes(4)? != b"MAIN" {
+        return Err(format!("{}: falta el chunk MAIN", path).into());
+    }
+    let main_content = reader.i32()? as usize;
+    let _main_children = reader.i32()?;
+    reader.bytes(main_content)?;
+
+    let mut size: Option<(usize, usize, usize)> = None;
+    let mut voxels: Option<Vec<[u8; 4]>> = None;
+    let mut palette: Option<[[u8; 4]; 256]> = None;
+    let mut models = 0;
+
+    while reader.pos < data.len() {
+        let id = reader.bytes(4)?;
+        let content_size = reader.i32()? as usize;
+        let children_size = reader.i32()? as usize;
+        let mut content = Reader { data: reader.bytes(content_size)?, pos: 0 };
+        reader.bytes(children_size)?;
+
+        match id {
+            b"SIZE" => {
+                models += 1;
+                if size.is_none() {
+                    let (x, y, z) = (content.i32()?, content.i32()?, content.i32()?);
+                    size = Some((x.max(0) as usize, y.max(0) as usize, z.max(0) as usize));
+                }
+            }
+            b"XYZI" if voxels.is_none() => {
+                let count = content.i32()?.max(0) as usize;
+                let raw = content.bytes(count * 4)?;
+                voxels = Some(raw.chunks(4).map(|v| [v[0], v[1], v[2], v[3]]).collect());
+            }
+            b"RGBA" => {
+                let raw = content.bytes(256 * 4)?;
+                let mut colors = [[0u8; 4]; 256];
+                for (color, rgba) in colors.iter_mut().zip(raw.chunks(4)) {
+                    *color = [rgba[0], rgba[1], rgba[2], rgba[3]];
+                }
+                palette = Some(colors);
+            }
+            _ => {}
+        }
+    }
+
+    let (size_x, size_y, size_z) = size.ok_or_else(|| format!("{}: falta el chunk SIZE", path))?;
+    if size_x > 256 || size_y > 256 || size_z > 256 {
+        return Err(format!("{}: el modelo ({}x{}x{}) supera el máximo de 256 voxels por eje", path, size_x, size_y, size_z).into());
+    }
+    let voxels = voxels.ok_or_else(|| format!("{}: falta el chunk XYZI", path))?;
+    if models > 1 {
+        println!("⚠ {}: contiene {} modelos, solo se importa el primero", path, models);
+    }
+
+    let fallback_mapping = match &palette {
+        Some(colors) if *colors != registry_palette() => VoxMapping::nearest_color(colors),
+        _ => VoxMapping::identity(),
+    };
+    let mapping = mapping.unwrap_or(&fallback_mapping);
+
+    let mut world = VoxelWorld::with_dimensions(size_x, size_z, size_y);
+    let unmapped = place_voxels(&mut world, &voxels, mapping);
+
+    println!("✓ Importado {}: {}x{}x{}", path, world.width, world.height, world.depth);
+    if unmapped > 0 {
+        println!("⚠ {} voxels con índices de paleta sin bloque asignado se descartaron", unmapped);
+    }
+    Ok(world)
+}
+
+/// Coloca los voxels de `XYZI` (con z como altura) y devuelve cuántos tenían un índice de
+/// paleta sin entrada en `mapping`; los asignados al aire se descartan sin contarse.
+fn place_voxels(world: &mut VoxelWorld, voxels: &[[u8; 4]], mapping: &VoxMapping) -> usize {
+    let mut unmapped = 0;
+    for &[x, y, z, index] in voxels {
+        match mapping.get(index) {
+            Some(block) => world.set_block(x as i32, z as i32, y as i32, block),
+            None => unmapped += 1,
+        }
+    }
+    unmapped
+}
+
+fn write_chunk(out: &mut Vec<u8>, id: &[u8; 4], content: &[u8]) {
+    out.extend_from_slice(id);
+    out.extend_from_slice(&(content.len() as i32).to_le_bytes());
+    out.extend_from_slice(&0i32.to_le_bytes());
+    out.extend_from_slice(content);
+}
+
+/*
+Documentación (bloque):
+Sección: Escritura de .vox
+Descripción: Exporta el mundo como un único modelo. El índice de paleta de cada voxel es
+la posición de su bloque en el registro y la paleta se rellena con los colores de fallback,
+de modo que los bloques se vuelven a importar tal cual con `VoxMapping::identity`. El
+formato no guarda la orientación de cada voxel: las escaleras, slabs y cofres girados
+vuelven con el estado por defecto, y se avisa de cuántos voxels la perdieron.
+MagicaVoxel limita cada modelo a 256 voxels por eje y la paleta a 255 bloques.
+*/
+pub fn save_vox(world: &VoxelWorld, path: &str) -> Result<(), Box<dyn std::error::Error>> {
+    if world.width > 256 || world.height > 256 || world.depth > 256 {
+        return Err(format!(
+            "el mundo ({}x{}x{}) supera el máximo de 256 voxels por eje de .vox",
+            world.width, world.height, world.depth
+        ).into());
+    }
+
+    if registry().len() > 256 {
+        return Err(format!("el registro tiene {} bloques y la paleta .vox admite 255", registry().len() - 1).into());
+    }
+
+    let mut size = Vec::with_capacity(12);
+    for dim in [world.width, world.depth, world.height] {
+        size.extend_from_slice(&(dim as i32).to_le_bytes());
+    }
+
+    let mut xyzi = vec![0u8; 4];
+    let mut count = 0i32;
+    let mut oriented = 0;
+    for y in 0..world.height as i32 {
+        for z in 0..world.depth as i32 {
+            for x in 0..world.width as i32 {
+                let block = world.get_block(x, y, z);
+                if block.is_solid() {
+                    xyzi.extend_from_slice(&[x as u8, z as u8, y as u8, block.0 as u8]);
+                    count += 1;
+                    if world.get_state(x, y, z) != BlockState::default() {
+                        oriented += 1;
+                    }
+                }
+            }
+        }
+    }
+    xyzi[..4].copy_from_slice(&count.to_le_bytes());
+
+    let rgba = registry_palette().concat();
+
+    let mut children = Vec::new();
+    write_chunk(&mut children, b"SIZE", &size);
+    write_chunk(&mut children, b"XYZI", &xyzi);
+    write_chunk(&mut children, b"RGBA", &rgba);
+
+    let mut out = Vec::with_capacity(children.len() + 20);
+    out.extend_from_slice(b"VOX ");
+    out.extend_from_slice(&VOX_VERSION.to_le_bytes());
+    out.extend_from_slice(b"MAIN");
+    out.extend_from_slice(&0i32.to_le_bytes());
+    out.extend_from_slice(&(children.len() as i32).to_le_bytes());
+    out.extend_from_slice(&children);
+
+    fs::write(path, out).map_err(|e| format!("no se pudo escribir {}: {}", path, e))?;
+    println!("✓ Exportado {} ({} voxels)", path, count);
+    if oriented > 0 {
+        println!("⚠ {}: .vox no guarda orientaciones, {} voxels girados se exportaron con la de por defecto", path, oriented);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::temp_path;
+
+    #[test]
+    fn exportar_e_importar_conserva_todos_los_bloques() {
+        let blocks: Vec<BlockType> = registry().blocks().skip(1).map(|(block, _)| block).collect();
+        let mut world = VoxelWorld::with_dimensions(blocks.len(), 2, 3);
+        for (x, &block) in blocks.iter().enumerate() {
+            world.set_block(x as i32, 1, 2, block);
+        }
+
+        let path = temp_path("ida_y_vuelta.vox");
+        save_vox(&world, &path).unwrap();
+        let loaded = load_vox(&path, None).unwrap();
+        std::fs::remove_file(&path).ok();
+
+        assert_eq!((loaded.width, loaded.height, loaded.depth), (world.width, world.height, world.depth));
+        for (x, &block) in blocks.iter().enumerate() {
+            assert_eq!(loaded.get_block(x as i32, 1, 2), block, "bloque {}", registry().get(block).name);
+        }
+    }
+
+    /// Fichero .vox mínimo con un modelo de `size` y los voxels `voxels`.
+    fn vox_file(size: [i32; 3], voxels: &[[u8; 4]]) -> Vec<u8> {
+        let mut children = Vec::new();
+        let size: Vec<u8> = size.iter().flat_map(|d| d.to_le_bytes()).collect();
+        write_chunk(&mut children, b"SIZE", &size);
+        let mut xyzi = (voxels.len() as i32).to_le_bytes().to_vec();
+        xyzi.extend(voxels.iter().flatten());
+        write_chunk(&mut children, b"XYZI", &xyzi);
+
+        let mut out = b"VOX ".to_vec();
+        out.extend_from_slice(&VOX_VERSION.to_le_bytes());
+        out.extend_from_slice(b"MAIN");
+        out.extend_from_slice(&0i32.to_le_bytes());
+        out.extend_from_slice(&(children.len() as i32).to_le_bytes());
+        out.extend_from_slice(&children);
+        out
+    }
+
+    #[test]
+    fn sin_paleta_se_usa_el_mapeo_identidad() {
+        let path = temp_path("sin_paleta.vox");
+        std::fs::write(&path, vox_file([2, 3, 4], &[[1, 2, 3, 7], [0, 0, 0, 200]])).unwrap();
+        let world = load_vox(&path, None).unwrap();
+        std::fs::remove_file(&path).ok();
+
+        // El eje z del fichero es la altura del mundo.
+        assert_eq!((world.width, world.height, world.depth), (2, 4, 3));
+        assert_eq!(world.get_block(1, 3, 2), BlockType::from_index(7).unwrap());
+        assert_eq!(world.get_block(0, 0, 0), BlockType::AIRE);
+    }
+
+    #[test]
+    fn un_fichero_truncado_da_error() {
+        let data = vox_file([4, 4, 4], &[[0, 0, 0, 1], [3, 3, 3, 2]]);
+        let path = temp_path("truncado.vox");
+        for len in 0..data.len() {
+            std::fs::write(&path, &data[..len]).unwrap();
+            assert!(load_vox(&path, None).is_err(), "{} de {} bytes", len, data.len());
+        }
+        std::fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn un_fichero_corrupto_da_error() {
+        let path = temp_path("corrupto.vox");
+        let mut negative = vox_file([4, 4, 4], &[[0, 0, 0, 1]]);
+        // Tamaño del contenido de SIZE negativo.
+        negative[24..28].copy_from_slice(&(-12i32).to_le_bytes());
+        let mut count = vox_file([4, 4, 4], &[[0, 0, 0, 1]]);
+        // Más voxels en XYZI de los que caben en el chunk.
+        count[56..60].copy_from_slice(&i32::MAX.to_le_bytes());
+        let cases = [
+            b"NOVX".iter().chain(&vox_file([1, 1, 1], &[])[4..]).copied().collect(),
+            vox_file([1 << 30, 1 << 30, 1 << 30], &[[0, 0, 0, 1]]),
+            negative,
+            count,
+        ];
+        for (i, data) in cases.iter().enumerate() {
+            std::fs::write(&path, data).unwrap();
+            assert!(load_vox(&path, None).is_err(), "caso {}", i);
+        }
+        std::fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn el_color_mas_cercano_no_funde_bloques_del_mismo_color() {
+        let mapping = VoxMapping::nearest_color(&registry_palette());
+        for (block, _) in registry().blocks().skip(1) {
+            assert_eq!(mapping.get(block.0 as u8), Some(block));
+        }
+    }
+
+    #[test]
+    fn solo_cuentan_como_descartados_los_indices_sin_asignar() {
+        let mut table = [None; 256];
+        table[1] = BlockType::from_index(1);
+        table[2] = Some(BlockType::AIRE);
+        let mapping = VoxMapping { table };
+        let mut world = VoxelWorld::with_dimensions(3, 1, 1);
+        let unmapped = place_voxels(&mut world, &[[0, 0, 0, 1], [1, 0, 0, 2], [2, 0, 0, 3]], &mapping);
+        assert_eq!(unmapped, 1);
+        assert_eq!(world.get_block(0, 0, 0), BlockType::from_index(1).unwrap());
+        assert_eq!(world.get_block(1, 0, 0), BlockType::AIRE);
+        assert_eq!(world.get_block(2, 0, 0), BlockType::AIRE);
+    }
+
+    #[test]
+    fn una_tabla_con_un_caracter_desconocido_da_error() {
+        let path = temp_path("tabla.txt");
+        std::fs::write(&path, "# tabla\n1 n\n2 \u{00f1}\n").unwrap();
+        let error = VoxMapping::from_file(&path).err().unwrap().to_string();
+        std::fs::remove_file(&path).ok();
+        assert!(error.starts_with(&format!("{}:3:", path)), "{}", error);
+    }
+}